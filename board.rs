@@ -1,8 +1,17 @@
+use nnue;
+use zobrist;
+
 use std::fmt;
+use std::mem;
+use std::sync::Arc;
 
 use std::convert::TryFrom;
 use std::convert::TryInto;
 
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+#[derive(Clone)]
 pub struct ChessBoard {
     pub board: [[u8; 8]; 8],
     opponent: u8,
@@ -14,10 +23,31 @@ pub struct ChessBoard {
     pub  ep: Option<(usize, usize)>,
     pub white_king_pos: (usize, usize),
     pub black_king_pos: (usize, usize),
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    // Network accumulators kept up to date by make and unmake, when a network is attached
+    pub nnue: Option<nnue::Accumulators>,
+    // Zobrist keys of the pieces on the board, kept up to date by make and unmake
+    pub piece_key: u64,
+}
+
+// Everything make() can change that unmake() cannot put back on its own
+#[derive(Clone, Copy)]
+pub struct BoardState {
+    ep: Option<(usize, usize)>,
+    white_kingside_castle: bool,
+    white_queenside_castle: bool,
+    black_kingside_castle: bool,
+    black_queenside_castle: bool,
+    halfmove_clock: u32,
 }
 
+pub const START_FEN : &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 pub fn build_board(board: [[u8; 8]; 8]) -> ChessBoard {
-    ChessBoard{ board: board , opponent: BLACK , protagonist: WHITE, white_kingside_castle: true, white_queenside_castle: true, black_kingside_castle: true, black_queenside_castle: true, ep: None, white_king_pos: (7,4), black_king_pos: (0,4)}
+    let mut chess_board = ChessBoard{ board, opponent: BLACK , protagonist: WHITE, white_kingside_castle: true, white_queenside_castle: true, black_kingside_castle: true, black_queenside_castle: true, ep: None, white_king_pos: (7,4), black_king_pos: (0,4), halfmove_clock: 0, fullmove_number: 1, nnue: None, piece_key: 0};
+    chess_board.refresh_key();
+    chess_board
 }
 
 
@@ -43,6 +73,9 @@ impl ChessBoard {
         self.white_queenside_castle = false;
         self.black_kingside_castle = false;
         self.black_queenside_castle = false;
+        self.ep = None;
+        self.halfmove_clock = 0;
+        self.fullmove_number = 0;
 
         let mut row = 0;
         let mut col = 0;
@@ -122,9 +155,144 @@ impl ChessBoard {
                     } else {
                         self.ep = Some((2, letter_to_col(c)));
                     }
+                } else if space_count == 4 && c.is_ascii_digit() { // Halfmove clock
+                    self.halfmove_clock = self.halfmove_clock * 10 + c.to_digit(10).unwrap();
+                } else if space_count == 5 && c.is_ascii_digit() { // Fullmove number
+                    self.fullmove_number = self.fullmove_number * 10 + c.to_digit(10).unwrap();
                 }
             }
         }
+        if self.fullmove_number == 0 {
+            self.fullmove_number = 1;
+        }
+        self.refresh_nnue();
+        self.refresh_key();
+    }
+
+    // For positions set up square by square instead of from a FEN
+    pub fn set_protagonist(&mut self, color : u8) {
+        self.protagonist = color;
+        self.opponent = if color == WHITE { BLACK } else { WHITE };
+    }
+
+    pub fn attach_network(&mut self, network : Arc<nnue::Network>) {
+        self.nnue = Some(nnue::Accumulators::new(network, self));
+    }
+
+    // Needed after writing to self.board directly
+    pub fn refresh_nnue(&mut self) {
+        if let Some(mut accumulators) = self.nnue.take() {
+            accumulators.refresh(self, WHITE);
+            accumulators.refresh(self, BLACK);
+            self.nnue = Some(accumulators);
+        }
+    }
+
+    // Needed after writing to self.board directly, like refresh_nnue
+    pub fn refresh_key(&mut self) {
+        self.piece_key = zobrist::piece_hash(self);
+    }
+
+    // Every square make and unmake change goes through here to keep the network inputs and the key in step
+    fn set_square(&mut self, row : usize, col : usize, piece : u8) {
+        let old = self.board[row][col];
+        if let Some(ref mut accumulators) = self.nnue {
+            accumulators.change_square(row, col, old, piece);
+        }
+        if old != 0b0000 {
+            self.piece_key ^= zobrist::PIECE_KEYS[row * 8 + col][old as usize];
+        }
+        if piece != 0b0000 {
+            self.piece_key ^= zobrist::PIECE_KEYS[row * 8 + col][piece as usize];
+        }
+        self.board[row][col] = piece;
+    }
+
+    fn refresh_nnue_kings(&mut self) {
+        if let Some(mut accumulators) = self.nnue.take() {
+            accumulators.refresh_if_king_moved(self);
+            self.nnue = Some(accumulators);
+        }
+    }
+
+    pub fn to_fen(& self) -> String {
+        let mut fen = String::new();
+        for row in 0..8 {
+            let mut empty = 0;
+            for col in 0..8 {
+                let piece = self.board[row][col];
+                if piece == 0b0000 {
+                    empty += 1;
+                } else {
+                    if empty > 0 {
+                        fen.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    fen.push(piece_to_char(piece));
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if row < 7 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.protagonist == WHITE { " w " } else { " b " });
+
+        let mut castling = String::new();
+        if self.white_kingside_castle {
+            castling.push('K');
+        }
+        if self.white_queenside_castle {
+            castling.push('Q');
+        }
+        if self.black_kingside_castle {
+            castling.push('k');
+        }
+        if self.black_queenside_castle {
+            castling.push('q');
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        match self.ep {
+            Some(square) => fen.push_str(&format!(" {}", square_to_alphanumeric(square))),
+            None => fen.push_str(" -"),
+        }
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    pub fn save_state(& self) -> BoardState {
+        BoardState {
+            ep: self.ep,
+            white_kingside_castle: self.white_kingside_castle,
+            white_queenside_castle: self.white_queenside_castle,
+            black_kingside_castle: self.black_kingside_castle,
+            black_queenside_castle: self.black_queenside_castle,
+            halfmove_clock: self.halfmove_clock,
+        }
+    }
+
+    pub fn restore_state(&mut self, state: BoardState) {
+        self.ep = state.ep;
+        self.white_kingside_castle = state.white_kingside_castle;
+        self.white_queenside_castle = state.white_queenside_castle;
+        self.black_kingside_castle = state.black_kingside_castle;
+        self.black_queenside_castle = state.black_queenside_castle;
+        self.halfmove_clock = state.halfmove_clock;
+    }
+
+    pub fn protagonist_in_check(& self) -> bool {
+        if self.protagonist == WHITE {
+            self.in_check(self.white_king_pos)
+        } else {
+            self.in_check(self.black_king_pos)
+        }
     }
 
     pub fn knight_moves(& self, source: (usize, usize)) -> Vec<((usize, usize),(usize, usize))> {
@@ -276,6 +444,15 @@ impl ChessBoard {
             }
         }
 
+        // King Checks
+        for row in source.0.saturating_sub(1)..=(source.0 + 1).min(7) {
+            for col in source.1.saturating_sub(1)..=(source.1 + 1).min(7) {
+                if self.board[row][col] == KING | self.opponent {
+                    return true;
+                }
+            }
+        }
+
         // Pawn Moves
         if self.opponent == BLACK {
             if source.0 > 0 && source.1 > 0 {
@@ -306,6 +483,88 @@ impl ChessBoard {
         return false;
     }
 
+    // Squares of the opponent pieces attacking a square
+    pub fn attackers(& self, source: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut attacker_vec : Vec<(usize, usize)> = Vec::new();
+        for a_move in self.knight_moves(source) {
+            let dest = a_move.1;
+            if self.board[dest.0][dest.1] == KNIGHT | self.opponent {
+                attacker_vec.push(dest);
+            }
+        }
+        for a_move in self.bishop_moves(source) {
+            let dest = a_move.1;
+            if self.board[dest.0][dest.1] == BISHOP | self.opponent || self.board[dest.0][dest.1] == QUEEN | self.opponent {
+                attacker_vec.push(dest);
+            }
+        }
+        for a_move in self.rook_moves(source) {
+            let dest = a_move.1;
+            if self.board[dest.0][dest.1] == ROOK | self.opponent || self.board[dest.0][dest.1] == QUEEN | self.opponent {
+                attacker_vec.push(dest);
+            }
+        }
+        for row in source.0.saturating_sub(1)..=(source.0 + 1).min(7) {
+            for col in source.1.saturating_sub(1)..=(source.1 + 1).min(7) {
+                if self.board[row][col] == KING | self.opponent {
+                    attacker_vec.push((row, col));
+                }
+            }
+        }
+        // Enemy pawns sit one row behind the square from their point of view
+        let pawn_row = if self.opponent == BLACK {
+            source.0.checked_sub(1)
+        } else if source.0 < 7 {
+            Some(source.0 + 1)
+        } else {
+            None
+        };
+        if let Some(row) = pawn_row {
+            if source.1 > 0 && self.board[row][source.1-1] == PAWN | self.opponent {
+                attacker_vec.push((row, source.1-1));
+            }
+            if source.1 < 7 && self.board[row][source.1+1] == PAWN | self.opponent {
+                attacker_vec.push((row, source.1+1));
+            }
+        }
+        attacker_vec
+    }
+
+    // Squares the piece on source attacks, whoever occupies them
+    pub fn attacks_from(& self, source: (usize, usize)) -> Vec<(usize, usize)> {
+        let piece = self.board[source.0][source.1];
+        let mut attack_vec : Vec<(usize, usize)> = Vec::new();
+        let (steps, slides) : (&[(i8, i8)], bool) = match clear_piece_color(piece) {
+            KNIGHT => (&KNIGHT_STEPS, false),
+            KING => (&KING_STEPS, false),
+            BISHOP => (&KING_STEPS[4..], true),
+            ROOK => (&KING_STEPS[..4], true),
+            QUEEN => (&KING_STEPS, true),
+            PAWN => if piece & WHITE == WHITE {
+                (&WHITE_PAWN_CAPTURES, false)
+            } else {
+                (&BLACK_PAWN_CAPTURES, false)
+            },
+            _ => (&[], false),
+        };
+        for step in steps {
+            let mut row = source.0 as i8;
+            let mut col = source.1 as i8;
+            loop {
+                row += step.0;
+                col += step.1;
+                if !(0..8).contains(&row) || !(0..8).contains(&col) {
+                    break;
+                }
+                attack_vec.push((row as usize, col as usize));
+                if !slides || self.board[row as usize][col as usize] != 0b0000 {
+                    break;
+                }
+            }
+        }
+        attack_vec
+    }
+
     pub fn pawn_moves(& self, source: (usize, usize)) -> Vec<((usize, usize),(usize, usize, u8))> {
         let mut move_vec : Vec<((usize, usize),(usize, usize, u8))> = Vec::new();
         if self.protagonist == BLACK {
@@ -416,6 +675,24 @@ impl ChessBoard {
         pawn_move_vec
     }
 
+    pub fn get_legal_moves(&mut self) -> Vec<Move> {
+        let mut legal_vec : Vec<Move> = Vec::new();
+        for amove in self.get_all_moves() {
+            let state = self.save_state();
+            match self.make(amove.0, amove.1) {
+                None => {
+                    // Illegal move, already unmade
+                },
+                Some(captured_piece) => {
+                    self.unmake(amove.0, amove.1, captured_piece);
+                    legal_vec.push(amove);
+                }
+            }
+            self.restore_state(state);
+        }
+        legal_vec
+    }
+
     pub fn make(&mut self, source: (usize, usize), dest: (usize, usize, u8)) -> Option<u8> {
         let captured_piece = self.board[dest.0][dest.1];
         let piece = self.board[source.0][source.1];
@@ -455,11 +732,11 @@ impl ChessBoard {
         if clear_piece_color(piece) == KING {
             if source.1.abs_diff(dest.1) > 1 { // Castling
                 if source.1 > dest.1 { // Queenside
-                    self.board[source.0][dest.1 + 1] = self.board[source.0][0];
-                    self.board[source.0][0] = 0b0000;
+                    self.set_square(source.0, dest.1 + 1, self.board[source.0][0]);
+                    self.set_square(source.0, 0, 0b0000);
                 } else { // Kingside
-                    self.board[source.0][dest.1 - 1] = self.board[source.0][7];
-                    self.board[source.0][7] = 0b0000;
+                    self.set_square(source.0, dest.1 - 1, self.board[source.0][7]);
+                    self.set_square(source.0, 7, 0b0000);
                 }
             }
             if self.protagonist == WHITE { // Update King Pos
@@ -472,12 +749,21 @@ impl ChessBoard {
                 self.black_queenside_castle = false;
             }
         }
+        if clear_piece_color(piece) == PAWN || captured_piece != 0b0000 {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.protagonist == BLACK {
+            self.fullmove_number += 1;
+        }
+
         if dest.2 == 0 {
-            self.board[dest.0][dest.1] = piece;
+            self.set_square(dest.0, dest.1, piece);
         } else { // Promotion
-            self.board[dest.0][dest.1] = dest.2 | self.protagonist;
+            self.set_square(dest.0, dest.1, dest.2 | self.protagonist);
         }
-        self.board[source.0][source.1] = 0b0000;
+        self.set_square(source.0, source.1, 0b0000);
 
         if piece == PAWN | self.protagonist {// Potential en passent
             match self.ep {
@@ -485,7 +771,7 @@ impl ChessBoard {
                     if (dest.0, dest.1) == square {
                         // Taking en passent
                         // source row (3 or 4), dest col
-                        self.board[source.0][dest.1] = 0b0000;
+                        self.set_square(source.0, dest.1, 0b0000);
                     };
                 },
                 None => {},
@@ -499,6 +785,8 @@ impl ChessBoard {
             }
         }
 
+        self.refresh_nnue_kings();
+
         let mut illegal = false;
         if ((self.protagonist == 0b1000) && self.in_check(self.white_king_pos)) || ((self.protagonist == 0b0000) && self.in_check(self.black_king_pos)) {
             // King illegally left in check
@@ -522,6 +810,10 @@ impl ChessBoard {
         self.protagonist = self.opponent;
         self.opponent = temp;
 
+        if self.protagonist == BLACK {
+            self.fullmove_number -= 1;
+        }
+
         if clear_piece_color(self.board[dest.0][dest.1]) == KING {
             if self.protagonist == WHITE {
                 self.white_king_pos = source;
@@ -531,28 +823,41 @@ impl ChessBoard {
 
             if source.1.abs_diff(dest.1) > 1 { // UnCastling
                 if source.1 > dest.1 { // Queenside
-                    self.board[source.0][0] = self.board[source.0][dest.1 + 1];
-                    self.board[source.0][dest.1 + 1] = 0b0000;
+                    self.set_square(source.0, 0, self.board[source.0][dest.1 + 1]);
+                    self.set_square(source.0, dest.1 + 1, 0b0000);
                 } else { // Kingside
-                    self.board[source.0][7] = self.board[source.0][dest.1 - 1];
-                    self.board[source.0][dest.1 - 1] = 0b0000;
+                    self.set_square(source.0, 7, self.board[source.0][dest.1 - 1]);
+                    self.set_square(source.0, dest.1 - 1, 0b0000);
                 }
             }
         }
 
         if dest.2 == 0 {
-            self.board[source.0][source.1] = self.board[dest.0][dest.1];
+            self.set_square(source.0, source.1, self.board[dest.0][dest.1]);
         } else { // Unpromotion
-            self.board[source.0][source.1] = PAWN | self.protagonist;
+            self.set_square(source.0, source.1, PAWN | self.protagonist);
         }
 
         if self.board[source.0][source.1] == PAWN | self.protagonist {// Potential en passent
             if captured_piece == 0b0000 && source.1 != dest.1 {
-                self.board[source.0][dest.1] = PAWN | self.opponent;
+                self.set_square(source.0, dest.1, PAWN | self.opponent);
             }
         }
 
-        self.board[dest.0][dest.1] = captured_piece;
+        self.set_square(dest.0, dest.1, captured_piece);
+        self.refresh_nnue_kings();
+    }
+
+    // Passes the turn without moving, for null-move pruning. The en passant
+    // square and clock come back with restore_state.
+    pub fn make_null(&mut self) {
+        self.ep = None;
+        self.halfmove_clock += 1;
+        mem::swap(&mut self.protagonist, &mut self.opponent);
+    }
+
+    pub fn unmake_null(&mut self) {
+        mem::swap(&mut self.protagonist, &mut self.opponent);
     }
 }
 
@@ -566,6 +871,12 @@ pub const ROOK : u8 = 0b0100;
 pub const KNIGHT : u8 = 0b0101;
 pub  const BISHOP : u8 = 0b0111;
 
+// (row, col) steps, orthogonal ones first
+const KING_STEPS : [(i8, i8); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+const KNIGHT_STEPS : [(i8, i8); 8] = [(1, 2), (2, 1), (-1, 2), (-2, 1), (1, -2), (2, -1), (-1, -2), (-2, -1)];
+const WHITE_PAWN_CAPTURES : [(i8, i8); 2] = [(-1, -1), (-1, 1)];
+const BLACK_PAWN_CAPTURES : [(i8, i8); 2] = [(1, -1), (1, 1)];
+
 pub fn piece_to_char(piece : u8) -> char {
     let piece_char : char = match clear_piece_color(piece) {
         KING=>'k',
@@ -633,6 +944,24 @@ fn letter_to_col(letter : char) -> usize {
     }
 }
 
+// Coordinate notation as used by UCI, e.g. e2e4 or e7e8q
+pub fn move_to_string(amove : ((usize, usize),(usize, usize, u8))) -> String {
+    let mut text = format!("{}{}", square_to_alphanumeric(amove.0), square_to_alphanumeric((amove.1.0, amove.1.1)));
+    if amove.1.2 != 0 {
+        text.push(piece_to_char(amove.1.2));
+    }
+    text
+}
+
+pub fn alphanumeric_to_square(text: &str) -> Option<(usize, usize)> {
+    let chars : Vec<char> = text.chars().collect();
+    if chars.len() != 2 || !('a'..='h').contains(&chars[0]) || !('1'..='8').contains(&chars[1]) {
+        return None;
+    }
+    let row : usize = (8 - chars[1].to_digit(10).unwrap()).try_into().unwrap();
+    Some((row, letter_to_col(chars[0])))
+}
+
 pub fn square_to_alphanumeric(square: (usize, usize)) ->  String {
     let num = 8 - square.0;
     let letter = match square.1 {
@@ -0,0 +1,65 @@
+mod board;
+mod endgame;
+mod eval;
+mod game;
+mod kpk;
+mod nnue;
+mod pgn;
+mod polyglot;
+mod san;
+mod zobrist;
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+
+fn usage() -> ! {
+    println!("usage: bookbuild <pgn>... [options]");
+    println!("options: --out <file>        where to write the Polyglot book (default book.bin)");
+    println!("         --max-ply <n>       only the first n plies of each game (default 20)");
+    println!("         --min-games <n>     leave out moves played in fewer games (default 3)");
+    process::exit(2);
+}
+
+fn main() {
+    let mut paths : Vec<String> = Vec::new();
+    let mut out = String::from("book.bin");
+    let mut options = polyglot::BuildOptions{ max_ply: 20, min_games: 3 };
+    let mut arg_iter = env::args().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--out" {
+            out = arg_iter.next().unwrap_or_else(|| usage());
+        } else if arg == "--max-ply" {
+            options.max_ply = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--min-games" {
+            options.min_games = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+
+    let started = Instant::now();
+    let mut games = Vec::new();
+    for path in &paths {
+        let text = fs::read_to_string(path).unwrap_or_else(|error| {
+            println!("Could not read {}: {}", path, error);
+            process::exit(1);
+        });
+        let (mut read, skipped) = pgn::parse_pgn(&text);
+        println!("{}: {} games, {} skipped", path, read.len(), skipped);
+        games.append(&mut read);
+    }
+
+    let book = polyglot::build(&games, &options);
+    if let Err(error) = polyglot::save_book(&out, &book) {
+        println!("Could not save the book: {}", error);
+        process::exit(1);
+    }
+    println!("Wrote {} entries from {} games to {} in {:.1}s", book.len(), games.len(), out, started.elapsed().as_secs_f64());
+}
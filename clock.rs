@@ -0,0 +1,133 @@
+use eval;
+
+use std::time::{Duration, Instant};
+
+// How long to think when playing on a clock. Every move gets a soft limit,
+// after which no new iteration is started, and a hard one the search never
+// goes past. The soft limit stretches while the search keeps changing its
+// mind or its score falls, and shrinks once the best move looks settled.
+
+pub const DEFAULT_OVERHEAD : Duration = Duration::from_millis(30);
+pub const MAX_OVERHEAD_MILLIS : u64 = 5000;
+
+// Moves assumed left in the game when the time control does not say
+const DEFAULT_MOVES_TO_GO : u32 = 30;
+const MAX_MOVES_TO_GO : u32 = 50;
+// Hard limits are this many soft ones, but never this share of the time left
+const HARD_SCALE : u32 = 4;
+const HARD_PERCENT : u32 = 80;
+// With the time left all lost to the overhead, this share of the increment
+// past the overhead is still spent rather than none
+const MIN_HARD_PERCENT : u32 = 25;
+
+// Per change of best move, halved every iteration
+const INSTABILITY_STEP : f64 = 0.6;
+// A score falling by a pawn doubles the time, a larger fall no more
+const DROP_FOR_DOUBLE : i32 = 100;
+// The same best move over this many iterations is an obvious one
+const OBVIOUS_ITERATIONS : u32 = 5;
+const OBVIOUS_SCALE : f64 = 0.4;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    pub time_left: Duration,
+    pub increment: Duration,
+    // Moves until the next time control, None for the rest of the game
+    pub moves_to_go: Option<u32>,
+    // Lost to communication and the GUI on every move
+    pub overhead: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeBudget {
+    pub soft: Duration,
+    pub hard: Duration,
+}
+
+pub fn budget(control : &TimeControl) -> TimeBudget {
+    let available = control.time_left.saturating_sub(control.overhead);
+    let moves_to_go = control.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, MAX_MOVES_TO_GO);
+    // The increment comes back after the move, most of it can be spent now
+    let soft = available / moves_to_go + control.increment * 3 / 4;
+    let hard = (soft * HARD_SCALE).min(available * HARD_PERCENT / 100)
+        .max(control.increment.saturating_sub(control.overhead) * MIN_HARD_PERCENT / 100);
+    TimeBudget{ soft: soft.min(hard), hard }
+}
+
+pub struct TimeManager {
+    budget: TimeBudget,
+    started: Instant,
+    best_move: Option<Move>,
+    // Iterations the best move has stood
+    stable: u32,
+    instability: f64,
+    score: Option<i32>,
+    drop: i32,
+}
+
+impl TimeManager {
+    pub fn new(budget : TimeBudget) -> TimeManager {
+        TimeManager{ budget, started: Instant::now(), best_move: None, stable: 0, instability: 0.0, score: None, drop: 0 }
+    }
+
+    pub fn budget(& self) -> TimeBudget {
+        self.budget
+    }
+
+    // The clock runs from now, what the iterations showed is kept
+    pub fn start(&mut self) {
+        self.started = Instant::now();
+    }
+
+    pub fn elapsed(& self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn out_of_time(& self) -> bool {
+        self.started.elapsed() >= self.budget.hard
+    }
+
+    // How far the soft limit stretches, from what the iterations so far showed
+    pub fn scale(& self) -> f64 {
+        let mut scale = 1.0 + self.instability;
+        scale *= 1.0 + self.drop.clamp(0, DROP_FOR_DOUBLE) as f64 / DROP_FOR_DOUBLE as f64;
+        if self.stable >= OBVIOUS_ITERATIONS {
+            scale *= OBVIOUS_SCALE;
+        }
+        scale
+    }
+
+    pub fn soft_limit(& self) -> Duration {
+        self.budget.soft.mul_f64(self.scale()).min(self.budget.hard)
+    }
+
+    // Takes in a finished iteration and says whether to start another. A
+    // forced move or a mate the search has seen to the end is played at once.
+    pub fn keep_going(&mut self, depth : u16, best_move : Move, score : i32, legal_moves : usize) -> bool {
+        self.instability /= 2.0;
+        if self.best_move == Some(best_move) {
+            self.stable += 1;
+        } else {
+            if self.best_move.is_some() {
+                self.instability += INSTABILITY_STEP;
+            }
+            self.stable = 0;
+            self.best_move = Some(best_move);
+        }
+        // Mate scores move by plies, not by how the position stands
+        self.drop = match self.score {
+            Some(previous) if !eval::is_mate_score(previous) && !eval::is_mate_score(score) => previous - score,
+            _ => 0,
+        };
+        self.score = Some(score);
+
+        if legal_moves == 1 {
+            return false;
+        }
+        let mate_seen = eval::mate_moves(score).is_some_and(|moves| moves > 0 && depth as i32 >= 2 * moves - 1);
+        !mate_seen && self.started.elapsed() < self.soft_limit()
+    }
+}
@@ -0,0 +1,205 @@
+use board;
+use eval;
+use kpk;
+
+// Clearly won but no mate in sight yet, kept well below the mate scores
+pub const KNOWN_WIN : i32 = 10000;
+
+// Scale factors out of SCALE_NORMAL applied to drawish endgames
+pub const SCALE_NORMAL : i32 = 64;
+const SCALE_OPPOSITE_BISHOPS : i32 = 24;
+const SCALE_OPPOSITE_BISHOPS_WITH_PIECES : i32 = 46;
+const SCALE_NO_PAWNS_MINOR_UP : i32 = 4; // KRKB, KRKN, KBNKB, ...
+const SCALE_NO_PAWNS_SMALL_EDGE : i32 = 14; // KQKRB style fortresses
+
+const PUSH_TO_EDGE : i32 = 20; // per step from the centre, for the defending king
+const PUSH_CLOSE : i32 = 20; // per step the kings are closer than the maximum
+const PUSH_TO_CORNER : i32 = 30; // KBNK, per step towards a corner of the bishop's color
+const KPK_PAWN_ADVANCE : i32 = 20; // per rank, for a won KPK
+
+// Simple minor piece units to decide what is winning material
+const MINOR : i32 = 3;
+const ROOK : i32 = 5;
+
+struct Side {
+    color: u8,
+    king: (usize, usize),
+    pawns: Vec<(usize, usize)>,
+    knights: usize,
+    light_bishops: usize,
+    dark_bishops: usize,
+    rooks: usize,
+    queens: usize,
+}
+
+impl Side {
+    fn bishops(& self) -> usize {
+        self.light_bishops + self.dark_bishops
+    }
+
+    fn pieces(& self) -> usize {
+        self.knights + self.bishops() + self.rooks + self.queens
+    }
+
+    fn non_pawn_units(& self) -> i32 {
+        (MINOR as usize * (self.knights + self.bishops()) + ROOK as usize * self.rooks + 9 * self.queens) as i32
+    }
+
+    fn bare(& self) -> bool {
+        self.pieces() == 0 && self.pawns.is_empty()
+    }
+}
+
+fn is_dark(square : (usize, usize)) -> bool {
+    // a1, row 7 col 0, is dark
+    (square.0 + square.1) % 2 == 1
+}
+
+fn side(board : &board::ChessBoard, color : u8) -> Side {
+    let mut side = Side{ color, king: (0, 0), pawns: Vec::new(), knights: 0, light_bishops: 0, dark_bishops: 0, rooks: 0, queens: 0 };
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if piece == 0b0000 || piece & 0b1000 != color {
+                continue;
+            }
+            match board::clear_piece_color(piece) {
+                board::KING => side.king = (row, col),
+                board::PAWN => side.pawns.push((row, col)),
+                board::KNIGHT => side.knights += 1,
+                board::BISHOP => if is_dark((row, col)) { side.dark_bishops += 1 } else { side.light_bishops += 1 },
+                board::ROOK => side.rooks += 1,
+                board::QUEEN => side.queens += 1,
+                _ => {},
+            }
+        }
+    }
+    side
+}
+
+fn distance(a : (usize, usize), b : (usize, usize)) -> i32 {
+    a.0.abs_diff(b.0).max(a.1.abs_diff(b.1)) as i32
+}
+
+// 0 in the four centre squares up to 6 in the corners
+fn centre_distance(square : (usize, usize)) -> i32 {
+    let row = if square.0 < 4 { 3 - square.0 } else { square.0 - 4 };
+    let col = if square.1 < 4 { 3 - square.1 } else { square.1 - 4 };
+    (row + col) as i32
+}
+
+// 7 in the two corners of the given color, 0 on the long diagonal between the other two
+fn corner_closeness(square : (usize, usize), dark : bool) -> i32 {
+    let rank = 7 - square.0 as i32;
+    let file = square.1 as i32;
+    if dark { (file + rank - 7).abs() } else { (file - rank).abs() }
+}
+
+// Can the pieces force mate against a bare king
+fn has_mating_material(side : &Side) -> bool {
+    side.queens > 0 || side.rooks > 0
+        || (side.light_bishops > 0 && side.dark_bishops > 0)
+        || (side.bishops() > 0 && side.knights > 0)
+        || side.knights >= 3
+}
+
+// Strong side against a bare king: keep the material and drive the king to the edge
+fn bare_king(params : &eval::EvalParams, board : &board::ChessBoard, strong : &Side, weak : &Side) -> (i32, &'static str) {
+    let mut score = 0;
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if piece != 0b0000 && piece & 0b1000 == strong.color {
+                score += params.piece_value(piece).1;
+            }
+        }
+    }
+    score += PUSH_TO_EDGE * centre_distance(weak.king) + PUSH_CLOSE * (7 - distance(strong.king, weak.king));
+
+    let name = if strong.pieces() == 2 && strong.bishops() == 1 && strong.knights == 1 && strong.pawns.is_empty() {
+        // Only the corners of the bishop's color can be mated in
+        score += PUSH_TO_CORNER * corner_closeness(weak.king, strong.dark_bishops == 1);
+        "KBNK"
+    } else if strong.pieces() == 1 && strong.queens == 1 && strong.pawns.is_empty() {
+        "KQK"
+    } else if strong.pieces() == 1 && strong.rooks == 1 && strong.pawns.is_empty() {
+        "KRK"
+    } else {
+        "KXK"
+    };
+    (KNOWN_WIN + score, name)
+}
+
+// Bishop and rook pawns where the bishop does not cover the queening square
+// and the defending king has reached the corner
+fn wrong_bishop(strong : &Side, weak : &Side) -> bool {
+    if strong.pawns.is_empty() || strong.pieces() != 1 || strong.bishops() != 1 {
+        return false;
+    }
+    let file = strong.pawns[0].1;
+    if (file != 0 && file != 7) || strong.pawns.iter().any(|pawn| pawn.1 != file) {
+        return false;
+    }
+    let queening = (if strong.color == board::WHITE { 0 } else { 7 }, file);
+    is_dark(queening) != (strong.dark_bishops == 1) && distance(weak.king, queening) <= 1
+}
+
+// Score from white's point of view after the endgame rules, along with the
+// name of the rule that applied
+pub fn adjust(params : &eval::EvalParams, board : &board::ChessBoard, score : i32) -> (i32, Option<&'static str>) {
+    let white = side(board, board::WHITE);
+    let black = side(board, board::BLACK);
+
+    for (strong, weak, sign) in [(&white, &black, 1), (&black, &white, -1)].iter() {
+        if !weak.bare() {
+            continue;
+        }
+        if strong.bare() {
+            return (0, Some("KK"));
+        }
+        if has_mating_material(strong) {
+            let (value, name) = bare_king(params, board, strong, weak);
+            return (sign * value, Some(name));
+        }
+        if strong.pieces() == 0 && strong.pawns.len() == 1 {
+            if kpk::probe_board(board) != Some(true) {
+                return (0, Some("KPK"));
+            }
+            let pawn = strong.pawns[0];
+            let rank = if strong.color == board::WHITE { 7 - pawn.0 } else { pawn.0 } as i32;
+            return (sign * (KNOWN_WIN + params.pawn_value.1 + KPK_PAWN_ADVANCE * rank), Some("KPK"));
+        }
+        if strong.pawns.is_empty() {
+            // KNK, KBK, KNNK, same colored bishops
+            return (0, Some("Insufficient material"));
+        }
+        if wrong_bishop(strong, weak) {
+            return (0, Some("Wrong bishop"));
+        }
+    }
+
+    let (strong, weak) = if score >= 0 { (&white, &black) } else { (&black, &white) };
+    let mut scale = SCALE_NORMAL;
+    let mut name = None;
+    // A queen beats a lone rook even though it is less than a rook ahead
+    let queen_against_rook = strong.queens > weak.queens && weak.pieces() == 1 && weak.rooks == 1;
+    if strong.pawns.is_empty() && strong.non_pawn_units() - weak.non_pawn_units() < ROOK && !queen_against_rook {
+        // No pawns to promote and not enough extra to mate
+        if strong.non_pawn_units() < ROOK {
+            scale = 0;
+        } else if weak.non_pawn_units() <= MINOR {
+            scale = SCALE_NO_PAWNS_MINOR_UP;
+        } else {
+            scale = SCALE_NO_PAWNS_SMALL_EDGE;
+        }
+        name = Some("No pawns");
+    } else if white.bishops() == 1 && black.bishops() == 1 && (white.dark_bishops == 1) != (black.dark_bishops == 1) {
+        if white.pieces() == 1 && black.pieces() == 1 {
+            scale = SCALE_OPPOSITE_BISHOPS;
+        } else {
+            scale = SCALE_OPPOSITE_BISHOPS_WITH_PIECES;
+        }
+        name = Some("Opposite bishops");
+    }
+    (score * scale / SCALE_NORMAL, name)
+}
@@ -1,115 +1,294 @@
 mod board;
+mod clock;
+mod endgame;
 mod eval;
+mod game;
+mod kpk;
+mod nnue;
+mod polyglot;
+mod san;
+mod search;
+mod syzygy;
+mod tablebase;
+mod transposition;
+mod uci;
+mod zobrist;
+
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 pub fn print_move(amove :& ((usize, usize), (usize, usize, u8))) {
     println!("{}, {}", board::square_to_alphanumeric(amove.0), board::square_to_alphanumeric((amove.1.0,amove.1.1)));
 }
 
-fn negamax(board : &mut board::ChessBoard, depth : u16) -> i32 {
-    let moves = board.get_all_moves();
-    let mut legal_moves = 0;
-    let mut max : i32 = i32::MIN;
-    for amove in moves {
-        let ep = board.ep;
-        let white_kingside_castle = board.white_kingside_castle;
-        let white_queenside_castle = board.white_queenside_castle;
-        let black_kingside_castle = board.black_kingside_castle;
-        let black_queenside_castle = board.black_queenside_castle;
-
-        let result = board.make(amove.0, amove.1);
-        let score;
-        match result {
-            None => {
-                // Illegal move, already unmade
+// Score of a fixed depth search, from the side to move's point of view
+fn search_score(board : &mut board::ChessBoard, depth : u16) -> i32 {
+    let mut limits = search::default_limits();
+    limits.depth = depth;
+    let stop = AtomicBool::new(false);
+    search::search(board, &limits, &search::default_options(), &stop, |_| {}).score().unwrap_or(0)
+}
+
+fn main() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+
+    let mut args : Vec<String> = env::args().collect();
+
+    // --params <file>: evaluate with weights from a parameter file
+    if let Some(index) = args.iter().position(|arg| arg == "--params") {
+        if index + 1 >= args.len() {
+            println!("--params needs a file");
+            return;
+        }
+        match eval::load_params(&args[index + 1]) {
+            Ok(params) => eval::set_params(params),
+            Err(error) => {
+                println!("Could not load parameters: {}", error);
+                return;
+            },
+        }
+        args.drain(index..index + 2);
+    }
+
+    // --nnue <file>: evaluate with a network instead
+    if let Some(index) = args.iter().position(|arg| arg == "--nnue") {
+        if index + 1 >= args.len() {
+            println!("--nnue needs a file");
+            return;
+        }
+        match nnue::load_network(&args[index + 1]) {
+            Ok(network) => board.attach_network(Arc::new(network)),
+            Err(error) => {
+                println!("Could not load network: {}", error);
+                return;
+            },
+        }
+        args.drain(index..index + 2);
+    }
+
+    // --tb <dir>: probe the endgame tables in a directory
+    if let Some(index) = args.iter().position(|arg| arg == "--tb") {
+        if index + 1 >= args.len() {
+            println!("--tb needs a directory");
+            return;
+        }
+        match tablebase::Tablebases::load_dir(&args[index + 1]) {
+            Ok(tables) => tablebase::set_tablebases(tables),
+            Err(error) => {
+                println!("Could not load tables: {}", error);
+                return;
+            },
+        }
+        args.drain(index..index + 2);
+    }
+
+    // --syzygy <path>: probe Syzygy tables, directories separated as in PATH
+    if let Some(index) = args.iter().position(|arg| arg == "--syzygy") {
+        if index + 1 >= args.len() {
+            println!("--syzygy needs a path");
+            return;
+        }
+        if syzygy::set_path(&args[index + 1]) == 0 {
+            println!("No Syzygy tables in {}", args[index + 1]);
+            return;
+        }
+        args.drain(index..index + 2);
+    }
+
+    // --book <file>: play from a Polyglot opening book, weighted at random
+    // unless --book-best asks for the heaviest move
+    if let Some(index) = args.iter().position(|arg| arg == "--book") {
+        if index + 1 >= args.len() {
+            println!("--book needs a file");
+            return;
+        }
+        match polyglot::load_book(&args[index + 1]) {
+            Ok(book) => polyglot::set_book(book),
+            Err(error) => {
+                println!("Could not load book: {}", error);
+                return;
+            },
+        }
+        args.drain(index..index + 2);
+    }
+    let mut book_selection = polyglot::Selection::Weighted;
+    if let Some(index) = args.iter().position(|arg| arg == "--book-best") {
+        book_selection = polyglot::Selection::Best;
+        args.remove(index);
+    }
+
+    // engine uci: talk UCI on standard input and output
+    if args.len() > 1 && args[1] == "uci" {
+        uci::run(board, polyglot::has_book(), book_selection);
+        return;
+    }
+
+    // engine analyse [fen]: the best lines, --depth and --multipv of them,
+    // searched on --threads threads
+    if args.len() > 1 && args[1] == "analyse" {
+        let mut limits = search::default_limits();
+        limits.depth = 6;
+        let mut threads = 1;
+        if let Some(index) = args.iter().position(|arg| arg == "--threads") {
+            threads = args.get(index + 1).and_then(|value| value.parse().ok()).unwrap_or(1);
+            args.drain(index..(index + 2).min(args.len()));
+        }
+        if let Some(index) = args.iter().position(|arg| arg == "--depth") {
+            limits.depth = args.get(index + 1).and_then(|value| value.parse().ok()).unwrap_or(limits.depth);
+            args.drain(index..(index + 2).min(args.len()));
+        }
+        if let Some(index) = args.iter().position(|arg| arg == "--multipv") {
+            limits.multipv = args.get(index + 1).and_then(|value| value.parse().ok()).unwrap_or(1);
+            args.drain(index..(index + 2).min(args.len()));
+        }
+        if args.len() > 2 {
+            board.from_fen(&args[2..].join(" "));
+        } else {
+            board.from_fen(board::START_FEN);
+        }
+        println!("{}", board);
+        let stop = AtomicBool::new(false);
+        let table = transposition::Table::new(transposition::DEFAULT_MEGABYTES);
+        search::parallel_search(&mut board, &limits, &search::default_options(), threads, &table, &stop, |info| println!("{}", uci::info_line(info)));
+        return;
+    }
+
+    // engine mate <moves> [--checks] [fen]: prove or disprove a forced mate,
+    // --checks trying only checking moves for the attacker
+    if args.len() > 2 && args[1] == "mate" {
+        let moves : u16 = match args[2].parse() {
+            Ok(moves) => moves,
+            Err(_) => {
+                println!("mate needs a number of moves");
+                return;
             },
-            Some(captured_piece) => {
-                // print_move(&amove);
-                //println!("{}", board.in_check(board.black_king_pos));
-                //println!("{}", board);
-                //println!("{:?}", board.black_king_pos);
-                legal_moves += 1;
-                if depth > 1 {
-                    score = -1 * negamax(board, depth-1);
-                } else {
-                    score = -1 * eval::eval(board);
-                }
-                if score > max {
-                    // println!("{}", score);
-                    max = score;
-                }
-
-                board.unmake(amove.0, amove.1, captured_piece);
-            }
-        }
-        board.ep = ep;
-        board.white_kingside_castle = white_kingside_castle;
-        board.white_queenside_castle = white_queenside_castle;
-        board.black_kingside_castle = black_kingside_castle;
-        board.black_queenside_castle = black_queenside_castle;
+        };
+        let mut checks_only = false;
+        if let Some(index) = args.iter().position(|arg| arg == "--checks") {
+            checks_only = true;
+            args.remove(index);
+        }
+        if args.len() > 3 {
+            board.from_fen(&args[3..].join(" "));
+        } else {
+            board.from_fen(board::START_FEN);
+        }
+        println!("{}", board);
+        let stop = AtomicBool::new(false);
+        match search::mate_search(&mut board, moves, checks_only, &stop) {
+            search::MateResult::Mate(line) => {
+                let line : Vec<String> = line.into_iter().map(board::move_to_string).collect();
+                println!("Mate in {}: {}", line.len().div_ceil(2), line.join(" "));
+            },
+            _ => println!("No mate in {}", moves),
+        }
+        return;
     }
 
-    if legal_moves == 0  {// check or stalemate position
-        if board.protagonist == board::WHITE {
-            if board.in_check(board.white_king_pos) {
-                max = -100000;
-            } else { //stalemate
-                max = 0;
-            }
+    // engine book [fen]: the book's moves and the one it would play
+    if args.len() > 1 && args[1] == "book" {
+        if args.len() > 2 {
+            board.from_fen(&args[2..].join(" "));
         } else {
-            if board.in_check(board.black_king_pos) {
-                max = -100000;
-            } else { //stalemate
-                max = 0;
-            }
+            board.from_fen(board::START_FEN);
         }
+        println!("{}", board);
+        println!("Polyglot key {:016x}", polyglot::key(&board));
+        for (amove, weight) in polyglot::book_moves(&mut board) {
+            println!("{} weight {}", board::move_to_string(amove), weight);
+        }
+        match polyglot::book_move(&mut board, book_selection) {
+            Some(amove) => println!("Book move: {}", board::move_to_string(amove)),
+            None => println!("Not in the book"),
+        }
+        return;
     }
 
-    return max;
-}
+    // engine syzygy <fen>: the Syzygy results and the moves that keep them
+    if args.len() > 2 && args[1] == "syzygy" {
+        board.from_fen(&args[2..].join(" "));
+        println!("{}", board);
+        match (syzygy::probe_wdl(&mut board), syzygy::probe_dtz(&mut board)) {
+            (Some(wdl), Some(dtz)) => println!("{}: {:?}, dtz {}", syzygy::table_name(&board), wdl, dtz),
+            _ => {
+                println!("{} is not in the tables", syzygy::table_name(&board));
+                return;
+            },
+        }
+        for root in syzygy::root_moves(&mut board).unwrap_or_default() {
+            println!("{} dtz {} rank {}", board::move_to_string(root.amove), root.dtz, root.rank);
+        }
+        let keep : Vec<String> = syzygy::filter_root_moves(&mut board).unwrap_or_default().into_iter().map(board::move_to_string).collect();
+        println!("Best: {}", keep.join(" "));
+        return;
+    }
 
-fn main() {
-    let stuff = [[0u8; 8]; 8];
-    let mut board = board::build_board(stuff);
+    // engine tb <fen>: the tables' result and best move
+    if args.len() > 2 && args[1] == "tb" {
+        board.from_fen(&args[2..].join(" "));
+        println!("{}", board);
+        match tablebase::probe_root(&mut board) {
+            Some((amove, result)) => println!("{:?}, best move {}", result, board::move_to_string(amove)),
+            None => println!("Not in the tables"),
+        }
+        return;
+    }
+
+    // engine eval [fen]: print the evaluation broken down by term
+    if args.len() > 1 && args[1] == "eval" {
+        if args.len() > 2 {
+            board.from_fen(&args[2..].join(" "));
+        } else {
+            board.from_fen(board::START_FEN);
+        }
+        println!("{}", board);
+        println!("{}", eval::eval_trace(&board));
+        if board.nnue.is_some() {
+            println!("Network evaluation: {} (side to move)", eval::score_to_uci(eval::eval(&mut board)));
+        }
+        return;
+    }
 
     /*
     // let position_1 = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
     let pos = "7k/8/8/8/8/8/6P1/2QKRBN1 w - - 0 1";
     //board.from_fen(position_1);
     board.from_fen(pos);
-    let score = negamax(&mut board, 4);
+    let score = search_score(&mut board, 4);
     print!("{}\n", score);
     */
 
     let mate_in_1 = "r3k2r/pp3p2/2n1p3/2pp1b2/6nq/1P1PPN2/PBP2PB1/R2QR1K1 b kq - 1 15";
     board.from_fen(mate_in_1);
-    let score = negamax(&mut board, 2);
-    println!("{}", score);
+    let score = search_score(&mut board, 2);
+    println!("{}", eval::score_to_uci(score));
 
-    /*
+    // Check extensions get this one at depth 3
     let mate_in_3 = "6k1/pp3p1p/2p3p1/3p1P2/3P1KPP/4Q3/P1q5/8 w - - 0 26";
     board.from_fen(mate_in_3);
-    let score = negamax(&mut board, 7);
-    println!("{}", score);
-    */
+    let score = search_score(&mut board, 3);
+    println!("{}", eval::score_to_uci(score));
 
     /*
     let pos_force_stalemate = "4r2k/5Q2/8/8/8/8/2RR2P1/2RKRBN1 b - - 0 1";
     board.from_fen(pos_force_stalemate);
-    let score = negamax(&mut board, 3);
+    let score = search_score(&mut board, 3);
     println!("{}", score);
     */
     
     /*
     let stale = "7k/5Q2/8/8/8/8/2RR2P1/2RKrBN1 w - - 0 2";
     board.from_fen(stale);
-    let score = negamax(&mut board, 2);
+    let score = search_score(&mut board, 2);
     println!("{}", score);
     */
 
     /*
     let stale = "7k/5Q2/8/8/8/8/2RR2P1/2R1KBN1 b - - 0 2";
     board.from_fen(stale);
-    let score = negamax(&mut board, 1);
+    let score = search_score(&mut board, 1);
     println!("{}", score);
     */
 }
@@ -0,0 +1,260 @@
+use board;
+use san;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum EpdOperand {
+    Move(Move),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    Symbol(String),
+}
+
+pub struct EpdOperation {
+    pub opcode: String,
+    pub operands: Vec<EpdOperand>,
+}
+
+pub struct Epd {
+    pub board: board::ChessBoard,
+    pub operations: Vec<EpdOperation>,
+}
+
+// Opcodes whose operands are SAN moves in the EPD position
+fn is_move_opcode(opcode : &str) -> bool {
+    opcode == "bm" || opcode == "am" || opcode == "pm" || opcode == "sm"
+}
+
+fn tokenize(text : &str) -> Option<Vec<String>> {
+    let mut tokens : Vec<String> = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == ';' {
+            tokens.push(";".to_string());
+        } else if c == '"' {
+            // Keep the quote so that strings stay distinguishable from symbols
+            let mut token = String::from("\"");
+            let mut closed = false;
+            while let Some(s) = chars.next() {
+                if s == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        token.push(escaped);
+                    }
+                } else if s == '"' {
+                    closed = true;
+                    break;
+                } else {
+                    token.push(s);
+                }
+            }
+            if !closed {
+                return None;
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            token.push(c);
+            while let Some(&s) = chars.peek() {
+                if s.is_whitespace() || s == ';' || s == '"' {
+                    break;
+                }
+                token.push(s);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    Some(tokens)
+}
+
+fn parse_operand(board : &mut board::ChessBoard, opcode : &str, token : &str) -> Option<EpdOperand> {
+    if let Some(text) = token.strip_prefix('"') {
+        return Some(EpdOperand::Text(text.to_string()));
+    }
+    if is_move_opcode(opcode) {
+        return san::san_to_move(board, token).map(EpdOperand::Move);
+    }
+    if let Ok(integer) = token.parse::<i64>() {
+        return Some(EpdOperand::Integer(integer));
+    }
+    if let Ok(float) = token.parse::<f64>() {
+        return Some(EpdOperand::Float(float));
+    }
+    Some(EpdOperand::Symbol(token.to_string()))
+}
+
+// Also accepts the halfmove and fullmove numbers of a full FEN before the operations,
+// as found in perft suites ("<fen> 0 1 ;D1 20 ;D2 400")
+pub fn parse_epd(line : &str) -> Option<Epd> {
+    let line = line.trim();
+    let mut fields : Vec<&str> = Vec::new();
+    let mut rest = line;
+    for _ in 0..4 {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    board.from_fen(&fields.join(" "));
+
+    let tokens = tokenize(rest)?;
+    let mut index = 0;
+    let mut clocks : Vec<u32> = Vec::new();
+    while index < tokens.len() && clocks.len() < 2 {
+        match tokens[index].parse::<u32>() {
+            Ok(number) => clocks.push(number),
+            Err(_) => break,
+        }
+        index += 1;
+    }
+    if clocks.len() == 2 {
+        board.halfmove_clock = clocks[0];
+        board.fullmove_number = clocks[1];
+    } else if clocks.len() == 1 {
+        return None;
+    }
+
+    let mut operations : Vec<EpdOperation> = Vec::new();
+    while index < tokens.len() {
+        if tokens[index] == ";" {
+            index += 1;
+            continue;
+        }
+        let opcode = tokens[index].clone();
+        if !opcode.chars().next().unwrap().is_ascii_alphabetic() {
+            return None;
+        }
+        index += 1;
+        let mut operands : Vec<EpdOperand> = Vec::new();
+        while index < tokens.len() && tokens[index] != ";" {
+            match parse_operand(&mut board, &opcode, &tokens[index]) {
+                Some(operand) => operands.push(operand),
+                None => return None,
+            }
+            index += 1;
+        }
+        operations.push(EpdOperation{ opcode, operands });
+    }
+
+    let mut epd = Epd{ board, operations };
+    if let Some(hmvc) = epd.integer("hmvc") {
+        epd.board.halfmove_clock = hmvc as u32;
+    }
+    if let Some(fmvn) = epd.integer("fmvn") {
+        epd.board.fullmove_number = fmvn as u32;
+    }
+    Some(epd)
+}
+
+impl Epd {
+    pub fn operands(& self, opcode : &str) -> Option<&Vec<EpdOperand>> {
+        for operation in &self.operations {
+            if operation.opcode == opcode {
+                return Some(&operation.operands);
+            }
+        }
+        None
+    }
+
+    pub fn set_operation(&mut self, opcode : &str, operands : Vec<EpdOperand>) {
+        for operation in self.operations.iter_mut() {
+            if operation.opcode == opcode {
+                operation.operands = operands;
+                return;
+            }
+        }
+        self.operations.push(EpdOperation{ opcode: opcode.to_string(), operands });
+    }
+
+    pub fn text(& self, opcode : &str) -> Option<&str> {
+        match self.operands(opcode) {
+            Some(operands) => match operands.first() {
+                Some(EpdOperand::Text(string)) => Some(string),
+                Some(EpdOperand::Symbol(symbol)) => Some(symbol),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    pub fn integer(& self, opcode : &str) -> Option<i64> {
+        match self.operands(opcode) {
+            Some(operands) => match operands.first() {
+                Some(EpdOperand::Integer(integer)) => Some(*integer),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    pub fn moves(& self, opcode : &str) -> Vec<Move> {
+        let mut move_vec : Vec<Move> = Vec::new();
+        if let Some(operands) = self.operands(opcode) {
+            for operand in operands {
+                if let EpdOperand::Move(amove) = operand {
+                    move_vec.push(*amove);
+                }
+            }
+        }
+        move_vec
+    }
+
+    pub fn id(& self) -> Option<&str> {
+        self.text("id")
+    }
+
+    pub fn comment(& self, number : u8) -> Option<&str> {
+        self.text(&format!("c{}", number))
+    }
+
+    pub fn best_moves(& self) -> Vec<Move> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(& self) -> Vec<Move> {
+        self.moves("am")
+    }
+
+    // Expected perft node count from a "D<depth> <count>" operation
+    pub fn perft_count(& self, depth : u16) -> Option<u64> {
+        self.integer(&format!("D{}", depth)).map(|count| count as u64)
+    }
+
+    pub fn to_epd(& self) -> String {
+        let fen = self.board.to_fen();
+        let mut text : String = fen.split(' ').take(4).collect::<Vec<&str>>().join(" ");
+        let mut board = self.board.clone();
+        for operation in &self.operations {
+            text.push(' ');
+            text.push_str(&operation.opcode);
+            for operand in &operation.operands {
+                text.push(' ');
+                match operand {
+                    EpdOperand::Move(amove) => text.push_str(&san::move_to_san(&mut board, *amove)),
+                    EpdOperand::Integer(integer) => text.push_str(&integer.to_string()),
+                    EpdOperand::Float(float) => text.push_str(&float.to_string()),
+                    EpdOperand::Text(string) => text.push_str(&format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))),
+                    EpdOperand::Symbol(symbol) => text.push_str(symbol),
+                }
+            }
+            text.push(';');
+        }
+        text
+    }
+}
+
+pub fn epd_from_board(board : &board::ChessBoard) -> Epd {
+    Epd{ board: board.clone(), operations: Vec::new() }
+}
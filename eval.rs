@@ -1,35 +1,1102 @@
 use board;
+use endgame;
+use zobrist;
 
-fn piece_value(piece : u8) -> i32 {
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Default piece values in centipawns, (middlegame, endgame)
+const PAWN_VALUE : (i32, i32) = (100, 120);
+const KNIGHT_VALUE : (i32, i32) = (320, 300);
+const BISHOP_VALUE : (i32, i32) = (330, 320);
+const ROOK_VALUE : (i32, i32) = (500, 520);
+const QUEEN_VALUE : (i32, i32) = (950, 950);
+
+// Piece-square tables in centipawns from white's point of view,
+// laid out like board.board: index 0 is a8, index 63 is h1
+const PAWN_MG : [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     10,  10,  20,  30,  30,  20,  10,  10,
+      5,   5,  10,  25,  25,  10,   5,   5,
+      0,   0,   0,  20,  20,   0,   0,   0,
+      5,  -5, -10,   0,   0, -10,  -5,   5,
+      5,  10,  10, -20, -20,  10,  10,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const PAWN_EG : [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     90,  90,  90,  90,  90,  90,  90,  90,
+     55,  55,  55,  55,  55,  55,  55,  55,
+     30,  30,  30,  30,  30,  30,  30,  30,
+     15,  15,  15,  15,  15,  15,  15,  15,
+      5,   5,   5,   5,   5,   5,   5,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const KNIGHT_MG : [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+const KNIGHT_EG : [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+const BISHOP_MG : [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+const BISHOP_EG : [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,  10,  15,  15,  10,   5, -10,
+    -10,   5,  10,  15,  15,  10,   5, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+const ROOK_MG : [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+      5,  10,  10,  10,  10,  10,  10,   5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+      0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+const ROOK_EG : [i32; 64] = [
+      5,   5,   5,   5,   5,   5,   5,   5,
+     10,  10,  10,  10,  10,  10,  10,  10,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+     -5,  -5,   0,   0,   0,   0,  -5,  -5,
+];
+
+const QUEEN_MG : [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+const QUEEN_EG : [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+    -10,   5,  10,  10,  10,  10,   5, -10,
+     -5,   5,  10,  15,  15,  10,   5,  -5,
+     -5,   5,  10,  15,  15,  10,   5,  -5,
+    -10,   5,  10,  10,  10,  10,   5, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+const KING_MG : [i32; 64] = [
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+     20,  20,   0,   0,   0,   0,  20,  20,
+     20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+const KING_EG : [i32; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+// Game phase weight of each piece, 24 with all pieces on the board
+const KNIGHT_PHASE : i32 = 1;
+const BISHOP_PHASE : i32 = 1;
+const ROOK_PHASE : i32 = 2;
+const QUEEN_PHASE : i32 = 4;
+pub const MAX_PHASE : i32 = 24;
+
+// Pawn structure weights in centipawns, (middlegame, endgame)
+const DOUBLED_PAWN : (i32, i32) = (-10, -20);
+const ISOLATED_PAWN : (i32, i32) = (-10, -15);
+const BACKWARD_PAWN : (i32, i32) = (-8, -10);
+const PAWN_ISLAND : (i32, i32) = (-5, -10); // for every island after the first
+const CONNECTED_PASSER : (i32, i32) = (10, 20);
+// Indexed by rank from the pawn's own side, 1 being its starting rank
+const PASSED_PAWN_MG : [i32; 8] = [0, 5, 5, 10, 25, 45, 70, 0];
+const PASSED_PAWN_EG : [i32; 8] = [0, 10, 15, 25, 45, 75, 120, 0];
+// Extra for a passer whose stop square is empty
+const FREE_PASSER_MG : [i32; 8] = [0, 0, 0, 5, 10, 20, 30, 0];
+const FREE_PASSER_EG : [i32; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+
+// King safety weights. They only have a middlegame part so they fade out as
+// material comes off, and the endgame king is rewarded for activity instead.
+const PAWN_SHIELD : [i32; 3] = [0, 12, 6]; // own pawn 1 or 2 rows in front, king file or next to it
+const PAWN_STORM : [i32; 5] = [0, -25, -20, -10, -5]; // closest enemy pawn 1 to 4 rows in front
+const KING_HALF_OPEN_FILE : i32 = -15;
+const KING_OPEN_FILE : i32 = -25;
+const KNIGHT_ATTACK_WEIGHT : i32 = 2;
+const BISHOP_ATTACK_WEIGHT : i32 = 2;
+const ROOK_ATTACK_WEIGHT : i32 = 3;
+const QUEEN_ATTACK_WEIGHT : i32 = 5;
+// By summed attack weight on the king zone, used from two attackers on
+const KING_DANGER : [i32; 16] = [0, 0, 5, 10, 20, 35, 50, 70, 95, 120, 150, 180, 210, 240, 270, 300];
+const KING_PAWN_DISTANCE_EG : i32 = -6; // per square from the king to the closest pawn
+
+// Mobility, per safe square reachable beyond the typical count for the piece.
+// A square is safe when it holds no own piece and no enemy pawn attacks it.
+const KNIGHT_MOBILITY : (i32, i32) = (4, 4);
+const BISHOP_MOBILITY : (i32, i32) = (5, 5);
+const ROOK_MOBILITY : (i32, i32) = (2, 4);
+const QUEEN_MOBILITY : (i32, i32) = (1, 2);
+const KNIGHT_MOBILITY_CENTER : i32 = 4;
+const BISHOP_MOBILITY_CENTER : i32 = 6;
+const ROOK_MOBILITY_CENTER : i32 = 7;
+const QUEEN_MOBILITY_CENTER : i32 = 13;
+
+// Piece activity
+const BISHOP_PAIR : (i32, i32) = (30, 50);
+const ROOK_OPEN_FILE : (i32, i32) = (25, 10);
+const ROOK_HALF_OPEN_FILE : (i32, i32) = (12, 5);
+const ROOK_ON_SEVENTH : (i32, i32) = (20, 30); // only with the enemy king or pawns on their back ranks
+const KNIGHT_OUTPOST : (i32, i32) = (20, 10); // pawn supported and out of reach of enemy pawns
+const TRAPPED_BISHOP : (i32, i32) = (-100, -80); // on a7/h7 (a2/h2 for black) cut off by a pawn
+const TRAPPED_ROOK : (i32, i32) = (-40, -10); // boxed in by a king that can no longer castle
+
+// Mate scores count plies from the root, so a shorter mate scores higher
+pub const MATE_SCORE : i32 = 100000;
+pub const MAX_MATE_PLY : i32 = 1000;
+pub const MATE_BOUND : i32 = MATE_SCORE - MAX_MATE_PLY; // anything beyond is a mate
+
+// Every tunable weight of the evaluation, in centipawns
+#[derive(Clone)]
+pub struct EvalParams {
+    pub pawn_value: (i32, i32),
+    pub knight_value: (i32, i32),
+    pub bishop_value: (i32, i32),
+    pub rook_value: (i32, i32),
+    pub queen_value: (i32, i32),
+    pub piece_squares_mg: [[i32; 64]; 6], // pawn, knight, bishop, rook, queen, king
+    pub piece_squares_eg: [[i32; 64]; 6],
+    pub doubled_pawn: (i32, i32),
+    pub isolated_pawn: (i32, i32),
+    pub backward_pawn: (i32, i32),
+    pub pawn_island: (i32, i32),
+    pub connected_passer: (i32, i32),
+    pub passed_pawn_mg: [i32; 8],
+    pub passed_pawn_eg: [i32; 8],
+    pub free_passer_mg: [i32; 8],
+    pub free_passer_eg: [i32; 8],
+    pub pawn_shield: [i32; 3],
+    pub pawn_storm: [i32; 5],
+    pub king_half_open_file: i32,
+    pub king_open_file: i32,
+    pub knight_attack_weight: i32,
+    pub bishop_attack_weight: i32,
+    pub rook_attack_weight: i32,
+    pub queen_attack_weight: i32,
+    pub king_danger: [i32; 16],
+    pub king_pawn_distance_eg: i32,
+    pub knight_mobility: (i32, i32),
+    pub bishop_mobility: (i32, i32),
+    pub rook_mobility: (i32, i32),
+    pub queen_mobility: (i32, i32),
+    pub bishop_pair: (i32, i32),
+    pub rook_open_file: (i32, i32),
+    pub rook_half_open_file: (i32, i32),
+    pub rook_on_seventh: (i32, i32),
+    pub knight_outpost: (i32, i32),
+    pub trapped_bishop: (i32, i32),
+    pub trapped_rook: (i32, i32),
+}
+
+pub const DEFAULT_PARAMS : EvalParams = EvalParams{
+    pawn_value: PAWN_VALUE,
+    knight_value: KNIGHT_VALUE,
+    bishop_value: BISHOP_VALUE,
+    rook_value: ROOK_VALUE,
+    queen_value: QUEEN_VALUE,
+    piece_squares_mg: [PAWN_MG, KNIGHT_MG, BISHOP_MG, ROOK_MG, QUEEN_MG, KING_MG],
+    piece_squares_eg: [PAWN_EG, KNIGHT_EG, BISHOP_EG, ROOK_EG, QUEEN_EG, KING_EG],
+    doubled_pawn: DOUBLED_PAWN,
+    isolated_pawn: ISOLATED_PAWN,
+    backward_pawn: BACKWARD_PAWN,
+    pawn_island: PAWN_ISLAND,
+    connected_passer: CONNECTED_PASSER,
+    passed_pawn_mg: PASSED_PAWN_MG,
+    passed_pawn_eg: PASSED_PAWN_EG,
+    free_passer_mg: FREE_PASSER_MG,
+    free_passer_eg: FREE_PASSER_EG,
+    pawn_shield: PAWN_SHIELD,
+    pawn_storm: PAWN_STORM,
+    king_half_open_file: KING_HALF_OPEN_FILE,
+    king_open_file: KING_OPEN_FILE,
+    knight_attack_weight: KNIGHT_ATTACK_WEIGHT,
+    bishop_attack_weight: BISHOP_ATTACK_WEIGHT,
+    rook_attack_weight: ROOK_ATTACK_WEIGHT,
+    queen_attack_weight: QUEEN_ATTACK_WEIGHT,
+    king_danger: KING_DANGER,
+    king_pawn_distance_eg: KING_PAWN_DISTANCE_EG,
+    knight_mobility: KNIGHT_MOBILITY,
+    bishop_mobility: BISHOP_MOBILITY,
+    rook_mobility: ROOK_MOBILITY,
+    queen_mobility: QUEEN_MOBILITY,
+    bishop_pair: BISHOP_PAIR,
+    rook_open_file: ROOK_OPEN_FILE,
+    rook_half_open_file: ROOK_HALF_OPEN_FILE,
+    rook_on_seventh: ROOK_ON_SEVENTH,
+    knight_outpost: KNIGHT_OUTPOST,
+    trapped_bishop: TRAPPED_BISHOP,
+    trapped_rook: TRAPPED_ROOK,
+};
+
+const PIECE_NAMES : [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+fn visit_pair<F : FnMut(&str, &mut i32)>(visit : &mut F, name : &str, pair : &mut (i32, i32)) {
+    visit(&format!("{}.mg", name), &mut pair.0);
+    visit(&format!("{}.eg", name), &mut pair.1);
+}
+
+fn visit_array<F : FnMut(&str, &mut i32)>(visit : &mut F, name : &str, values : &mut [i32]) {
+    for (index, value) in values.iter_mut().enumerate() {
+        visit(&format!("{}.{}", name, index), value);
+    }
+}
+
+impl EvalParams {
+    pub fn piece_value(& self, piece : u8) -> (i32, i32) {
+        match board::clear_piece_color(piece) {
+            board::PAWN => self.pawn_value,
+            board::KNIGHT => self.knight_value,
+            board::BISHOP => self.bishop_value,
+            board::ROOK => self.rook_value,
+            board::QUEEN => self.queen_value,
+            _ => (0, 0),
+        }
+    }
+
+    // Calls visit with the name and a reference to every single weight,
+    // in the order they are written to a parameter file
+    pub fn for_each_weight<F : FnMut(&str, &mut i32)>(&mut self, mut visit : F) {
+        visit_pair(&mut visit, "pawn_value", &mut self.pawn_value);
+        visit_pair(&mut visit, "knight_value", &mut self.knight_value);
+        visit_pair(&mut visit, "bishop_value", &mut self.bishop_value);
+        visit_pair(&mut visit, "rook_value", &mut self.rook_value);
+        visit_pair(&mut visit, "queen_value", &mut self.queen_value);
+        for (index, name) in PIECE_NAMES.iter().enumerate() {
+            visit_array(&mut visit, &format!("{}_squares.mg", name), &mut self.piece_squares_mg[index]);
+            visit_array(&mut visit, &format!("{}_squares.eg", name), &mut self.piece_squares_eg[index]);
+        }
+        visit_pair(&mut visit, "doubled_pawn", &mut self.doubled_pawn);
+        visit_pair(&mut visit, "isolated_pawn", &mut self.isolated_pawn);
+        visit_pair(&mut visit, "backward_pawn", &mut self.backward_pawn);
+        visit_pair(&mut visit, "pawn_island", &mut self.pawn_island);
+        visit_pair(&mut visit, "connected_passer", &mut self.connected_passer);
+        visit_array(&mut visit, "passed_pawn.mg", &mut self.passed_pawn_mg);
+        visit_array(&mut visit, "passed_pawn.eg", &mut self.passed_pawn_eg);
+        visit_array(&mut visit, "free_passer.mg", &mut self.free_passer_mg);
+        visit_array(&mut visit, "free_passer.eg", &mut self.free_passer_eg);
+        visit_array(&mut visit, "pawn_shield", &mut self.pawn_shield);
+        visit_array(&mut visit, "pawn_storm", &mut self.pawn_storm);
+        visit("king_half_open_file", &mut self.king_half_open_file);
+        visit("king_open_file", &mut self.king_open_file);
+        visit("knight_attack_weight", &mut self.knight_attack_weight);
+        visit("bishop_attack_weight", &mut self.bishop_attack_weight);
+        visit("rook_attack_weight", &mut self.rook_attack_weight);
+        visit("queen_attack_weight", &mut self.queen_attack_weight);
+        visit_array(&mut visit, "king_danger", &mut self.king_danger);
+        visit("king_pawn_distance.eg", &mut self.king_pawn_distance_eg);
+        visit_pair(&mut visit, "knight_mobility", &mut self.knight_mobility);
+        visit_pair(&mut visit, "bishop_mobility", &mut self.bishop_mobility);
+        visit_pair(&mut visit, "rook_mobility", &mut self.rook_mobility);
+        visit_pair(&mut visit, "queen_mobility", &mut self.queen_mobility);
+        visit_pair(&mut visit, "bishop_pair", &mut self.bishop_pair);
+        visit_pair(&mut visit, "rook_open_file", &mut self.rook_open_file);
+        visit_pair(&mut visit, "rook_half_open_file", &mut self.rook_half_open_file);
+        visit_pair(&mut visit, "rook_on_seventh", &mut self.rook_on_seventh);
+        visit_pair(&mut visit, "knight_outpost", &mut self.knight_outpost);
+        visit_pair(&mut visit, "trapped_bishop", &mut self.trapped_bishop);
+        visit_pair(&mut visit, "trapped_rook", &mut self.trapped_rook);
+    }
+
+    // One "name value" line per weight
+    pub fn to_text(& self) -> String {
+        let mut text = String::from("# Evaluation parameters in centipawns\n");
+        let mut copy = self.clone();
+        copy.for_each_weight(|name, value| text.push_str(&format!("{} {}\n", name, value)));
+        text
+    }
+}
+
+// Weights missing from the text keep their default value, unknown names are an error
+pub fn params_from_text(text : &str) -> Result<EvalParams, String> {
+    let mut values : HashMap<String, i32> = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields : Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            return Err(format!("line {}: expected \"name value\"", number + 1));
+        }
+        match fields[1].parse::<i32>() {
+            Ok(value) => { values.insert(fields[0].to_string(), value); },
+            Err(_) => return Err(format!("line {}: bad value {}", number + 1, fields[1])),
+        }
+    }
+
+    let mut params = DEFAULT_PARAMS;
+    params.for_each_weight(|name, value| {
+        if let Some(loaded) = values.remove(name) {
+            *value = loaded;
+        }
+    });
+    match values.keys().next() {
+        Some(name) => Err(format!("unknown parameter {}", name)),
+        None => Ok(params),
+    }
+}
+
+pub fn load_params(path : &str) -> Result<EvalParams, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => params_from_text(&text),
+        Err(error) => Err(format!("{}: {}", path, error)),
+    }
+}
+
+pub fn save_params(path : &str, params : &EvalParams) -> Result<(), String> {
+    fs::write(path, params.to_text()).map_err(|error| format!("{}: {}", path, error))
+}
+
+// The parameters eval() uses, with the version that tells every thread's
+// pawn table which set its scores came from. set_params swaps in a new set,
+// so a search can hold on to the one it started with and never lock.
+#[derive(Clone)]
+pub struct ParamsSnapshot {
+    version: usize,
+    params: Arc<EvalParams>,
+}
+
+// None until first used, standing for DEFAULT_PARAMS
+static PARAMS : RwLock<Option<ParamsSnapshot>> = RwLock::new(None);
+static PARAMS_VERSION : AtomicUsize = AtomicUsize::new(0);
+
+pub fn params_snapshot() -> ParamsSnapshot {
+    if let Some(ref snapshot) = *PARAMS.read().unwrap() {
+        return snapshot.clone();
+    }
+    let mut params = PARAMS.write().unwrap();
+    params.get_or_insert_with(|| ParamsSnapshot{ version: 0, params: Arc::new(DEFAULT_PARAMS) }).clone()
+}
+
+pub fn params() -> EvalParams {
+    (*params_snapshot().params).clone()
+}
+
+pub fn set_params(params : EvalParams) {
+    let version = PARAMS_VERSION.fetch_add(1, Ordering::SeqCst) + 1;
+    *PARAMS.write().unwrap() = Some(ParamsSnapshot{ version, params: Arc::new(params) });
+}
+
+pub fn mate_in(ply : i32) -> i32 {
+    MATE_SCORE - ply
+}
+
+pub fn mated_in(ply : i32) -> i32 {
+    -MATE_SCORE + ply
+}
+
+pub fn is_mate_score(score : i32) -> bool {
+    score.abs() > MATE_BOUND
+}
+
+// Full moves until mate, negative when the side to move gets mated
+pub fn mate_moves(score : i32) -> Option<i32> {
+    if score > MATE_BOUND {
+        Some((MATE_SCORE - score + 1) / 2)
+    } else if score < -MATE_BOUND {
+        Some(-(MATE_SCORE + score) / 2)
+    } else {
+        None
+    }
+}
+
+// The score part of a UCI info line, "cp 35" or "mate -3"
+pub fn score_to_uci(score : i32) -> String {
+    match mate_moves(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    fn add(&mut self, mg : i32, eg : i32) {
+        self.mg += mg;
+        self.eg += eg;
+    }
+}
+
+// Order of the piece-square tables in EvalParams
+fn piece_index(piece : u8) -> usize {
     match board::clear_piece_color(piece) {
-        board::PAWN => 1,
-        board::BISHOP => 3,
-        board::KNIGHT => 3,
-        board::ROOK => 5,
-        board::QUEEN => 9,
-        board::KING => 0,
-        0_u8 => 0, // empty square
-        6_u8 | 8_u8..=u8::MAX => todo!(),
+        board::PAWN => 0,
+        board::KNIGHT => 1,
+        board::BISHOP => 2,
+        board::ROOK => 3,
+        board::QUEEN => 4,
+        board::KING => 5,
+        _ => unreachable!("empty square has no table"),
     }
 }
 
-pub fn eval(board : &mut board::ChessBoard) -> i32 {
-    let mut score : i32 = 0;
+// Black uses the white tables with the rows flipped
+fn table_index(color : u8, row : usize, col : usize) -> usize {
+    if color == board::WHITE {
+        row * 8 + col
+    } else {
+        (7 - row) * 8 + col
+    }
+}
+
+fn material(params : &EvalParams, board : &board::ChessBoard, color : u8) -> Score {
+    let mut score = Score::default();
     for row in 0..=7 {
         for col in 0..=7 {
             let piece = board.board[row][col];
-            let val = piece_value(piece);
-            if piece & 0b1000 == board::WHITE {
-                score += val;
-            } else {
-                score -= val;
+            if piece != 0b0000 && piece & 0b1000 == color {
+                let value = params.piece_value(piece);
+                score.add(value.0, value.1);
             }
         }
     }
-    if board.protagonist == board::WHITE {
-        score = score;
+    score
+}
+
+fn piece_squares(params : &EvalParams, board : &board::ChessBoard, color : u8) -> Score {
+    let mut score = Score::default();
+    for row in 0..=7 {
+        for col in 0..=7 {
+            let piece = board.board[row][col];
+            if piece == 0b0000 || piece & 0b1000 != color {
+                continue;
+            }
+            let piece_index = piece_index(piece);
+            let index = table_index(color, row, col);
+            score.add(params.piece_squares_mg[piece_index][index], params.piece_squares_eg[piece_index][index]);
+        }
+    }
+    score
+}
+
+fn color_index(color : u8) -> usize {
+    if color == board::WHITE { 0 } else { 1 }
+}
+
+// Rank counted from the color's own back rank, 0 to 7
+fn relative_rank(color : u8, row : usize) -> usize {
+    if color == board::WHITE { 7 - row } else { row }
+}
+
+// One row towards the enemy
+fn forward(color : u8, row : usize) -> Option<usize> {
+    if color == board::WHITE {
+        row.checked_sub(1)
+    } else if row < 7 {
+        Some(row + 1)
     } else {
-        score = -1*score;
+        None
+    }
+}
+
+fn enemy(color : u8) -> u8 {
+    if color == board::WHITE { board::BLACK } else { board::WHITE }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    scores: [Score; 2],
+    passed: [u64; 2], // squares of passed pawns, bit row * 8 + col
+}
+
+const PAWN_TABLE_SIZE : usize = 16384;
+
+// One table per search thread, along with the parameter version it was filled with.
+// An unused entry also answers correctly for positions without pawns, whose key is 0.
+thread_local! {
+    static PAWN_TABLE : RefCell<(usize, Vec<PawnEntry>)> = RefCell::new((0, vec![PawnEntry::default(); PAWN_TABLE_SIZE]));
+}
+
+fn is_pawn(board : &board::ChessBoard, row : usize, col : usize, color : u8) -> bool {
+    board.board[row][col] == board::PAWN | color
+}
+
+fn is_passed(board : &board::ChessBoard, color : u8, row : usize, col : usize) -> bool {
+    let mut ahead = forward(color, row);
+    while let Some(r) = ahead {
+        for c in col.saturating_sub(1)..=(col + 1).min(7) {
+            if is_pawn(board, r, c, enemy(color)) {
+                return false;
+            }
+        }
+        ahead = forward(color, r);
+    }
+    true
+}
+
+// No friendly pawn beside or behind it on the neighbouring files to protect its
+// advance, and the square in front is controlled by an enemy pawn
+fn is_backward(board : &board::ChessBoard, color : u8, row : usize, col : usize) -> bool {
+    for r in 0..8 {
+        if relative_rank(color, r) > relative_rank(color, row) {
+            continue;
+        }
+        if (col > 0 && is_pawn(board, r, col - 1, color)) || (col < 7 && is_pawn(board, r, col + 1, color)) {
+            return false;
+        }
+    }
+    let stop = match forward(color, row) {
+        Some(stop) => stop,
+        None => return false,
+    };
+    match forward(color, stop) {
+        Some(attacker_row) => (col > 0 && is_pawn(board, attacker_row, col - 1, enemy(color)))
+            || (col < 7 && is_pawn(board, attacker_row, col + 1, enemy(color))),
+        None => false,
+    }
+}
+
+fn pawn_structure(params : &EvalParams, board : &board::ChessBoard, color : u8) -> (Score, u64) {
+    let mut score = Score::default();
+    let mut passed : u64 = 0;
+    let mut file_counts = [0; 8];
+    for (col, count) in file_counts.iter_mut().enumerate() {
+        *count = (0..8).filter(|row| is_pawn(board, *row, col, color)).count() as i32;
+    }
+
+    let mut islands = 0;
+    for col in 0..8 {
+        if file_counts[col] > 0 && (col == 0 || file_counts[col - 1] == 0) {
+            islands += 1;
+        }
+        if file_counts[col] > 1 {
+            let extra = file_counts[col] - 1;
+            score.add(params.doubled_pawn.0 * extra, params.doubled_pawn.1 * extra);
+        }
+    }
+    if islands > 1 {
+        score.add(params.pawn_island.0 * (islands - 1), params.pawn_island.1 * (islands - 1));
+    }
+
+    for row in 0..8 {
+        for col in 0..8 {
+            if !is_pawn(board, row, col, color) {
+                continue;
+            }
+            let isolated = (col == 0 || file_counts[col - 1] == 0) && (col == 7 || file_counts[col + 1] == 0);
+            if isolated {
+                score.add(params.isolated_pawn.0, params.isolated_pawn.1);
+            } else if is_backward(board, color, row, col) {
+                score.add(params.backward_pawn.0, params.backward_pawn.1);
+            }
+            if is_passed(board, color, row, col) {
+                let rank = relative_rank(color, row);
+                score.add(params.passed_pawn_mg[rank], params.passed_pawn_eg[rank]);
+                passed |= 1 << (row * 8 + col);
+            }
+        }
+    }
+
+    // Passers side by side or defending each other
+    for square in 0..64usize {
+        if passed & (1 << square) == 0 {
+            continue;
+        }
+        let (row, col) = (square / 8, square % 8);
+        let mut connected = false;
+        for r in row.saturating_sub(1)..=(row + 1).min(7) {
+            for c in [col.wrapping_sub(1), col + 1].iter() {
+                if *c < 8 && passed & (1 << (r * 8 + c)) != 0 {
+                    connected = true;
+                }
+            }
+        }
+        if connected {
+            score.add(params.connected_passer.0, params.connected_passer.1);
+        }
+    }
+    (score, passed)
+}
+
+fn pawn_entry(params : &EvalParams, board : &board::ChessBoard, key : u64) -> PawnEntry {
+    let (white_score, white_passed) = pawn_structure(params, board, board::WHITE);
+    let (black_score, black_passed) = pawn_structure(params, board, board::BLACK);
+    PawnEntry{ key, scores: [white_score, black_score], passed: [white_passed, black_passed] }
+}
+
+fn probe_pawn_table(snapshot : &ParamsSnapshot, board : &board::ChessBoard) -> PawnEntry {
+    let params = &snapshot.params;
+    let key = zobrist::pawn_hash(board);
+    let version = snapshot.version;
+    PAWN_TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        if table.0 != version {
+            *table = (version, vec![PawnEntry::default(); PAWN_TABLE_SIZE]);
+        }
+        let index = key as usize % PAWN_TABLE_SIZE;
+        if table.1[index].key != key {
+            table.1[index] = pawn_entry(params, board, key);
+        }
+        table.1[index]
+    })
+}
+
+// Depends on the other pieces as well, so it is not part of the pawn table
+fn free_passers(params : &EvalParams, board : &board::ChessBoard, color : u8, passed : u64) -> Score {
+    let mut score = Score::default();
+    for square in 0..64usize {
+        if passed & (1 << square) == 0 {
+            continue;
+        }
+        let (row, col) = (square / 8, square % 8);
+        if let Some(stop) = forward(color, row) {
+            if board.board[stop][col] == 0b0000 {
+                let rank = relative_rank(color, row);
+                score.add(params.free_passer_mg[rank], params.free_passer_eg[rank]);
+            }
+        }
     }
     score
 }
+
+fn square_bit(row : usize, col : usize) -> u64 {
+    1 << (row * 8 + col)
+}
+
+// A piece's square, the piece and the squares it attacks
+type PieceAttacks = ((usize, usize), u8, Vec<(usize, usize)>);
+
+// Every piece with the squares it attacks, worked out once per evaluation
+struct AttackMaps {
+    pieces: Vec<PieceAttacks>,
+    pawn_attacks: [u64; 2],
+}
+
+fn attack_maps(board : &board::ChessBoard) -> AttackMaps {
+    let mut maps = AttackMaps{ pieces: Vec::new(), pawn_attacks: [0, 0] };
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if piece == 0b0000 {
+                continue;
+            }
+            let attacks = board.attacks_from((row, col));
+            if board::clear_piece_color(piece) == board::PAWN {
+                for square in &attacks {
+                    maps.pawn_attacks[color_index(piece & 0b1000)] |= square_bit(square.0, square.1);
+                }
+            }
+            maps.pieces.push(((row, col), piece, attacks));
+        }
+    }
+    maps
+}
+
+fn king_position(board : &board::ChessBoard, color : u8) -> Option<(usize, usize)> {
+    let king_pos = if color == board::WHITE { board.white_king_pos } else { board.black_king_pos };
+    if board.board[king_pos.0][king_pos.1] == board::KING | color {
+        Some(king_pos)
+    } else {
+        None
+    }
+}
+
+fn attack_weight(params : &EvalParams, piece : u8) -> usize {
+    match board::clear_piece_color(piece) {
+        board::KNIGHT => params.knight_attack_weight.max(0) as usize,
+        board::BISHOP => params.bishop_attack_weight.max(0) as usize,
+        board::ROOK => params.rook_attack_weight.max(0) as usize,
+        board::QUEEN => params.queen_attack_weight.max(0) as usize,
+        _ => 0,
+    }
+}
+
+fn king_safety(params : &EvalParams, board : &board::ChessBoard, color : u8, maps : &AttackMaps) -> Score {
+    let mut score = Score::default();
+    let king = match king_position(board, color) {
+        Some(king) => king,
+        None => return score,
+    };
+
+    for col in king.1.saturating_sub(1)..=(king.1 + 1).min(7) {
+        // Shield and storm, looking up the board from the king
+        let mut shield_found = false;
+        let mut storm_found = false;
+        let mut ahead = forward(color, king.0);
+        let mut distance = 1;
+        while let Some(row) = ahead {
+            if !shield_found && distance < params.pawn_shield.len() && is_pawn(board, row, col, color) {
+                score.add(params.pawn_shield[distance], 0);
+                shield_found = true;
+            }
+            if !storm_found && distance < params.pawn_storm.len() && is_pawn(board, row, col, enemy(color)) {
+                score.add(params.pawn_storm[distance], 0);
+                storm_found = true;
+            }
+            ahead = forward(color, row);
+            distance += 1;
+        }
+
+        let own_pawn = (0..8).any(|row| is_pawn(board, row, col, color));
+        let enemy_pawn = (0..8).any(|row| is_pawn(board, row, col, enemy(color)));
+        if !own_pawn && !enemy_pawn {
+            score.add(params.king_open_file, 0);
+        } else if !own_pawn {
+            score.add(params.king_half_open_file, 0);
+        }
+    }
+
+    // King zone is the king's neighbourhood plus two rows towards the enemy
+    let mut zone : u64 = 0;
+    for row in king.0.saturating_sub(1)..=(king.0 + 1).min(7) {
+        for col in king.1.saturating_sub(1)..=(king.1 + 1).min(7) {
+            zone |= square_bit(row, col);
+        }
+    }
+    if let Some(front) = forward(color, king.0).and_then(|row| forward(color, row)) {
+        for col in king.1.saturating_sub(1)..=(king.1 + 1).min(7) {
+            zone |= square_bit(front, col);
+        }
+    }
+    let mut attackers = 0;
+    let mut weight = 0;
+    for (_, piece, attacks) in &maps.pieces {
+        if piece & 0b1000 == color || attack_weight(params, *piece) == 0 {
+            continue;
+        }
+        if attacks.iter().any(|square| zone & square_bit(square.0, square.1) != 0) {
+            attackers += 1;
+            weight += attack_weight(params, *piece);
+        }
+    }
+    if attackers >= 2 {
+        score.add(-params.king_danger[weight.min(params.king_danger.len() - 1)], 0);
+    }
+
+    // Endgame activity, the king should head for the pawns
+    let mut closest = None;
+    for row in 0..8 {
+        for col in 0..8 {
+            if board::clear_piece_color(board.board[row][col]) == board::PAWN {
+                let distance = king.0.abs_diff(row).max(king.1.abs_diff(col)) as i32;
+                closest = Some(closest.map_or(distance, |best : i32| best.min(distance)));
+            }
+        }
+    }
+    if let Some(distance) = closest {
+        score.add(0, params.king_pawn_distance_eg * distance);
+    }
+    score
+}
+
+fn mobility(params : &EvalParams, board : &board::ChessBoard, color : u8, maps : &AttackMaps) -> Score {
+    let mut score = Score::default();
+    let enemy_pawn_attacks = maps.pawn_attacks[color_index(enemy(color))];
+    for (_, piece, attacks) in &maps.pieces {
+        if piece & 0b1000 != color {
+            continue;
+        }
+        let (weight, center) = match board::clear_piece_color(*piece) {
+            board::KNIGHT => (params.knight_mobility, KNIGHT_MOBILITY_CENTER),
+            board::BISHOP => (params.bishop_mobility, BISHOP_MOBILITY_CENTER),
+            board::ROOK => (params.rook_mobility, ROOK_MOBILITY_CENTER),
+            board::QUEEN => (params.queen_mobility, QUEEN_MOBILITY_CENTER),
+            _ => continue,
+        };
+        let safe = attacks.iter().filter(|square| {
+            let target = board.board[square.0][square.1];
+            (target == 0b0000 || target & 0b1000 != color) && enemy_pawn_attacks & square_bit(square.0, square.1) == 0
+        }).count() as i32;
+        score.add(weight.0 * (safe - center), weight.1 * (safe - center));
+    }
+    score
+}
+
+fn piece_activity(params : &EvalParams, board : &board::ChessBoard, color : u8, maps : &AttackMaps) -> Score {
+    let mut score = Score::default();
+    let mut bishops = 0;
+    let own_pawn_attacks = maps.pawn_attacks[color_index(color)];
+    let enemy_king = king_position(board, enemy(color));
+    // Rank 7 and 8 from this side's point of view
+    let seventh = if color == board::WHITE { 1 } else { 6 };
+    let eighth = if color == board::WHITE { 0 } else { 7 };
+
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if piece == 0b0000 || piece & 0b1000 != color {
+                continue;
+            }
+            match board::clear_piece_color(piece) {
+                board::BISHOP => {
+                    bishops += 1;
+                    // Bxa7 b6 style traps
+                    if row == seventh && (col == 0 || col == 7) {
+                        let blocker_col = if col == 0 { 1 } else { 6 };
+                        if let Some(blocker_row) = forward(enemy(color), row) {
+                            if is_pawn(board, blocker_row, blocker_col, enemy(color)) {
+                                score.add(params.trapped_bishop.0, params.trapped_bishop.1);
+                            }
+                        }
+                    }
+                },
+                board::ROOK => {
+                    let own_pawn = (0..8).any(|r| is_pawn(board, r, col, color));
+                    let enemy_pawn = (0..8).any(|r| is_pawn(board, r, col, enemy(color)));
+                    if !own_pawn && !enemy_pawn {
+                        score.add(params.rook_open_file.0, params.rook_open_file.1);
+                    } else if !own_pawn {
+                        score.add(params.rook_half_open_file.0, params.rook_half_open_file.1);
+                    }
+                    if row == seventh {
+                        let king_cut_off = enemy_king.is_some_and(|king| king.0 == eighth);
+                        let pawns_to_eat = (0..8).any(|c| is_pawn(board, seventh, c, enemy(color)));
+                        if king_cut_off || pawns_to_eat {
+                            score.add(params.rook_on_seventh.0, params.rook_on_seventh.1);
+                        }
+                    }
+                },
+                board::KNIGHT => {
+                    let rank = relative_rank(color, row);
+                    let supported = own_pawn_attacks & square_bit(row, col) != 0;
+                    if (3..=5).contains(&rank) && supported && !can_be_chased(board, color, row, col) {
+                        score.add(params.knight_outpost.0, params.knight_outpost.1);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+    if bishops >= 2 {
+        score.add(params.bishop_pair.0, params.bishop_pair.1);
+    }
+
+    // A rook in the corner behind a king that stepped aside instead of castling
+    if let Some(king) = king_position(board, color) {
+        let can_castle = if color == board::WHITE {
+            board.white_kingside_castle || board.white_queenside_castle
+        } else {
+            board.black_kingside_castle || board.black_queenside_castle
+        };
+        let back_rank = 7 - eighth;
+        if king.0 == back_rank && !can_castle {
+            let rook = board::ROOK | color;
+            let kingside_trap = (king.1 == 5 || king.1 == 6) && (board.board[back_rank][7] == rook || (board.board[back_rank][6] == rook && king.1 == 5));
+            let queenside_trap = (king.1 == 1 || king.1 == 2) && (board.board[back_rank][0] == rook || (board.board[back_rank][1] == rook && king.1 == 2));
+            if kingside_trap || queenside_trap {
+                score.add(params.trapped_rook.0, params.trapped_rook.1);
+            }
+        }
+    }
+    score
+}
+
+// Could an enemy pawn ever attack this square
+fn can_be_chased(board : &board::ChessBoard, color : u8, row : usize, col : usize) -> bool {
+    let mut ahead = forward(color, row);
+    while let Some(r) = ahead {
+        if (col > 0 && is_pawn(board, r, col - 1, enemy(color))) || (col < 7 && is_pawn(board, r, col + 1, enemy(color))) {
+            return true;
+        }
+        ahead = forward(color, r);
+    }
+    false
+}
+
+// MAX_PHASE in the opening down to 0 with only kings and pawns left
+pub fn game_phase(board : &board::ChessBoard) -> i32 {
+    let mut phase = 0;
+    for row in 0..=7 {
+        for col in 0..=7 {
+            phase += match board::clear_piece_color(board.board[row][col]) {
+                board::KNIGHT => KNIGHT_PHASE,
+                board::BISHOP => BISHOP_PHASE,
+                board::ROOK => ROOK_PHASE,
+                board::QUEEN => QUEEN_PHASE,
+                _ => 0,
+            };
+        }
+    }
+    phase.min(MAX_PHASE)
+}
+
+fn taper(score : Score, phase : i32) -> i32 {
+    (score.mg * phase + score.eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+const TERM_COUNT : usize = 7;
+
+// Every evaluation term for one side, eval() and eval_trace() both add these up
+fn side_terms(params : &EvalParams, board : &board::ChessBoard, color : u8, pawns : &PawnEntry, maps : &AttackMaps) -> [(&'static str, Score); TERM_COUNT] {
+    [
+        ("Material", material(params, board, color)),
+        ("Piece squares", piece_squares(params, board, color)),
+        ("Pawn structure", pawns.scores[color_index(color)]),
+        ("Free passers", free_passers(params, board, color, pawns.passed[color_index(color)])),
+        ("King safety", king_safety(params, board, color, maps)),
+        ("Mobility", mobility(params, board, color, maps)),
+        ("Piece activity", piece_activity(params, board, color, maps)),
+    ]
+}
+
+fn side_score(params : &EvalParams, board : &board::ChessBoard, color : u8, pawns : &PawnEntry, maps : &AttackMaps) -> Score {
+    let mut score = Score::default();
+    for (_, term) in side_terms(params, board, color, pawns, maps).iter() {
+        score.add(term.mg, term.eg);
+    }
+    score
+}
+
+// From white's point of view
+fn evaluate(params : &EvalParams, board : &board::ChessBoard, pawns : &PawnEntry) -> i32 {
+    let maps = attack_maps(board);
+    let white = side_score(params, board, board::WHITE, pawns, &maps);
+    let black = side_score(params, board, board::BLACK, pawns, &maps);
+    let total = Score{ mg: white.mg - black.mg, eg: white.eg - black.eg };
+    endgame::adjust(params, board, taper(total, game_phase(board))).0
+}
+
+// In centipawns from the side to move's point of view. Boards with a network
+// attached are evaluated by the network instead.
+pub fn eval(board : &mut board::ChessBoard) -> i32 {
+    eval_snapshot(&params_snapshot(), board)
+}
+
+// Same as eval() with parameters taken earlier, for the search
+pub fn eval_snapshot(snapshot : &ParamsSnapshot, board : &mut board::ChessBoard) -> i32 {
+    if let Some(ref accumulators) = board.nnue {
+        return accumulators.evaluate(board.protagonist);
+    }
+    let pawns = probe_pawn_table(snapshot, board);
+    let score = evaluate(&snapshot.params, board, &pawns);
+
+    if board.protagonist == board::WHITE {
+        score
+    } else {
+        -score
+    }
+}
+
+// Same as eval() with the given parameters, skipping the pawn table
+pub fn eval_with(params : &EvalParams, board : &board::ChessBoard) -> i32 {
+    let pawns = pawn_entry(params, board, 0);
+    let score = evaluate(params, board, &pawns);
+
+    if board.protagonist == board::WHITE {
+        score
+    } else {
+        -score
+    }
+}
+
+pub struct EvalTrace {
+    pub terms: Vec<(&'static str, Score, Score)>, // name, white, black
+    pub phase: i32,
+    pub endgame: Option<&'static str>, // endgame rule that changed the score
+    pub score: i32, // blended, from white's point of view
+}
+
+pub fn eval_trace(board : &board::ChessBoard) -> EvalTrace {
+    let snapshot = params_snapshot();
+    let params = &snapshot.params;
+    let pawns = probe_pawn_table(&snapshot, board);
+    let maps = attack_maps(board);
+    let white = side_terms(params, board, board::WHITE, &pawns, &maps);
+    let black = side_terms(params, board, board::BLACK, &pawns, &maps);
+
+    let mut terms = Vec::new();
+    let mut total = Score::default();
+    for index in 0..TERM_COUNT {
+        terms.push((white[index].0, white[index].1, black[index].1));
+        total.add(white[index].1.mg - black[index].1.mg, white[index].1.eg - black[index].1.eg);
+    }
+    let phase = game_phase(board);
+    let (score, endgame) = endgame::adjust(params, board, taper(total, phase));
+    EvalTrace{ terms, phase, endgame, score }
+}
+
+fn pawns(centipawns : i32) -> String {
+    format!("{:.2}", centipawns as f64 / 100.0)
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16}|{:^17}|{:^17}|{:^17}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:<16}|{:>8} {:>8}|{:>8} {:>8}|{:>8} {:>8}", "", "MG", "EG", "MG", "EG", "MG", "EG")?;
+        writeln!(f, "{:-<16}+{:-<17}+{:-<17}+{:-<17}", "", "", "", "")?;
+        let mut total = Score::default();
+        for (name, white, black) in &self.terms {
+            let mg = white.mg - black.mg;
+            let eg = white.eg - black.eg;
+            total.add(mg, eg);
+            writeln!(f, "{:<16}|{:>8} {:>8}|{:>8} {:>8}|{:>8} {:>8}", name,
+                     pawns(white.mg), pawns(white.eg), pawns(black.mg), pawns(black.eg), pawns(mg), pawns(eg))?;
+        }
+        writeln!(f, "{:-<16}+{:-<17}+{:-<17}+{:-<17}", "", "", "", "")?;
+        writeln!(f, "{:<16}|{:>17}|{:>17}|{:>8} {:>8}", "Total", "", "", pawns(total.mg), pawns(total.eg))?;
+        writeln!(f)?;
+        writeln!(f, "Phase: {}/{} (middlegame weight)", self.phase, MAX_PHASE)?;
+        if let Some(endgame) = self.endgame {
+            writeln!(f, "Endgame: {}", endgame)?;
+        }
+        write!(f, "Final evaluation: {} (white side)", pawns(self.score))
+    }
+}
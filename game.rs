@@ -0,0 +1,86 @@
+use board;
+use san;
+
+pub struct RecordedMove {
+    pub amove: ((usize, usize),(usize, usize, u8)),
+    pub san: String,
+    pub eval: Option<i32>, // centipawns from white's point of view, after the move
+    pub clock: Option<u64>, // milliseconds left on the mover's clock after the move
+    pub comment: Option<String>,
+}
+
+pub struct GameRecord {
+    pub start_fen: String,
+    pub board: board::ChessBoard,
+    pub moves: Vec<RecordedMove>,
+    pub tags: Vec<(String, String)>,
+    pub result: String,
+}
+
+pub fn new_game() -> GameRecord {
+    game_from_fen(board::START_FEN)
+}
+
+pub fn game_from_fen(fen : &str) -> GameRecord {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    board.from_fen(fen);
+    GameRecord{ start_fen: board.to_fen(), board, moves: Vec::new(), tags: Vec::new(), result: "*".to_string() }
+}
+
+impl GameRecord {
+    pub fn set_tag(&mut self, name : &str, value : &str) {
+        for tag in self.tags.iter_mut() {
+            if tag.0 == name {
+                tag.1 = value.to_string();
+                return;
+            }
+        }
+        self.tags.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn get_tag(& self, name : &str) -> Option<&str> {
+        for tag in &self.tags {
+            if tag.0 == name {
+                return Some(&tag.1);
+            }
+        }
+        None
+    }
+
+    // Returns false and leaves the record untouched if the move is not legal
+    pub fn play(&mut self, amove : ((usize, usize),(usize, usize, u8))) -> bool {
+        if !self.board.get_legal_moves().contains(&amove) {
+            return false;
+        }
+        let san = san::move_to_san(&mut self.board, amove);
+        self.board.make(amove.0, amove.1);
+        self.moves.push(RecordedMove{ amove, san, eval: None, clock: None, comment: None });
+
+        if self.board.get_legal_moves().is_empty() {
+            if !self.board.protagonist_in_check() {
+                self.result = "1/2-1/2".to_string();
+            } else if self.board.protagonist == board::WHITE {
+                self.result = "0-1".to_string();
+            } else {
+                self.result = "1-0".to_string();
+            }
+        }
+        true
+    }
+
+    pub fn play_san(&mut self, san : &str) -> bool {
+        match san::san_to_move(&mut self.board, san) {
+            Some(amove) => self.play(amove),
+            None => false,
+        }
+    }
+
+    pub fn annotate(&mut self, eval : Option<i32>, clock : Option<u64>, comment : Option<&str>) {
+        if let Some(last) = self.moves.last_mut() {
+            last.eval = eval;
+            last.clock = clock;
+            last.comment = comment.map(|text| text.to_string());
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use board;
+
+use std::sync::OnceLock;
+
+// King and pawn against king, from the pawn side's point of view with the pawn
+// moving up the board as white's does. Pawns on the e-h files are mirrored onto
+// a-d, which leaves 24 pawn squares, 64 squares for each king and the side to move.
+const PAWN_SQUARES : usize = 24;
+const POSITIONS : usize = 2 * 64 * 64 * PAWN_SQUARES;
+
+// What a move leads to: another position in the table or a known result
+const SUCCESSOR_WIN : u32 = u32::MAX;
+const SUCCESSOR_DRAW : u32 = u32::MAX - 1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Unknown,
+    Invalid,
+    Draw,
+    Win,
+}
+
+// One bit per position, set when the pawn side wins
+pub struct Bitbase {
+    wins: Vec<u64>,
+}
+
+static BITBASE : OnceLock<Bitbase> = OnceLock::new();
+
+// Squares are (row, col) board coordinates, the pawn on cols 0-3 and rows 1-6
+fn index(strong_to_move : bool, strong_king : (usize, usize), weak_king : (usize, usize), pawn : (usize, usize)) -> usize {
+    let side = if strong_to_move { 0 } else { 1 };
+    let pawn_index = (pawn.0 - 1) * 4 + pawn.1;
+    ((side * 64 + strong_king.0 * 8 + strong_king.1) * 64 + weak_king.0 * 8 + weak_king.1) * PAWN_SQUARES + pawn_index
+}
+
+// Strong side to move, then the strong king, weak king and pawn squares
+type Position = (bool, (usize, usize), (usize, usize), (usize, usize));
+
+fn decode(index : usize) -> Position {
+    let pawn_index = index % PAWN_SQUARES;
+    let weak_king = (index / PAWN_SQUARES) % 64;
+    let strong_king = (index / PAWN_SQUARES / 64) % 64;
+    let side = index / PAWN_SQUARES / 64 / 64;
+    (side == 0, (strong_king / 8, strong_king % 8), (weak_king / 8, weak_king % 8), (pawn_index / 4 + 1, pawn_index % 4))
+}
+
+fn setup(board : &mut board::ChessBoard, strong_to_move : bool, strong_king : (usize, usize), weak_king : (usize, usize), pawn : (usize, usize)) {
+    board.board = [[0u8; 8]; 8];
+    board.board[strong_king.0][strong_king.1] = board::KING | board::WHITE;
+    board.board[weak_king.0][weak_king.1] = board::KING | board::BLACK;
+    board.board[pawn.0][pawn.1] = board::PAWN | board::WHITE;
+    board.white_king_pos = strong_king;
+    board.black_king_pos = weak_king;
+    board.white_kingside_castle = false;
+    board.white_queenside_castle = false;
+    board.black_kingside_castle = false;
+    board.black_queenside_castle = false;
+    board.ep = None;
+    board.set_protagonist(if strong_to_move { board::WHITE } else { board::BLACK });
+}
+
+// After a promotion it is the defending king's move: the new piece wins unless
+// it can be taken or the king is stalemated
+fn promotion_result(board : &mut board::ChessBoard, square : (usize, usize)) -> u32 {
+    let replies = board.get_legal_moves();
+    if replies.is_empty() {
+        return if board.protagonist_in_check() { SUCCESSOR_WIN } else { SUCCESSOR_DRAW };
+    }
+    if replies.iter().any(|reply| (reply.1.0, reply.1.1) == square) {
+        SUCCESSOR_DRAW
+    } else {
+        SUCCESSOR_WIN
+    }
+}
+
+fn successors(board : &mut board::ChessBoard) -> Vec<u32> {
+    let mut result = Vec::new();
+    for amove in board.get_legal_moves() {
+        let promotion = amove.1.2;
+        if promotion == board::KNIGHT || promotion == board::BISHOP {
+            continue; // never better than a queen or rook
+        }
+        let state = board.save_state();
+        let captured_piece = board.make(amove.0, amove.1).unwrap();
+        if captured_piece != 0b0000 {
+            result.push(SUCCESSOR_DRAW);
+        } else if promotion != 0 {
+            result.push(promotion_result(board, (amove.1.0, amove.1.1)));
+        } else {
+            let pawn = (0..8).flat_map(|row| (0..4).map(move |col| (row, col)))
+                .find(|square| board.board[square.0][square.1] == board::PAWN | board::WHITE).unwrap();
+            let strong_to_move = board.protagonist == board::WHITE;
+            result.push(index(strong_to_move, board.white_king_pos, board.black_king_pos, pawn) as u32);
+        }
+        board.unmake(amove.0, amove.1, captured_piece);
+        board.restore_state(state);
+    }
+    result
+}
+
+// Retrograde analysis: every move of every position is generated once, then
+// results are propagated until nothing changes. Whatever is left undecided
+// cannot be forced to a win and is a draw.
+pub fn generate() -> Bitbase {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let mut results = vec![Outcome::Unknown; POSITIONS];
+    let mut moves : Vec<Vec<u32>> = vec![Vec::new(); POSITIONS];
+
+    for position in 0..POSITIONS {
+        let (strong_to_move, strong_king, weak_king, pawn) = decode(position);
+        if strong_king == weak_king || strong_king == pawn || weak_king == pawn
+            || strong_king.0.abs_diff(weak_king.0) <= 1 && strong_king.1.abs_diff(weak_king.1) <= 1 {
+            results[position] = Outcome::Invalid;
+            continue;
+        }
+        // The side that just moved cannot be in check
+        setup(&mut board, !strong_to_move, strong_king, weak_king, pawn);
+        if board.protagonist_in_check() {
+            results[position] = Outcome::Invalid;
+            continue;
+        }
+        setup(&mut board, strong_to_move, strong_king, weak_king, pawn);
+        moves[position] = successors(&mut board);
+        if moves[position].is_empty() {
+            results[position] = if !strong_to_move && board.protagonist_in_check() { Outcome::Win } else { Outcome::Draw };
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for position in 0..POSITIONS {
+            if results[position] != Outcome::Unknown {
+                continue;
+            }
+            let strong_to_move = position < POSITIONS / 2;
+            let mut any_win = false;
+            let mut any_draw = false;
+            let mut all_known = true;
+            for successor in &moves[position] {
+                let result = match *successor {
+                    SUCCESSOR_WIN => Outcome::Win,
+                    SUCCESSOR_DRAW => Outcome::Draw,
+                    next => results[next as usize],
+                };
+                match result {
+                    Outcome::Win => any_win = true,
+                    Outcome::Draw => any_draw = true,
+                    _ => all_known = false,
+                }
+            }
+            let result = if strong_to_move {
+                if any_win { Outcome::Win } else if all_known { Outcome::Draw } else { Outcome::Unknown }
+            } else {
+                if any_draw { Outcome::Draw } else if all_known { Outcome::Win } else { Outcome::Unknown }
+            };
+            if result != Outcome::Unknown {
+                results[position] = result;
+                changed = true;
+            }
+        }
+    }
+
+    let mut wins = vec![0u64; POSITIONS.div_ceil(64)];
+    for position in 0..POSITIONS {
+        if results[position] == Outcome::Win {
+            wins[position / 64] |= 1 << (position % 64);
+        }
+    }
+    Bitbase{ wins }
+}
+
+// Generated on first use
+pub fn bitbase() -> &'static Bitbase {
+    BITBASE.get_or_init(generate)
+}
+
+impl Bitbase {
+    pub fn wins(& self) -> usize {
+        self.wins.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    // Squares as (row, col) on the real board. A black pawn side is flipped
+    // so its pawn moves up the board.
+    pub fn probe(& self, strong_color : u8, strong_to_move : bool, strong_king : (usize, usize),
+                 weak_king : (usize, usize), pawn : (usize, usize)) -> bool {
+        let orient = |square : (usize, usize)| {
+            let row = if strong_color == board::WHITE { square.0 } else { 7 - square.0 };
+            let col = if pawn.1 >= 4 { 7 - square.1 } else { square.1 };
+            (row, col)
+        };
+        let position = index(strong_to_move, orient(strong_king), orient(weak_king), orient(pawn));
+        self.wins[position / 64] & (1 << (position % 64)) != 0
+    }
+}
+
+// Some(true) when the pawn side wins, Some(false) for a draw,
+// None when the board is not king and pawn against king
+pub fn probe_board(board : &board::ChessBoard) -> Option<bool> {
+    let mut pawn = None;
+    for row in 0..8 {
+        for col in 0..8 {
+            match board::clear_piece_color(board.board[row][col]) {
+                board::KING | 0b0000 => {},
+                board::PAWN if pawn.is_none() => pawn = Some(((row, col), board.board[row][col] & 0b1000)),
+                _ => return None,
+            }
+        }
+    }
+    let (square, color) = pawn?;
+    if square.0 == 0 || square.0 == 7 {
+        return None;
+    }
+    let (strong_king, weak_king) = if color == board::WHITE {
+        (board.white_king_pos, board.black_king_pos)
+    } else {
+        (board.black_king_pos, board.white_king_pos)
+    };
+    Some(bitbase().probe(color, board.protagonist == color, strong_king, weak_king, square))
+}
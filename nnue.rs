@@ -0,0 +1,242 @@
+use board;
+
+use std::fs;
+use std::sync::Arc;
+
+// Inputs are piece squares seen from each side's own king. The king's rank picks
+// one of KING_BUCKETS weight sets and a king on files e-h mirrors the board, so
+// every side always looks at the position as if its king stood on the a-d files.
+pub const KING_BUCKETS : usize = 4;
+const KING_BUCKET_BY_RANK : [usize; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+pub const INPUTS : usize = KING_BUCKETS * 12 * 64;
+pub const HIDDEN : usize = 64;
+
+// Quantization: feature weights are scaled by QA, output weights by QB,
+// and the network output times SCALE is centipawns
+pub const QA : i32 = 255;
+pub const QB : i32 = 64;
+pub const SCALE : i32 = 400;
+
+// Inputs on at once: one per piece on the board, and one more while make and
+// unmake set a moving piece's (or castling rook's) new square before clearing the old
+const MAX_ACTIVE : i32 = 33;
+
+const MAGIC : &[u8; 4] = b"RCNN";
+const VERSION : u32 = 1;
+
+pub struct Network {
+    pub feature_weights: Vec<i16>, // INPUTS rows of HIDDEN
+    pub feature_biases: Vec<i16>,
+    pub output_weights: Vec<i16>, // side to move's half first, then the other side's
+    pub output_bias: i32,
+}
+
+// Both sides' hidden layers before activation, index 0 for white.
+// transforms remembers each side's king bucket and mirroring they were built for.
+#[derive(Clone)]
+pub struct Accumulators {
+    network: Arc<Network>,
+    pub values: [[i16; HIDDEN]; 2],
+    transforms: [usize; 2],
+}
+
+fn side_index(color : u8) -> usize {
+    if color == board::WHITE { 0 } else { 1 }
+}
+
+fn piece_index(piece : u8) -> usize {
+    match board::clear_piece_color(piece) {
+        board::PAWN => 0,
+        board::KNIGHT => 1,
+        board::BISHOP => 2,
+        board::ROOK => 3,
+        board::QUEEN => 4,
+        board::KING => 5,
+        _ => unreachable!("empty square has no table"),
+    }
+}
+
+// Bucket * 2 + 1 when mirrored, from the king square of the side looking
+fn king_transform(color : u8, king : (usize, usize)) -> usize {
+    let rank = if color == board::WHITE { 7 - king.0 } else { king.0 };
+    let mirrored = if king.1 >= 4 { 1 } else { 0 };
+    KING_BUCKET_BY_RANK[rank] * 2 + mirrored
+}
+
+fn feature(color : u8, transform : usize, piece : u8, row : usize, col : usize) -> usize {
+    let rank = if color == board::WHITE { 7 - row } else { row };
+    let file = if transform % 2 == 1 { 7 - col } else { col };
+    let own = if piece & 0b1000 == color { 0 } else { 6 };
+    (transform / 2) * 768 + (own + piece_index(piece)) * 64 + rank * 8 + file
+}
+
+fn king_square(board : &board::ChessBoard, color : u8) -> (usize, usize) {
+    if color == board::WHITE { board.white_king_pos } else { board.black_king_pos }
+}
+
+// Every input that is on for the side looking at the board
+pub fn active_features(board : &board::ChessBoard, color : u8) -> Vec<usize> {
+    let transform = king_transform(color, king_square(board, color));
+    let mut features = Vec::with_capacity(32);
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if piece != 0b0000 {
+                features.push(feature(color, transform, piece, row, col));
+            }
+        }
+    }
+    features
+}
+
+impl Accumulators {
+    pub fn new(network : Arc<Network>, board : &board::ChessBoard) -> Accumulators {
+        let mut accumulators = Accumulators{ network, values: [[0; HIDDEN]; 2], transforms: [0, 0] };
+        accumulators.refresh(board, board::WHITE);
+        accumulators.refresh(board, board::BLACK);
+        accumulators
+    }
+
+    // Rebuild one side from the board
+    pub fn refresh(&mut self, board : &board::ChessBoard, color : u8) {
+        let side = side_index(color);
+        let mut values = [0i16; HIDDEN];
+        values.copy_from_slice(&self.network.feature_biases);
+        for feature in active_features(board, color) {
+            let row = &self.network.feature_weights[feature * HIDDEN..(feature + 1) * HIDDEN];
+            for index in 0..HIDDEN {
+                values[index] += row[index];
+            }
+        }
+        self.values[side] = values;
+        self.transforms[side] = king_transform(color, king_square(board, color));
+    }
+
+    // A side whose king changed bucket or wing needs every input recomputed
+    pub fn refresh_if_king_moved(&mut self, board : &board::ChessBoard) {
+        for color in [board::WHITE, board::BLACK].iter() {
+            if king_transform(*color, king_square(board, *color)) != self.transforms[side_index(*color)] {
+                self.refresh(board, *color);
+            }
+        }
+    }
+
+    fn update(&mut self, piece : u8, row : usize, col : usize, add : bool) {
+        for color in [board::WHITE, board::BLACK].iter() {
+            let side = side_index(*color);
+            let feature = feature(*color, self.transforms[side], piece, row, col);
+            let weights = &self.network.feature_weights[feature * HIDDEN..(feature + 1) * HIDDEN];
+            for (value, weight) in self.values[side].iter_mut().zip(weights) {
+                if add {
+                    *value += *weight;
+                } else {
+                    *value -= *weight;
+                }
+            }
+        }
+    }
+
+    // Called by the board for every square it changes
+    pub fn change_square(&mut self, row : usize, col : usize, old : u8, new : u8) {
+        if old != 0b0000 {
+            self.update(old, row, col, false);
+        }
+        if new != 0b0000 {
+            self.update(new, row, col, true);
+        }
+    }
+
+    // In centipawns from the side to move's point of view. In i64 as a loaded
+    // file may hold any output weights and bias.
+    pub fn evaluate(& self, side_to_move : u8) -> i32 {
+        let us = side_index(side_to_move);
+        let mut output = self.network.output_bias as i64;
+        for (half, side) in [us, 1 - us].iter().enumerate() {
+            let weights = &self.network.output_weights[half * HIDDEN..(half + 1) * HIDDEN];
+            for (value, weight) in self.values[*side].iter().zip(weights) {
+                let activation = (*value as i32).clamp(0, QA);
+                output += (activation * *weight as i32) as i64;
+            }
+        }
+        (output * SCALE as i64 / (QA * QB) as i64) as i32
+    }
+}
+
+fn read_u32(bytes : &[u8], offset : &mut usize) -> Option<u32> {
+    let value = bytes.get(*offset..*offset + 4)?;
+    *offset += 4;
+    Some(u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+fn read_i16s(bytes : &[u8], offset : &mut usize, count : usize) -> Option<Vec<i16>> {
+    let values = bytes.get(*offset..*offset + count * 2)?;
+    *offset += count * 2;
+    Some(values.chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+// "RCNN", version, input and hidden sizes as u32, then the feature weights,
+// feature biases and output weights as i16 and the output bias as i32,
+// all little endian
+impl Network {
+    pub fn to_bytes(& self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for value in [VERSION, INPUTS as u32, HIDDEN as u32].iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for values in [&self.feature_weights, &self.feature_biases, &self.output_weights].iter() {
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    // Whether every hidden value stays inside an i16, the bias plus the
+    // largest weights of a full board in the middle of a move
+    pub fn fits_accumulators(& self) -> bool {
+        (0..HIDDEN).all(|index| {
+            let largest = (0..INPUTS).map(|feature| (self.feature_weights[feature * HIDDEN + index] as i32).abs()).max().unwrap_or(0);
+            (self.feature_biases[index] as i32).abs() + MAX_ACTIVE * largest <= i16::MAX as i32
+        })
+    }
+
+    pub fn from_bytes(bytes : &[u8]) -> Result<Network, String> {
+        if bytes.len() < 4 || &bytes[..4] != MAGIC {
+            return Err("not a network file".to_string());
+        }
+        let mut offset = 4;
+        let header = (read_u32(bytes, &mut offset), read_u32(bytes, &mut offset), read_u32(bytes, &mut offset));
+        if header != (Some(VERSION), Some(INPUTS as u32), Some(HIDDEN as u32)) {
+            return Err(format!("expected version {} with {} inputs and {} hidden", VERSION, INPUTS, HIDDEN));
+        }
+        let feature_weights = read_i16s(bytes, &mut offset, INPUTS * HIDDEN);
+        let feature_biases = read_i16s(bytes, &mut offset, HIDDEN);
+        let output_weights = read_i16s(bytes, &mut offset, 2 * HIDDEN);
+        let output_bias = read_u32(bytes, &mut offset).map(|bias| bias as i32);
+        if offset != bytes.len() {
+            return Err("wrong file size".to_string());
+        }
+        match (feature_weights, feature_biases, output_weights, output_bias) {
+            (Some(feature_weights), Some(feature_biases), Some(output_weights), Some(output_bias)) => {
+                let network = Network{ feature_weights, feature_biases, output_weights, output_bias };
+                if !network.fits_accumulators() {
+                    return Err("weights too large for the accumulators".to_string());
+                }
+                Ok(network)
+            },
+            _ => Err("truncated file".to_string()),
+        }
+    }
+}
+
+pub fn load_network(path : &str) -> Result<Network, String> {
+    match fs::read(path) {
+        Ok(bytes) => Network::from_bytes(&bytes).map_err(|error| format!("{}: {}", path, error)),
+        Err(error) => Err(format!("{}: {}", path, error)),
+    }
+}
+
+pub fn save_network(path : &str, network : &Network) -> Result<(), String> {
+    fs::write(path, network.to_bytes()).map_err(|error| format!("{}: {}", path, error))
+}
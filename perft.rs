@@ -1,4 +1,18 @@
 mod board;
+mod san;
+mod epd;
+mod nnue;
+mod zobrist;
+
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
 
 pub fn print_moves(moves :& Vec<((usize, usize), (usize, usize, u8))>) {
     for amove in moves {
@@ -7,14 +21,13 @@ pub fn print_moves(moves :& Vec<((usize, usize), (usize, usize, u8))>) {
 }
 
 fn perft(board : &mut board::ChessBoard, perft_count :&mut u64, depth : u16) {
+    if depth == 0 {
+        *perft_count += 1;
+        return;
+    }
     let moves = board.get_all_moves();
-    let mut legal_moves = 0;
     for amove in moves {
-        let ep = board.ep;
-        let white_kingside_castle = board.white_kingside_castle;
-        let white_queenside_castle = board.white_queenside_castle;
-        let black_kingside_castle = board.black_kingside_castle;
-        let black_queenside_castle = board.black_queenside_castle;
+        let state = board.save_state();
 
         let result = board.make(amove.0, amove.1);
         match result {
@@ -22,7 +35,6 @@ fn perft(board : &mut board::ChessBoard, perft_count :&mut u64, depth : u16) {
                 // Illegal move, already unmade
             },
             Some(captured_piece) => {
-                legal_moves += 1;
                 if depth > 1 {
                     perft(board, perft_count, depth-1);
                 } else {
@@ -31,68 +43,449 @@ fn perft(board : &mut board::ChessBoard, perft_count :&mut u64, depth : u16) {
                 board.unmake(amove.0, amove.1, captured_piece);
             }
         }
-        board.ep = ep;
-        board.white_kingside_castle = white_kingside_castle;
-        board.white_queenside_castle = white_queenside_castle;
-        board.black_kingside_castle = black_kingside_castle;
-        board.black_queenside_castle = black_queenside_castle;
+        board.restore_state(state);
+    }
+}
 
-        // By move split
-        //if depth == 5 {
-        //    println!("{}, {}", board::square_to_alphanumeric(amove.0), board::square_to_alphanumeric((amove.1.0,amove.1.1)));
-        //    println!(": {}", perft_count); 
-        //}
+// Node count below each legal root move
+fn divide(board : &mut board::ChessBoard, depth : u16) -> Vec<(Move, u64)> {
+    let mut split = Vec::new();
+    for amove in board.get_legal_moves() {
+        let state = board.save_state();
+        let captured_piece = board.make(amove.0, amove.1).unwrap();
+        let mut perft_count = 0;
+        perft(board, &mut perft_count, depth - 1);
+        board.unmake(amove.0, amove.1, captured_piece);
+        board.restore_state(state);
+        split.push((amove, perft_count));
     }
-    //if legal_moves == 0  {// check or stalemate position
-    // These should are counted for perf at the n-1 depth
-    //    *perft_count += 1;
-    //}
+    split
 }
 
+// Subtree counts keyed by position hash and depth. Entries are stored as
+// (key ^ data, data) so a torn write from another thread fails the key check
+// instead of returning a wrong count.
+struct PerftTable {
+    entries: Vec<[AtomicU64; 2]>,
+    mask: usize,
+}
+
+fn new_perft_table(megabytes : usize) -> PerftTable {
+    let mut size = 1;
+    while size * 2 * 16 <= megabytes * 1024 * 1024 {
+        size *= 2;
+    }
+    let mut entries = Vec::with_capacity(size);
+    for _ in 0..size {
+        entries.push([AtomicU64::new(0), AtomicU64::new(0)]);
+    }
+    PerftTable{ entries, mask: size - 1 }
+}
+
+impl PerftTable {
+    fn probe(& self, key : u64, depth : u16) -> Option<u64> {
+        let entry = &self.entries[key as usize & self.mask];
+        let check = entry[0].load(Ordering::Relaxed);
+        let data = entry[1].load(Ordering::Relaxed);
+        if check ^ data == key && (data & 0xFF) as u16 == depth {
+            Some(data >> 8)
+        } else {
+            None
+        }
+    }
+
+    fn store(& self, key : u64, depth : u16, count : u64) {
+        let entry = &self.entries[key as usize & self.mask];
+        let data = (count << 8) | depth as u64;
+        entry[0].store(key ^ data, Ordering::Relaxed);
+        entry[1].store(data, Ordering::Relaxed);
+    }
+}
+
+fn perft_hashed(board : &mut board::ChessBoard, table : &PerftTable, depth : u16) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    // Counting the moves of a leaf parent is cheaper than hashing it
+    let key = if depth > 1 { zobrist::hash(board) } else { 0 };
+    if depth > 1 {
+        if let Some(count) = table.probe(key, depth) {
+            return count;
+        }
+    }
 
-fn main() {
     let mut perft_count = 0;
+    let moves = board.get_all_moves();
+    for amove in moves {
+        let state = board.save_state();
+        match board.make(amove.0, amove.1) {
+            None => {
+                // Illegal move, already unmade
+            },
+            Some(captured_piece) => {
+                perft_count += perft_hashed(board, table, depth - 1);
+                board.unmake(amove.0, amove.1, captured_piece);
+            }
+        }
+        board.restore_state(state);
+    }
+
+    if depth > 1 {
+        table.store(key, depth, perft_count);
+    }
+    perft_count
+}
+
+// Root moves are handed out to the threads one at a time, each thread works on
+// its own copy of the board. Without a table it counts exactly like perft().
+fn divide_parallel(board : &mut board::ChessBoard, depth : u16, threads : usize, table : Option<&PerftTable>) -> Vec<(Move, u64)> {
+    let root_moves = board.get_legal_moves();
+    let next_move = AtomicUsize::new(0);
+    let mut counts = vec![0u64; root_moves.len()];
+
+    thread::scope(|scope| {
+        let mut handles = Vec::new();
+        for _ in 0..threads.max(1) {
+            let mut thread_board = board.clone();
+            let root_moves = &root_moves;
+            let next_move = &next_move;
+            handles.push(scope.spawn(move || {
+                let mut results = Vec::new();
+                loop {
+                    let index = next_move.fetch_add(1, Ordering::Relaxed);
+                    if index >= root_moves.len() {
+                        break;
+                    }
+                    let amove = root_moves[index];
+                    let state = thread_board.save_state();
+                    let captured_piece = thread_board.make(amove.0, amove.1).unwrap();
+                    let count = match table {
+                        Some(table) => perft_hashed(&mut thread_board, table, depth - 1),
+                        None => {
+                            let mut perft_count = 0;
+                            perft(&mut thread_board, &mut perft_count, depth - 1);
+                            perft_count
+                        }
+                    };
+                    thread_board.unmake(amove.0, amove.1, captured_piece);
+                    thread_board.restore_state(state);
+                    results.push((index, count));
+                }
+                results
+            }));
+        }
+        for handle in handles {
+            for (index, count) in handle.join().unwrap() {
+                counts[index] = count;
+            }
+        }
+    });
+
+    root_moves.into_iter().zip(counts).collect()
+}
+
+struct PerftOptions {
+    threads: usize,
+    hash_megabytes: usize,
+}
+
+fn run_split(board : &mut board::ChessBoard, depth : u16, options : &PerftOptions, table : &Option<PerftTable>) -> Vec<(Move, u64)> {
+    if options.threads <= 1 && table.is_none() {
+        divide(board, depth)
+    } else {
+        divide_parallel(board, depth, options.threads, table.as_ref())
+    }
+}
+
+fn make_table(options : &PerftOptions) -> Option<PerftTable> {
+    if options.hash_megabytes > 0 {
+        Some(new_perft_table(options.hash_megabytes))
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PerftStats {
+    nodes: u64,
+    captures: u64,
+    en_passant: u64,
+    castles: u64,
+    promotions: u64,
+    checks: u64,
+    discovered_checks: u64,
+    double_checks: u64,
+    checkmates: u64,
+}
+
+// Fills stats[ply] with the counters of the moves made at that ply, so one run
+// gives the whole reference table up to the depth
+fn perft_stats(board : &mut board::ChessBoard, stats : &mut Vec<PerftStats>, ply : usize, depth : u16) {
+    let moves = board.get_all_moves();
+    for amove in moves {
+        let state = board.save_state();
+        let source = amove.0;
+        let dest = amove.1;
+        let piece = board::clear_piece_color(board.board[source.0][source.1]);
+        let is_en_passant = piece == board::PAWN && source.1 != dest.1 && board.board[dest.0][dest.1] == 0b0000;
+        let is_castle = piece == board::KING && source.1.abs_diff(dest.1) > 1;
+
+        let result = board.make(source, dest);
+        match result {
+            None => {
+                // Illegal move, already unmade
+            },
+            Some(captured_piece) => {
+                let counters = &mut stats[ply];
+                counters.nodes += 1;
+                if captured_piece != 0b0000 || is_en_passant {
+                    counters.captures += 1;
+                }
+                if is_en_passant {
+                    counters.en_passant += 1;
+                }
+                if is_castle {
+                    counters.castles += 1;
+                }
+                if dest.2 != 0 {
+                    counters.promotions += 1;
+                }
+
+                let king_pos = if board.protagonist == board::WHITE {
+                    board.white_king_pos
+                } else {
+                    board.black_king_pos
+                };
+                let checkers = board.attackers(king_pos);
+                if !checkers.is_empty() {
+                    counters.checks += 1;
+                    // As in the reference tables a double check is not also counted as a
+                    // discovery, and the castling rook counts as the moved piece
+                    let rook_square = if is_castle {
+                        (dest.0, (source.1 + dest.1) / 2)
+                    } else {
+                        (dest.0, dest.1)
+                    };
+                    if checkers.len() > 1 {
+                        counters.double_checks += 1;
+                    } else if checkers[0] != (dest.0, dest.1) && checkers[0] != rook_square {
+                        counters.discovered_checks += 1;
+                    }
+                    if board.get_legal_moves().is_empty() {
+                        counters.checkmates += 1;
+                    }
+                }
+
+                if depth > 1 {
+                    perft_stats(board, stats, ply + 1, depth - 1);
+                }
+                board.unmake(source, dest, captured_piece);
+            }
+        }
+        board.restore_state(state);
+    }
+}
 
+fn run_stats(fen : &str, depth : u16) {
     let stuff = [[0u8; 8]; 8];
     let mut board = board::build_board(stuff);
+    board.from_fen(fen);
+    println!("{}", board);
+
+    let start = Instant::now();
+    let mut stats = vec![PerftStats::default(); depth as usize];
+    perft_stats(&mut board, &mut stats, 0, depth);
+
+    println!("{:>5} {:>12} {:>10} {:>8} {:>8} {:>10} {:>10} {:>9} {:>8} {:>8}",
+             "Depth", "Nodes", "Captures", "E.p.", "Castles", "Promotions", "Checks", "Discovery", "Double", "Mates");
+    for (ply, counters) in stats.iter().enumerate() {
+        println!("{:>5} {:>12} {:>10} {:>8} {:>8} {:>10} {:>10} {:>9} {:>8} {:>8}",
+                 ply + 1, counters.nodes, counters.captures, counters.en_passant, counters.castles, counters.promotions,
+                 counters.checks, counters.discovered_checks, counters.double_checks, counters.checkmates);
+    }
+    print_timing(stats.iter().map(|counters| counters.nodes).sum(), start);
+}
+
+fn print_timing(nodes : u64, start : Instant) {
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("Time: {:.3}s", elapsed);
+    if elapsed > 0.0 {
+        println!("NPS: {:.0}", nodes as f64 / elapsed);
+    }
+}
 
-    let position_3 = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - ";
-    board.from_fen(position_3);
-    perft(&mut board, &mut perft_count, 4);
-    assert!(perft_count == 43238, "Pos 3 Failed");
-    perft_count = 0;
-
-    let position_6 = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
-    board.from_fen(position_6);
-    perft(&mut board, &mut perft_count, 4);
-    assert!(perft_count == 3894594, "Pos 6 Failed");
-    perft_count = 0;
-
-    let position_1 = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-    board.from_fen(position_1);
-    perft(&mut board, &mut perft_count, 5);
-    assert!(perft_count == 4865609, "Pos 1 Failed");
-    perft_count = 0;
-
-    let position_2 = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -";
-    board.from_fen(position_2);
-    perft(&mut board, &mut perft_count, 4);
-    assert!(perft_count == 4085603, "Pos 2 Failed");
-    perft_count = 0;
-
-    let position_4 = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
-    board.from_fen(position_4);
-    perft(&mut board, &mut perft_count, 4);
-    assert!(perft_count == 422333, "Pos 4 Failed");
-    perft_count = 0;
-
-    let position_5 = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
-    board.from_fen(position_5);
-    perft(&mut board, &mut perft_count, 4);
-    assert!(perft_count == 2103487, "Pos 4 Failed");
-    perft_count = 0;
+fn run_divide(fen : &str, depth : u16, options : &PerftOptions) {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    board.from_fen(fen);
+    println!("{}", board);
+
+    let table = make_table(options);
+    let start = Instant::now();
+    let mut total = 0;
+    for (amove, count) in run_split(&mut board, depth, options, &table) {
+        println!("{}: {}", board::move_to_string(amove), count);
+        total += count;
+    }
+    println!("\nNodes: {}", total);
+    print_timing(total, start);
+}
+
+// Each line is a FEN followed by "D<depth> <count>" operations, e.g.
+// rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400 ;D3 8902
+fn run_suite(path : &str, max_depth : u16, options : &PerftOptions) -> bool {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            println!("Could not read {}: {}", path, error);
+            return false;
+        }
+    };
+
+    let table = make_table(options);
+    let start = Instant::now();
+    let mut total = 0;
+    let mut failures = 0;
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parsed = match epd::parse_epd(line) {
+            Some(parsed) => parsed,
+            None => {
+                println!("Line {}: could not parse \"{}\"", line_number + 1, line);
+                failures += 1;
+                continue;
+            }
+        };
+        let name = match parsed.id() {
+            Some(id) => id.to_string(),
+            None => parsed.board.to_fen(),
+        };
+        for depth in 1..=max_depth {
+            let expected = match parsed.perft_count(depth) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let perft_count : u64 = run_split(&mut parsed.board, depth, options, &table).iter().map(|split| split.1).sum();
+            total += perft_count;
+            if perft_count == expected {
+                println!("Line {} depth {}: {} OK", line_number + 1, depth, perft_count);
+            } else {
+                println!("Line {} depth {}: expected {} got {} for {}", line_number + 1, depth, expected, perft_count, name);
+                failures += 1;
+                // Deeper counts will be off as well
+                break;
+            }
+        }
+    }
+    println!("\nNodes: {}", total);
+    print_timing(total, start);
+    if failures > 0 {
+        println!("{} FAILED", failures);
+    }
+    failures == 0
+}
+
+fn run_builtin() {
+    let positions = [
+        ("Pos 3", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - ", 4, 43238),
+        ("Pos 6", "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 4, 3894594),
+        ("Pos 1", board::START_FEN, 5, 4865609),
+        ("Pos 2", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -", 4, 4085603),
+        ("Pos 4", "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 4, 422333),
+        ("Pos 5", "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 4, 2103487),
+    ];
+
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let start = Instant::now();
+    let mut total = 0;
+    for (name, fen, depth, expected) in positions.iter() {
+        let mut perft_count = 0;
+        board.from_fen(fen);
+        perft(&mut board, &mut perft_count, *depth);
+        assert!(perft_count == *expected, "{} Failed", name);
+        total += perft_count;
+    }
 
     print!("{}\n", board);
+    print_timing(total, start);
+
+    // The hashed, threaded variant has to agree with perft() exactly
+    let threads = thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+    let table = new_perft_table(64);
+    let start = Instant::now();
+    for (name, fen, depth, expected) in positions.iter() {
+        board.from_fen(fen);
+        let perft_count : u64 = divide_parallel(&mut board, *depth, threads, Some(&table)).iter().map(|split| split.1).sum();
+        assert!(perft_count == *expected, "{} Failed hashed with {} threads", name, threads);
+    }
+    println!("Hashed, {} threads", threads);
+    print_timing(total, start);
     print!("\nALL GOOD\n");
 }
 
+fn usage() -> ! {
+    println!("usage: perft                          run the built-in positions");
+    println!("       perft <depth> [fen]            divide by root move (start position by default)");
+    println!("       perft --suite <file> [depth]   check every D<n> count up to depth (default 6)");
+    println!("       perft --stats <depth> [fen]    captures, checks, mates, ... per depth");
+    println!("options for divide and suites: --threads <n> --hash <megabytes>");
+    process::exit(2);
+}
+
+fn main() {
+    let mut args : Vec<String> = Vec::new();
+    let mut options = PerftOptions{ threads: 1, hash_megabytes: 0 };
+    let mut arg_iter = env::args();
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--threads" {
+            options.threads = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--hash" {
+            options.hash_megabytes = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else {
+            args.push(arg);
+        }
+    }
+
+    if args.len() == 1 {
+        run_builtin();
+    } else if args[1] == "--suite" {
+        if args.len() < 3 {
+            usage();
+        }
+        let max_depth = match args.get(3) {
+            Some(depth) => depth.parse::<u16>().unwrap_or_else(|_| usage()),
+            None => 6,
+        };
+        if !run_suite(&args[2], max_depth, &options) {
+            process::exit(1);
+        }
+    } else if args[1] == "--stats" {
+        if args.len() < 3 {
+            usage();
+        }
+        let depth = args[2].parse::<u16>().unwrap_or_else(|_| usage());
+        if depth == 0 {
+            usage();
+        }
+        let fen = if args.len() > 3 {
+            args[3..].join(" ")
+        } else {
+            board::START_FEN.to_string()
+        };
+        run_stats(&fen, depth);
+    } else {
+        let depth = args[1].parse::<u16>().unwrap_or_else(|_| usage());
+        if depth == 0 {
+            usage();
+        }
+        let fen = if args.len() > 2 {
+            args[2..].join(" ")
+        } else {
+            board::START_FEN.to_string()
+        };
+        run_divide(&fen, depth, &options);
+    }
+}
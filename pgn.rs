@@ -0,0 +1,289 @@
+use board;
+use eval;
+use game;
+
+const LINE_WIDTH : usize = 79;
+
+const SEVEN_TAG_ROSTER : [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+fn escape_tag_value(value : &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Pawns with two decimals, or #N / #-N for a mate in N moves
+pub fn format_eval(centipawns : i32) -> String {
+    match eval::mate_moves(centipawns) {
+        Some(moves) => format!("#{}", moves),
+        None => format!("{:.2}", centipawns as f64 / 100.0),
+    }
+}
+
+pub fn format_clock(millis : u64) -> String {
+    let seconds = millis / 1000;
+    format!("{}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+fn move_comment(recorded : &game::RecordedMove) -> Option<String> {
+    let mut parts : Vec<String> = Vec::new();
+    if let Some(eval) = recorded.eval {
+        parts.push(format!("[%eval {}]", format_eval(eval)));
+    }
+    if let Some(clock) = recorded.clock {
+        parts.push(format!("[%clk {}]", format_clock(clock)));
+    }
+    if let Some(ref text) = recorded.comment {
+        // Braces would end the comment early
+        parts.push(text.replace('{', "(").replace('}', ")"));
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!("{{{}}}", parts.join(" ")))
+    }
+}
+
+fn push_wrapped(text : &mut String, line_len : &mut usize, token : &str) {
+    for word in token.split_whitespace() {
+        if *line_len > 0 && *line_len + 1 + word.len() > LINE_WIDTH {
+            text.push('\n');
+            *line_len = 0;
+        } else if *line_len > 0 {
+            text.push(' ');
+            *line_len += 1;
+        }
+        text.push_str(word);
+        *line_len += word.len();
+    }
+}
+
+pub fn to_pgn(game : &game::GameRecord) -> String {
+    let mut text = String::new();
+
+    for (name, default) in SEVEN_TAG_ROSTER.iter() {
+        let value = if *name == "Result" {
+            game.result.as_str()
+        } else {
+            game.get_tag(name).unwrap_or(default)
+        };
+        text.push_str(&format!("[{} \"{}\"]\n", name, escape_tag_value(value)));
+    }
+
+    let stuff = [[0u8; 8]; 8];
+    let mut start = board::build_board(stuff);
+    start.from_fen(board::START_FEN);
+    let standard_start = game.start_fen == start.to_fen();
+
+    for tag in &game.tags {
+        let name = tag.0.as_str();
+        if SEVEN_TAG_ROSTER.iter().any(|roster| roster.0 == name) || name == "SetUp" || name == "FEN" {
+            continue;
+        }
+        text.push_str(&format!("[{} \"{}\"]\n", name, escape_tag_value(&tag.1)));
+    }
+    if !standard_start {
+        text.push_str("[SetUp \"1\"]\n");
+        text.push_str(&format!("[FEN \"{}\"]\n", game.start_fen));
+    }
+    text.push('\n');
+
+    start.from_fen(&game.start_fen);
+    let mut move_number = start.fullmove_number;
+    let mut white_to_move = start.protagonist == board::WHITE;
+    let mut need_number = true;
+    let mut line_len = 0;
+
+    for recorded in &game.moves {
+        if white_to_move {
+            push_wrapped(&mut text, &mut line_len, &format!("{}.", move_number));
+        } else if need_number {
+            push_wrapped(&mut text, &mut line_len, &format!("{}...", move_number));
+        }
+        push_wrapped(&mut text, &mut line_len, &recorded.san);
+
+        need_number = false;
+        if let Some(comment) = move_comment(recorded) {
+            push_wrapped(&mut text, &mut line_len, &comment);
+            need_number = true;
+        }
+
+        if !white_to_move {
+            move_number += 1;
+        }
+        white_to_move = !white_to_move;
+    }
+    push_wrapped(&mut text, &mut line_len, &game.result);
+    text.push('\n');
+    text
+}
+
+const RESULTS : [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+// Name and value of a tag pair, the text between its brackets
+fn parse_tag(text : &str) -> Option<(String, String)> {
+    let text = text.trim();
+    let split = text.find(char::is_whitespace)?;
+    let value = text[split..].trim();
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return None;
+    }
+    let value = value[1..value.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\");
+    Some((text[..split].to_string(), value))
+}
+
+fn finish_game(tags : &mut Vec<(String, String)>, tokens : &mut Vec<String>, games : &mut Vec<game::GameRecord>, skipped : &mut usize) {
+    if tags.is_empty() && tokens.is_empty() {
+        return;
+    }
+    let mut record = match tags.iter().find(|tag| tag.0 == "FEN") {
+        Some(tag) => game::game_from_fen(&tag.1),
+        None => game::new_game(),
+    };
+    let mut legal = true;
+    for token in tokens.iter() {
+        if RESULTS.contains(&token.as_str()) {
+            record.result = token.clone();
+        } else if !record.play_san(token) {
+            legal = false;
+            break;
+        }
+    }
+    for (name, value) in tags.iter() {
+        if name == "Result" {
+            if tokens.iter().all(|token| !RESULTS.contains(&token.as_str())) {
+                record.result = value.clone();
+            }
+        } else if name != "SetUp" && name != "FEN" {
+            record.set_tag(name, value);
+        }
+    }
+    if legal {
+        games.push(record);
+    } else {
+        *skipped += 1;
+    }
+    tags.clear();
+    tokens.clear();
+}
+
+// Moves of a movetext token, without its move number: "12.", "12..." and
+// "12.e4" all start with one, castling with zeros does not
+fn strip_move_number(token : &str) -> &str {
+    if RESULTS.contains(&token) || token.starts_with("0-0") {
+        return token;
+    }
+    token.trim_start_matches(|c : char| c.is_ascii_digit()).trim_start_matches('.')
+}
+
+fn push_token(token : &mut String, tokens : &mut Vec<String>) {
+    let stripped = strip_move_number(token);
+    if !stripped.is_empty() {
+        tokens.push(stripped.to_string());
+    }
+    token.clear();
+}
+
+// Games of a PGN file and the number skipped for an illegal or unreadable
+// move. Comments, variations and NAGs are dropped.
+pub fn parse_pgn(text : &str) -> (Vec<game::GameRecord>, usize) {
+    let mut games = Vec::new();
+    let mut skipped = 0;
+    let mut tags : Vec<(String, String)> = Vec::new();
+    let mut tokens : Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        let at_line_start = line_start;
+        line_start = c == '\n';
+        let separator = c.is_whitespace() || c == '[' || c == '{' || c == ';' || c == '(' || c == '$' || (c == '%' && at_line_start);
+        if separator {
+            push_token(&mut token, &mut tokens);
+        }
+        match c {
+            '[' => {
+                // A tag after movetext starts the next game
+                if !tokens.is_empty() {
+                    finish_game(&mut tags, &mut tokens, &mut games, &mut skipped);
+                }
+                let mut inside = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '\\' {
+                        inside.push(c);
+                        if let Some(escaped) = chars.next() {
+                            inside.push(escaped);
+                        }
+                    } else if c == ']' {
+                        break;
+                    } else {
+                        inside.push(c);
+                    }
+                }
+                if let Some(tag) = parse_tag(&inside) {
+                    tags.push(tag);
+                }
+            },
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            },
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                }
+            },
+            '%' if at_line_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                }
+            },
+            '(' => {
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        '{' => {
+                            for c in chars.by_ref() {
+                                if c == '}' {
+                                    break;
+                                }
+                            }
+                        },
+                        _ => {},
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            },
+            '$' => {
+                while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    chars.next();
+                }
+            },
+            _ if c.is_whitespace() => {},
+            _ => token.push(c),
+        }
+    }
+    push_token(&mut token, &mut tokens);
+    finish_game(&mut tags, &mut tokens, &mut games, &mut skipped);
+    (games, skipped)
+}
@@ -1,7 +1,66 @@
 mod board;
+mod clock;
+mod san;
+mod syzygy;
+mod syzygy_writer;
+mod game;
+mod pgn;
+mod epd;
+mod endgame;
+mod eval;
+mod kpk;
+mod nnue;
+mod polyglot;
+mod retrograde;
+mod search;
+mod tablebase;
+mod transposition;
+mod trainer;
+mod tuner;
+mod uci;
+mod zobrist;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
 
 fn main() {
     _test_checks();
+    _test_fen_round_trip();
+    _test_san();
+    _test_pgn_export();
+    _test_pgn_import();
+    _test_epd();
+    _test_eval();
+    _test_pawn_structure();
+    _test_king_safety();
+    _test_piece_activity();
+    _test_eval_trace();
+    _test_eval_params();
+    _test_mate_scores();
+    _test_tuner();
+    _test_nnue();
+    _test_endgames();
+    _test_kpk();
+    _test_tablebase();
+    _test_syzygy();
+    _test_polyglot();
+    _test_null_move();
+    _test_search();
+    _test_mate_search();
+    _test_transposition();
+    _test_parallel_search();
+    _test_clock();
+    _test_ponder();
+    _test_draws();
+    _test_uci();
 }
 
 pub fn print_moves(moves :& Vec<((usize, usize), (usize, usize, u8))>) {
@@ -106,6 +165,12 @@ fn _test_checks() {
     let a_pos = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
     board3.from_fen(a_pos);
     assert!(board3.in_check((2,1))==true, "b6 should be in check");
+
+    // Squares next to the enemy king
+    board3.from_fen("8/8/8/8/8/8/8/K1k5 w - - 0 1");
+    assert!(board3.in_check((7,1)), "b1 should be in check");
+    assert!(board3.in_check((6,1)), "b2 should be in check");
+    assert!(!board3.in_check((6,0)), "a2 should not be in check");
 }
 
 fn _king_castles() {
@@ -307,3 +372,1465 @@ fn _test_fen() {
     println!("Test Position");
     println!("{}", board3);
 }
+
+fn _test_fen_round_trip() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    let fens = [
+        board::START_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    for fen in fens.iter() {
+        board3.from_fen(fen);
+        assert!(board3.to_fen() == *fen, "FEN did not round trip");
+    }
+
+    board3.from_fen(board::START_FEN);
+    board3.make((6,4), (4,4,0));
+    assert!(board3.to_fen() == "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    board3.make((0,6), (2,5,0));
+    assert!(board3.to_fen() == "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2");
+}
+
+fn _test_san() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    board3.from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    assert!(san::move_to_san(&mut board3, ((7,4),(7,6,0))) == "O-O");
+    assert!(san::move_to_san(&mut board3, ((7,4),(7,2,0))) == "O-O-O");
+    assert!(san::move_to_san(&mut board3, ((3,4),(1,5,0))) == "Nxf7");
+    assert!(san::move_to_san(&mut board3, ((6,6),(5,7,0))) == "gxh3");
+    assert!(san::move_to_san(&mut board3, ((5,2),(4,1,0))) == "Nxb4");
+    assert!(san::move_to_san(&mut board3, ((7,0),(7,1,0))) == "Rb1");
+    assert!(san::san_to_move(&mut board3, "Nxf7") == Some(((3,4),(1,5,0))));
+    assert!(san::san_to_move(&mut board3, "O-O-O") == Some(((7,4),(7,2,0))));
+    assert!(san::san_to_move(&mut board3, "Qxf6") == Some(((5,5),(2,5,0))));
+    assert!(san::san_to_move(&mut board3, "Ke3").is_none(), "Illegal move parsed");
+
+    // Two knights reaching the same square
+    board3.from_fen("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1");
+    assert!(san::move_to_san(&mut board3, ((7,1),(6,3,0))) == "Nbd2");
+    assert!(san::san_to_move(&mut board3, "Nfd2") == Some(((7,5),(6,3,0))));
+    assert!(san::san_to_move(&mut board3, "Nd2").is_none(), "Ambiguous move parsed");
+
+    // Promotion with check and en passant
+    board3.from_fen("3k4/1P6/8/3pP3/8/8/8/4K3 w - d6 0 1");
+    assert!(san::move_to_san(&mut board3, ((1,1),(0,1,board::QUEEN))) == "b8=Q+");
+    assert!(san::move_to_san(&mut board3, ((3,4),(2,3,0))) == "exd6");
+    assert!(san::san_to_move(&mut board3, "b8N") == Some(((1,1),(0,1,board::KNIGHT))));
+
+    board3.from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+    assert!(san::move_to_san(&mut board3, ((7,0),(0,0,0))) == "Ra8#");
+}
+
+fn _test_pgn_export() {
+    let mut record = game::new_game();
+    record.set_tag("Event", "Self-play");
+    record.set_tag("White", "RustChess");
+    record.set_tag("Black", "RustChess");
+    record.set_tag("TimeControl", "60+1");
+    for san in ["f3", "e5", "g4", "Qh4#"].iter() {
+        assert!(record.play_san(san), "Could not play {}", san);
+    }
+    record.moves[1].eval = Some(35);
+    record.moves[1].clock = Some(59500);
+    assert!(record.result == "0-1");
+
+    let expected = "[Event \"Self-play\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+[White \"RustChess\"]\n[Black \"RustChess\"]\n[Result \"0-1\"]\n[TimeControl \"60+1\"]\n\n\
+1. f3 e5 {[%eval 0.35] [%clk 0:00:59]} 2. g4 Qh4# 0-1\n";
+    assert!(pgn::to_pgn(&record) == expected, "Unexpected PGN:\n{}", pgn::to_pgn(&record));
+
+    // Non-standard start with black to move and a comment after white's move
+    let mut record = game::game_from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 40");
+    assert!(record.play_san("Kd7"));
+    assert!(record.play_san("e4"));
+    record.annotate(Some(-120), None, Some("only move"));
+    assert!(record.play_san("Ke6"));
+    let text = pgn::to_pgn(&record);
+    assert!(text.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n"));
+    assert!(text.ends_with("\n\n40... Kd7 41. e4 {[%eval -1.20] only move} 41... Ke6 *\n"), "Unexpected PGN:\n{}", text);
+
+    // Long games wrap below 80 columns
+    let mut record = game::new_game();
+    for _ in 0..20 {
+        for san in ["Nf3", "Nf6", "Ng1", "Ng8"].iter() {
+            assert!(record.play_san(san));
+        }
+    }
+    for line in pgn::to_pgn(&record).lines() {
+        assert!(line.len() < 80, "Line too long: {}", line);
+    }
+}
+
+fn _test_pgn_import() {
+    // What to_pgn writes reads back the same
+    let mut record = game::new_game();
+    record.set_tag("Event", "Self-play");
+    for san in ["e4", "e5", "Nf3", "Nc6", "Bc4", "Nf6", "O-O", "Bc5"].iter() {
+        assert!(record.play_san(san), "Could not play {}", san);
+    }
+    record.result = "1/2-1/2".to_string();
+    let (games, skipped) = pgn::parse_pgn(&pgn::to_pgn(&record));
+    assert!(games.len() == 1 && skipped == 0);
+    assert!(pgn::to_pgn(&games[0]) == pgn::to_pgn(&record), "Unexpected PGN:\n{}", pgn::to_pgn(&games[0]));
+    assert!(games[0].get_tag("Event") == Some("Self-play"));
+    assert!(games[0].result == "1/2-1/2");
+
+    // Comments, variations, NAGs, escape lines and a second game from a FEN
+    let text = "[Event \"Club \\\"open\\\"\"]\n[Result \"1-0\"]\n\n\
+1.e4 $1 {best by test} e5 (1... c5 2. Nf3 (2. c3) d6) 2. Nf3!? ; a comment\n\
+2... Nc6 3.Bb5 1-0\n\n\
+% an escaped line\n\
+[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n[SetUp \"1\"]\n\n40... Kd7 41. e4 *\n\n\
+[Event \"Broken\"]\n\n1. e4 e5 2. Ke3 *\n";
+    let (games, skipped) = pgn::parse_pgn(text);
+    assert!(games.len() == 2 && skipped == 1, "{} games, {} skipped", games.len(), skipped);
+    let sans : Vec<&str> = games[0].moves.iter().map(|recorded| recorded.san.as_str()).collect();
+    assert!(sans == ["e4", "e5", "Nf3", "Nc6", "Bb5"], "{:?}", sans);
+    assert!(games[0].get_tag("Event") == Some("Club \"open\""));
+    assert!(games[0].result == "1-0");
+    assert!(games[1].start_fen == "4k3/8/8/8/8/8/4P3/4K3 b - - 0 40");
+    assert!(games[1].moves.len() == 2 && games[1].get_tag("FEN").is_none());
+}
+
+fn _test_epd() {
+    let wac = "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";";
+    let parsed = epd::parse_epd(wac).expect("WAC.001 did not parse");
+    assert!(parsed.id() == Some("WAC.001"));
+    assert!(parsed.best_moves() == vec![((5,6),(2,6,0))]);
+    assert!(parsed.avoid_moves().is_empty());
+    assert!(parsed.to_epd() == "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";");
+
+    let several = "r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - am Nxc6 Nb5; bm Qd2 Nb3; c0 \"quote \\\" inside\"; acd 12; ce -35;";
+    let parsed = epd::parse_epd(several).expect("Operation list did not parse");
+    assert!(parsed.avoid_moves() == vec![((4,3),(2,2,0)), ((4,3),(3,1,0))]);
+    assert!(parsed.best_moves().len() == 2);
+    assert!(parsed.comment(0) == Some("quote \" inside"));
+    assert!(parsed.integer("acd") == Some(12));
+    assert!(parsed.integer("ce") == Some(-35));
+    assert!(epd::parse_epd(&parsed.to_epd()).unwrap().to_epd() == parsed.to_epd());
+
+    // Perft suite lines carry the FEN clocks and depth counts
+    let perft_line = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1 ;D1 48 ;D2 2039 ;D3 97862";
+    let parsed = epd::parse_epd(perft_line).expect("Perft line did not parse");
+    assert!(parsed.perft_count(2) == Some(2039));
+    assert!(parsed.perft_count(4).is_none());
+    assert!(parsed.board.fullmove_number == 1);
+
+    let with_clocks = "4k3/8/8/8/8/8/4P3/4K3 w - - hmvc 7; fmvn 33;";
+    let parsed = epd::parse_epd(with_clocks).unwrap();
+    assert!(parsed.board.to_fen() == "4k3/8/8/8/8/8/4P3/4K3 w - - 7 33");
+
+    assert!(epd::parse_epd("4k3/8/8/8/8/8/4P3/4K3 w - - bm Kd2;").is_some());
+    assert!(epd::parse_epd("4k3/8/8/8/8/8/4P3/4K3 w - - bm Kxe8;").is_none(), "Illegal bm accepted");
+    assert!(epd::parse_epd("4k3/8/8/8/8/8/4P3/4K3 w").is_none(), "Short EPD accepted");
+
+    let mut written = epd::epd_from_board(&parsed.board);
+    written.set_operation("id", vec![epd::EpdOperand::Text("pawn".to_string())]);
+    written.set_operation("bm", vec![epd::EpdOperand::Move(((6,4),(4,4,0)))]);
+    assert!(written.to_epd() == "4k3/8/8/8/8/8/4P3/4K3 w - - id \"pawn\"; bm e4;");
+}
+
+// Same position with colors swapped and the board flipped
+fn _mirror_fen(fen : &str) -> String {
+    let fields : Vec<&str> = fen.split(' ').collect();
+    let rows : Vec<String> = fields[0].split('/').rev().map(|row| row.chars().map(|c| {
+        if c.is_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }
+    }).collect()).collect();
+    let side = if fields[1] == "w" { "b" } else { "w" };
+    let castling : String = fields[2].chars().map(|c| {
+        if c.is_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }
+    }).collect();
+    format!("{} {} {} -", rows.join("/"), side, castling)
+}
+
+fn _test_eval() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    board3.from_fen(board::START_FEN);
+    assert!(eval::eval(&mut board3) == 0, "Start position should be level");
+    assert!(eval::game_phase(&board3) == eval::MAX_PHASE);
+
+    // Central pawns and developed knights are worth more than rim moves
+    board3.from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+    let after_e4 = -eval::eval(&mut board3);
+    board3.from_fen("rnbqkbnr/pppppppp/8/8/8/P7/1PPPPPPP/RNBQKBNR b KQkq - 0 1");
+    let after_a3 = -eval::eval(&mut board3);
+    assert!(after_e4 > after_a3, "e4 should beat a3");
+    board3.from_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+    let after_nf3 = -eval::eval(&mut board3);
+    board3.from_fen("rnbqkbnr/pppppppp/8/8/8/7N/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+    let after_nh3 = -eval::eval(&mut board3);
+    assert!(after_nf3 > after_nh3, "Nf3 should beat Nh3");
+
+    // The king belongs in the centre once the pieces are gone
+    board3.from_fen("8/7p/8/3k4/8/8/7P/K7 w - - 0 1");
+    assert!(eval::game_phase(&board3) == 0);
+    assert!(eval::eval(&mut board3) < 0, "Central king should be better in the endgame");
+
+    // Side to move relative and color symmetric
+    let positions = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    for fen in positions.iter() {
+        board3.from_fen(fen);
+        let score = eval::eval(&mut board3);
+        board3.from_fen(&_mirror_fen(fen));
+        assert!(eval::eval(&mut board3) == score, "Mirrored eval differs for {}", fen);
+    }
+}
+
+fn _test_pawn_structure() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    // Same material, white's pawns doubled and isolated against a healthy chain
+    board3.from_fen("4k3/ppp5/8/8/8/8/P1P5/2P1K3 w - - 0 1");
+    let broken = eval::eval(&mut board3);
+    board3.from_fen("4k3/ppp5/8/8/8/8/PPP5/4K3 w - - 0 1");
+    let healthy = eval::eval(&mut board3);
+    assert!(broken < healthy, "Doubled isolated pawns should cost");
+
+    // A free passer is worth more than a blocked one
+    board3.from_fen("4k3/p7/8/3P4/8/8/P7/4K3 w - - 0 1");
+    let free = eval::eval(&mut board3);
+    board3.from_fen("4k3/p7/3n4/3P4/8/8/P7/4KN2 w - - 0 1");
+    let blocked = eval::eval(&mut board3);
+    board3.from_fen("4k3/p7/8/3n4/3P4/8/P7/4KN2 w - - 0 1");
+    let blocked_lower = eval::eval(&mut board3);
+    assert!(free > 0);
+    assert!(blocked < free && blocked_lower < blocked, "Passer should be scored by rank and blockade");
+
+    // Connected passers beat split ones on the same ranks
+    board3.from_fen("4k3/8/8/2PP4/8/8/8/4K3 w - - 0 1");
+    let connected = eval::eval(&mut board3);
+    board3.from_fen("4k3/8/8/1P2P3/8/8/8/4K3 w - - 0 1");
+    let split = eval::eval(&mut board3);
+    assert!(connected > split, "Connected passers should get a bonus");
+
+    // Cached and fresh pawn entries agree
+    board3.from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let first = eval::eval(&mut board3);
+    assert!(eval::eval(&mut board3) == first);
+    assert!(zobrist::pawn_hash(&board3) != 0);
+    board3.from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    assert!(zobrist::pawn_hash(&board3) == 0);
+}
+
+fn _test_king_safety() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    board3.from_fen("4k3/8/8/8/8/8/8/8 w - - 0 1");
+    let mut attacked = board3.attacks_from((0,4));
+    attacked.sort();
+    assert!(attacked == vec![(0,3), (0,5), (1,3), (1,4), (1,5)]);
+    board3.from_fen("8/8/8/8/3p4/8/1R1Q4/8 w - - 0 1");
+    assert!(board3.attacks_from((6,3)).len() == 18, "Queen should see up to the pawn and her own rook");
+    assert!(board3.attacks_from((4,3)) == vec![(5,2), (5,4)]);
+
+    // Castled king behind its pawns against the same king with the shield pushed
+    board3.from_fen("r4rk1/ppp2ppp/8/8/8/8/PPP2PPP/R4RK1 w - - 0 1");
+    let sheltered = eval::eval(&mut board3);
+    board3.from_fen("r4rk1/ppp2ppp/8/8/8/5PPP/PPP5/R4RK1 w - - 0 1");
+    let exposed = eval::eval(&mut board3);
+    assert!(sheltered == 0 && exposed < sheltered, "Pushed shield should cost");
+
+    // Pieces swarming the king zone
+    board3.from_fen("r4rk1/ppp2ppp/8/8/8/8/PPP2PPP/R4RK1 w - - 0 1");
+    let quiet = eval::eval(&mut board3);
+    board3.from_fen("r5k1/ppp2ppp/8/8/4q3/5n2/PPP2PPP/R4RK1 w - - 0 1");
+    let attacked = eval::eval(&mut board3);
+    board3.from_fen("r5k1/ppp2ppp/4q3/8/8/2n5/PPP2PPP/R4RK1 w - - 0 1");
+    let distant = eval::eval(&mut board3);
+    assert!(attacked < distant, "Attack on the king zone should count");
+    assert!(quiet > distant);
+
+    // In a pawn endgame an active king is better than a sheltered one
+    board3.from_fen("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+    let passive = eval::eval(&mut board3);
+    board3.from_fen("6k1/5ppp/8/8/4K3/8/5PPP/8 w - - 0 1");
+    let active = eval::eval(&mut board3);
+    assert!(active > passive, "Central king should be preferred in the endgame");
+}
+
+fn _test_piece_activity() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    // Bishop pair against bishop and knight
+    board3.from_fen("4k3/pppppppp/8/8/8/8/PPPPPPPP/2B1KB2 w - - 0 1");
+    let pair = eval::eval(&mut board3);
+    board3.from_fen("4k3/pppppppp/8/8/8/8/PPPPPPPP/2B1KN2 w - - 0 1");
+    let no_pair = eval::eval(&mut board3);
+    assert!(pair > no_pair, "Bishop pair should count");
+
+    // Rook on the open file rather than behind its own pawn
+    board3.from_fen("4k3/pp3ppp/8/8/8/8/PP3PPP/3RK3 w - - 0 1");
+    let open = eval::eval(&mut board3);
+    board3.from_fen("4k3/pp3ppp/8/8/8/8/PP3PPP/R3K3 w - - 0 1");
+    let closed = eval::eval(&mut board3);
+    assert!(open > closed, "Rook should like the open file");
+
+    // Knight outpost on d5 supported by e4, compared with one c6 can chase away
+    board3.from_fen("4k3/pp3ppp/8/3N4/4P3/8/PP3PPP/4K3 w - - 0 1");
+    let outpost = eval::eval(&mut board3);
+    board3.from_fen("4k3/pp3ppp/2p5/3N4/4P3/8/PP3PPP/4K3 w - - 0 1");
+    let chased = eval::eval(&mut board3);
+    board3.from_fen("4k3/pp3ppp/2p5/8/4P3/8/PP3PPP/4K3 w - - 0 1");
+    let pawn_up_no_knight = eval::eval(&mut board3);
+    board3.from_fen("4k3/pp3ppp/8/8/4P3/8/PP3PPP/4K3 w - - 0 1");
+    let no_knight = eval::eval(&mut board3);
+    assert!(outpost - no_knight > chased - pawn_up_no_knight, "Outpost should be worth more");
+
+    // Trapped bishop on a7
+    board3.from_fen("4k3/B1p2ppp/1p6/8/8/8/5PPP/4K3 w - - 0 1");
+    let trapped = eval::eval(&mut board3);
+    board3.from_fen("4k3/2p2ppp/1p6/8/8/4B3/5PPP/4K3 w - - 0 1");
+    let free = eval::eval(&mut board3);
+    assert!(trapped < free - 50, "Bishop on a7 should be trapped");
+
+    // More room for the pieces is better
+    board3.from_fen("4k3/pppppppp/8/8/8/8/PPPPPPPP/R3K3 w - - 0 1");
+    let boxed = eval::eval(&mut board3);
+    board3.from_fen("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1");
+    let no_rook = eval::eval(&mut board3);
+    board3.from_fen("4k3/pppppppp/8/8/3R4/8/PPPPPPPP/4K3 w - - 0 1");
+    let central = eval::eval(&mut board3);
+    assert!(central > boxed && boxed > no_rook, "Mobility should favour the active rook");
+}
+
+fn _test_eval_trace() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    let positions = [
+        board::START_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R b KQ - 1 8",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    for fen in positions.iter() {
+        board3.from_fen(fen);
+        let trace = eval::eval_trace(&board3);
+        let side_to_move_score = if board3.protagonist == board::WHITE { trace.score } else { -trace.score };
+        assert!(side_to_move_score == eval::eval(&mut board3), "Trace disagrees with eval for {}", fen);
+        assert!(trace.phase == eval::game_phase(&board3));
+        assert!(trace.terms.iter().any(|term| term.0 == "Mobility"));
+    }
+
+    board3.from_fen(board::START_FEN);
+    let trace = eval::eval_trace(&board3);
+    let material = trace.terms.iter().find(|term| term.0 == "Material").unwrap();
+    assert!(material.1.mg == 4050 && material.2.eg == 4190);
+    let table = format!("{}", trace);
+    assert!(table.contains("King safety") && table.contains("Phase: 24/24"));
+}
+
+fn _test_eval_params() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    let text = eval::DEFAULT_PARAMS.to_text();
+    assert!(text.contains("\nknight_value.mg 320\n"));
+    assert!(text.contains("\npassed_pawn.eg.6 120\n"));
+    let mut loaded = eval::params_from_text(&text).unwrap();
+    assert!(loaded.to_text() == text, "Parameters should survive a round trip");
+
+    loaded = eval::params_from_text("# only the knight\nknight_value.mg 400 # more\n\n").unwrap();
+    assert!(loaded.knight_value == (400, 300) && loaded.queen_value == eval::DEFAULT_PARAMS.queen_value);
+    assert!(eval::params_from_text("knight_value.mg").is_err());
+    assert!(eval::params_from_text("knight_value.mg lots").is_err());
+    assert!(eval::params_from_text("knight_valu.mg 400").is_err());
+
+    // eval_with and the global parameters agree
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    board3.from_fen(fen);
+    let default_score = eval::eval(&mut board3);
+    assert!(eval::eval_with(&eval::DEFAULT_PARAMS, &board3) == default_score);
+
+    // A pawn structure change takes effect despite the cached pawn entries
+    board3.from_fen("4k3/pp6/8/8/8/2P5/2P5/4K3 w - - 0 1");
+    let default_score = eval::eval(&mut board3);
+    let mut params = eval::DEFAULT_PARAMS;
+    params.doubled_pawn = (-100, -100);
+    let changed = eval::eval_with(&params, &board3);
+    assert!(changed != default_score);
+    let snapshot = eval::params_snapshot();
+    eval::set_params(params);
+    assert!(eval::eval(&mut board3) == changed);
+    // A snapshot keeps the parameters it was taken with
+    assert!(eval::eval_snapshot(&snapshot, &mut board3) == default_score);
+    eval::set_params(eval::DEFAULT_PARAMS);
+    assert!(eval::eval(&mut board3) == default_score);
+}
+
+fn _test_mate_scores() {
+    assert!(eval::mate_in(1) > eval::mate_in(3));
+    assert!(eval::mated_in(2) < eval::mated_in(4));
+    assert!(eval::is_mate_score(eval::mate_in(5)) && eval::is_mate_score(eval::mated_in(6)));
+    assert!(!eval::is_mate_score(900));
+
+    // Plies to full moves, for the side to move
+    assert!(eval::mate_moves(eval::mate_in(1)) == Some(1));
+    assert!(eval::mate_moves(eval::mate_in(5)) == Some(3));
+    assert!(eval::mate_moves(eval::mated_in(2)) == Some(-1));
+    assert!(eval::mate_moves(eval::mated_in(6)) == Some(-3));
+    assert!(eval::mate_moves(250).is_none());
+
+    assert!(eval::score_to_uci(35) == "cp 35");
+    assert!(eval::score_to_uci(-120) == "cp -120");
+    assert!(eval::score_to_uci(eval::mate_in(3)) == "mate 2");
+    assert!(eval::score_to_uci(eval::mated_in(4)) == "mate -2");
+    assert!(pgn::format_eval(eval::mate_in(1)) == "#1");
+    assert!(pgn::format_eval(eval::mated_in(2)) == "#-1");
+    assert!(pgn::format_eval(-35) == "-0.35");
+}
+
+fn _test_tuner() {
+    let position = tuner::parse_labelled_position("4k3/pppp4/8/8/8/8/PPPP4/1N2K3 w - - 0 1 1-0").unwrap();
+    assert!(position.result == 1.0 && position.board.board[7][1] == board::KNIGHT | board::WHITE);
+    let position = tuner::parse_labelled_position("4k3/pppp4/8/8/8/8/PPPP4/4K3 b - - c9 \"1/2-1/2\";").unwrap();
+    assert!(position.result == 0.5 && position.board.protagonist == board::BLACK);
+    assert!(tuner::parse_labelled_position("4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1").is_none(), "Clocks are not a result");
+
+    let text = "# comment\n\
+                4k3/8/8/8/8/8/8/1N2K3 w - - 0-1\n\
+                4k3/8/8/8/8/8/8/4K2r w - - 0 1 0-1\n\
+                not a position\n";
+    let (positions, skipped) = tuner::parse_labelled_positions(text);
+    assert!(positions.len() == 1 && skipped == 2, "In check positions are not quiet");
+
+    assert!(tuner::sigmoid(1.0, 0) == 0.5);
+    assert!(tuner::sigmoid(1.0, 400) > 0.9 && tuner::sigmoid(1.0, -400) < 0.1);
+
+    // Won games a knight up and drawn games a pawn up pull the knight up and the pawn down
+    let mut text = String::new();
+    for _ in 0..4 {
+        text.push_str("4k3/pppp4/8/8/8/8/PPPP4/1N2K3 w - - 0 1 1-0\n");
+        text.push_str("4k3/ppp5/8/8/8/8/PPPP4/4K3 w - - 0 1 1/2-1/2\n");
+    }
+    let (positions, _) = tuner::parse_labelled_positions(&text);
+    let options = tuner::TuneOptions{ k: 1.0, step: 10, max_passes: 3, only: vec!["knight_value".to_string(), "pawn_value".to_string()] };
+    let before = tuner::mean_error(&eval::DEFAULT_PARAMS, &positions, 1.0);
+    let mut passes = 0;
+    let tuned = tuner::local_search(&eval::DEFAULT_PARAMS, &positions, &options, |_, _, _| passes += 1);
+    assert!(passes == 3);
+    assert!(tuner::mean_error(&tuned, &positions, 1.0) < before);
+    assert!(tuned.knight_value.1 > eval::DEFAULT_PARAMS.knight_value.1);
+    assert!(tuned.pawn_value.1 < eval::DEFAULT_PARAMS.pawn_value.1);
+    assert!(tuned.rook_value == eval::DEFAULT_PARAMS.rook_value);
+}
+
+fn _check_accumulators(board3 : &mut board::ChessBoard, depth : u16) {
+    let mut fresh = board3.clone();
+    fresh.refresh_nnue();
+    assert!(board3.nnue.as_ref().unwrap().values == fresh.nnue.unwrap().values, "Stale accumulators in {}", board3.to_fen());
+    if depth == 0 {
+        return;
+    }
+    for amove in board3.get_all_moves() {
+        let state = board3.save_state();
+        if let Some(captured_piece) = board3.make(amove.0, amove.1) {
+            _check_accumulators(board3, depth - 1);
+            board3.unmake(amove.0, amove.1, captured_piece);
+        }
+        board3.restore_state(state);
+    }
+}
+
+fn _test_nnue() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+    let network = Arc::new(trainer::random_network(7).quantize());
+    board3.attach_network(network.clone());
+
+    // Castling, en passant, promotions and king moves across buckets
+    let positions = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    for fen in positions.iter() {
+        board3.from_fen(fen);
+        _check_accumulators(&mut board3, 2);
+    }
+    board3.from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
+    _check_accumulators(&mut board3, 4);
+
+    // The network stands in for the hand-written evaluation only where it is attached
+    board3.from_fen(board::START_FEN);
+    let network_score = eval::eval(&mut board3);
+    assert!(network_score == board3.nnue.as_ref().unwrap().evaluate(board::WHITE));
+    let mut plain = board::build_board(stuff);
+    plain.from_fen(board::START_FEN);
+    assert!(eval::eval(&mut plain) == 0);
+
+    let bytes = network.to_bytes();
+    let loaded = nnue::Network::from_bytes(&bytes).unwrap();
+    assert!(loaded.to_bytes() == bytes);
+    assert!(nnue::Network::from_bytes(&bytes[..bytes.len() - 2]).is_err());
+    assert!(nnue::Network::from_bytes(b"not a network").is_err());
+    // A bias that a full board of large weights would overflow
+    let mut overflowing = trainer::random_network(7).quantize();
+    overflowing.feature_weights[0] = 1000;
+    overflowing.feature_biases[0] = 1000;
+    assert!(!overflowing.fits_accumulators());
+    assert!(nnue::Network::from_bytes(&overflowing.to_bytes()).is_err());
+    // Enough for 32 pieces, but a move briefly has 33 inputs on
+    overflowing.feature_weights[0] = 1000;
+    overflowing.feature_biases[0] = 767;
+    assert!(!overflowing.fits_accumulators());
+    // Output weights and bias at their limits, as a loaded file may have them
+    let mut extreme = trainer::random_network(7).quantize();
+    extreme.output_weights = vec![i16::MAX; 2 * nnue::HIDDEN];
+    extreme.output_bias = i32::MAX;
+    plain.attach_network(Arc::new(nnue::Network::from_bytes(&extreme.to_bytes()).unwrap()));
+    assert!(plain.nnue.as_ref().unwrap().evaluate(board::WHITE) > 0);
+
+    // A few epochs on a knight-up-wins set bring the error down
+    let mut text = String::new();
+    for _ in 0..8 {
+        text.push_str("4k3/pppp4/8/8/8/8/PPPP4/1N2K3 w - - 0 1 1-0\n");
+        text.push_str("1n2k3/pppp4/8/8/8/8/PPPP4/4K3 b - - 0 1 0-1\n");
+        text.push_str("4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1 1/2-1/2\n");
+    }
+    let (positions, _) = tuner::parse_labelled_positions(&text);
+    let samples = trainer::samples(&positions);
+    let mut float_network = trainer::random_network(3);
+    let before = float_network.mean_error(&samples);
+    let options = trainer::TrainOptions{ epochs: 40, batch_size: 4, learning_rate: 0.001, seed: 5 };
+    let mut epochs = 0;
+    trainer::train(&mut float_network, &samples, &options, |_, _, _| epochs += 1);
+    assert!(epochs == 40);
+    assert!(float_network.mean_error(&samples) < before / 2.0, "Training should fit a tiny set");
+
+    board3.attach_network(Arc::new(float_network.quantize()));
+    board3.from_fen("4k3/pppp4/8/8/8/8/PPPP4/1N2K3 w - - 0 1");
+    assert!(eval::eval(&mut board3) > 50, "Trained network should like the extra knight");
+}
+
+fn _test_endgames() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    // Lone kings are driven to the edge and the stronger side keeps its winning score
+    board3.from_fen("8/8/8/3k4/8/8/8/R6K w - - 0 1");
+    let centre = eval::eval(&mut board3);
+    board3.from_fen("3k4/8/8/8/7K/8/8/R7 w - - 0 1");
+    let edge = eval::eval(&mut board3);
+    board3.from_fen("3k4/8/3K4/8/8/8/8/R7 w - - 0 1");
+    let close = eval::eval(&mut board3);
+    assert!(centre > endgame::KNOWN_WIN && edge > centre && close > edge, "KRK should push the king to the edge");
+    assert!(!eval::is_mate_score(close));
+    assert!(eval::eval_trace(&board3).endgame == Some("KRK"));
+
+    // KBNK mates only in a corner of the bishop's color, a1 and h8 for a dark bishop
+    board3.from_fen("k7/8/2K5/8/8/8/8/3NB3 w - - 0 1");
+    let dark_bishop_wrong_corner = eval::eval(&mut board3);
+    board3.from_fen("7k/8/5K2/8/8/8/8/3NB3 w - - 0 1");
+    let dark_bishop_right_corner = eval::eval(&mut board3);
+    assert!(dark_bishop_right_corner > dark_bishop_wrong_corner, "KBNK should aim for the bishop's corner");
+    board3.from_fen("k7/8/2K5/8/8/8/8/3NB3 b - - 0 1");
+    assert!(eval::eval_trace(&board3).endgame == Some("KBNK"));
+
+    // Drawn material
+    let draws = [
+        "8/8/3k4/8/8/3K4/8/8 w - - 0 1",
+        "8/8/3k4/8/8/3KN3/8/8 w - - 0 1",
+        "8/8/3k4/8/8/3KB3/8/8 b - - 0 1",
+        "8/8/3k4/8/8/2NKN3/8/8 w - - 0 1",
+        "8/8/3k4/8/8/3K4/4B3/3B4 w - - 0 1", // both bishops on light squares
+        "k7/8/8/8/8/8/P7/1B2K3 w - - 0 1", // light bishop, a8 is light
+        "7k/8/8/8/8/8/7P/2B1K3 w - - 0 1", // dark bishop, h8 is dark
+        "8/8/8/8/8/8/p7/K3kb2 b - - 0 1", // light bishop, a1 is dark
+    ];
+    let expected_draw = [true, true, true, true, true, false, false, true];
+    for (fen, draw) in draws.iter().zip(expected_draw.iter()) {
+        board3.from_fen(fen);
+        assert!((eval::eval(&mut board3) == 0) == *draw, "Draw recognition wrong for {}", fen);
+    }
+    // Wrong bishop: h8 is dark and the bishop is light
+    board3.from_fen("7k/8/8/8/8/8/7P/3BK3 w - - 0 1");
+    assert!(eval::eval(&mut board3) == 0);
+    board3.from_fen("8/8/8/8/8/8/p7/1K2kb2 b - - 0 1");
+    assert!(eval::eval(&mut board3) == 0, "Wrong bishop with the king next to the corner");
+    board3.from_fen("8/8/8/8/8/8/p7/1K2k1b1 b - - 0 1");
+    assert!(eval::eval(&mut board3) > 0);
+
+    // Pawnless edges of less than a rook are drawish, opposite bishops scale down
+    board3.from_fen("8/8/3k4/3n4/8/3K4/3R4/8 w - - 0 1");
+    assert!(eval::eval(&mut board3) < 60, "KRKN is drawish");
+    board3.from_fen("8/8/8/3k4/8/8/2r5/KQ6 w - - 0 1");
+    assert!(eval::eval(&mut board3) > 300 && eval::eval_trace(&board3).endgame.is_none(), "KQKR is a win");
+    board3.from_fen("8/4kp2/6p1/1p5p/1P1b3P/6P1/2B2PK1/8 w - - 0 1");
+    let opposite = eval::eval_trace(&board3);
+    board3.from_fen("8/4kp2/6p1/1p5p/1P5P/3b2P1/2B2PK1/8 w - - 0 1");
+    let same = eval::eval_trace(&board3);
+    assert!(opposite.endgame == Some("Opposite bishops") && same.endgame.is_none());
+
+    // Mirrored endgames score the same for the other side
+    let positions = [
+        "8/8/8/3k4/8/8/8/R3K3 w - - 0 1",
+        "k7/8/2K5/8/8/8/8/3NB3 b - - 0 1",
+        "8/8/3k4/3n4/8/3K4/3R4/8 w - - 0 1",
+        "8/8/8/3k4/8/8/2r5/KQ6 w - - 0 1",
+        "8/4kp2/6p1/1p5p/1P1b3P/6P1/2B2PK1/8 b - - 0 1",
+    ];
+    for fen in positions.iter() {
+        board3.from_fen(fen);
+        let score = eval::eval(&mut board3);
+        board3.from_fen(&_mirror_fen(fen));
+        assert!(eval::eval(&mut board3) == score, "Mirrored endgame differs for {}", fen);
+    }
+}
+
+fn _test_kpk() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+
+    let known = [
+        ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", true), // king on the sixth in front of its pawn
+        ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", true),
+        ("4k3/4P3/4K3/8/8/8/8/8 w - - 0 1", true),
+        ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", false), // stalemate
+        ("k7/8/1K6/P7/8/8/8/8 w - - 0 1", false), // rook pawn
+        ("7k/8/6K1/7P/8/8/8/8 w - - 0 1", false),
+        ("8/8/8/8/8/8/4P3/4K2k w - - 0 1", true), // outside the square
+        ("7k/8/8/8/8/8/P7/K7 b - - 0 1", true),
+        ("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1", false),
+        ("8/8/8/8/8/4k3/4P3/4K3 b - - 0 1", false),
+        ("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1", true), // black pawn
+        ("8/8/8/8/4p3/4k3/8/4K3 w - - 0 1", true),
+        ("8/8/8/8/8/1k6/p7/K7 w - - 0 1", false),
+    ];
+    for (fen, win) in known.iter() {
+        board3.from_fen(fen);
+        assert!(kpk::probe_board(&board3) == Some(*win), "KPK wrong for {}", fen);
+        board3.from_fen(&_mirror_fen(fen));
+        assert!(kpk::probe_board(&board3) == Some(*win), "KPK wrong for the mirror of {}", fen);
+    }
+    board3.from_fen(board::START_FEN);
+    assert!(kpk::probe_board(&board3).is_none());
+    board3.from_fen("4k3/8/4K3/4P3/8/8/8/7N w - - 0 1");
+    assert!(kpk::probe_board(&board3).is_none());
+
+    // The evaluation knows which are won
+    board3.from_fen("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1");
+    assert!(eval::eval(&mut board3) < -endgame::KNOWN_WIN);
+    board3.from_fen("k7/8/1K6/P7/8/8/8/8 w - - 0 1");
+    assert!(eval::eval(&mut board3) == 0);
+    board3.from_fen("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1");
+    assert!(eval::eval(&mut board3) > endgame::KNOWN_WIN && eval::eval_trace(&board3).endgame == Some("KPK"));
+}
+
+fn _test_tablebase() {
+    assert!(tablebase::parse_signature("kkq").unwrap().0 == "KQK");
+    assert!(tablebase::parse_signature("KNKR").unwrap().0 == "KRKN");
+    assert!(tablebase::parse_signature("KPK").is_err());
+    assert!(tablebase::parse_signature("KQRKR").is_err());
+    assert!(tablebase::dependencies("KQKR").unwrap() == vec!["KRK".to_string(), "KQK".to_string()]);
+    assert!(tablebase::dependencies("KQK").unwrap().is_empty());
+
+    let mut tables = tablebase::Tablebases::new();
+    assert!(tablebase::generate("KRKN", &tables).is_err());
+    let table = tablebase::generate("KQK", &tables).unwrap();
+    assert!(table.longest_mate().unwrap().0 == 19); // mate in ten moves at most
+    assert!(table.counts(true) == (table.counts(true).0, 0, 0)); // the queen always wins with the move
+    let bytes = table.to_bytes();
+    assert!(tablebase::Tablebase::from_bytes(&bytes).unwrap().to_bytes() == bytes);
+    assert!(tablebase::Tablebase::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    tables.insert(table);
+
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+    let known = [
+        ("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", tablebase::Probe::Win(1)),
+        ("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1", tablebase::Probe::Loss(0)),
+        ("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", tablebase::Probe::Draw), // stalemate
+        ("k7/1Q6/8/8/8/8/8/K7 b - - 0 1", tablebase::Probe::Draw), // the queen is lost
+        ("8/8/8/3k4/8/8/8/KQ6 w - - 0 1", tablebase::Probe::Win(17)),
+    ];
+    for (fen, result) in known.iter() {
+        board3.from_fen(fen);
+        assert!(tables.probe(&board3) == Some(*result), "wrong table result for {}", fen);
+        board3.from_fen(&_mirror_fen(fen));
+        assert!(tables.probe(&board3) == Some(*result), "wrong table result for the mirror of {}", fen);
+    }
+    board3.from_fen("8/8/8/3k4/8/8/8/KR6 w - - 0 1");
+    assert!(tables.probe(&board3).is_none());
+    board3.from_fen("8/8/8/8/8/8/8/K6k w - - 0 1");
+    assert!(tables.probe(&board3) == Some(tablebase::Probe::Draw));
+
+    // Best moves keep to the shortest mate
+    board3.from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+    let (amove, result) = tables.best_move(&mut board3).unwrap();
+    assert!(board::move_to_string(amove) == "g1g8" && result == tablebase::Probe::Win(1));
+    board3.from_fen("8/8/8/3k4/8/8/8/KQ6 w - - 0 1");
+    let (amove, result) = tables.best_move(&mut board3).unwrap();
+    assert!(result == tablebase::Probe::Win(17));
+    let state = board3.save_state();
+    let captured_piece = board3.make(amove.0, amove.1).unwrap();
+    assert!(tables.probe(&board3) == Some(tablebase::Probe::Loss(16)));
+    board3.unmake(amove.0, amove.1, captured_piece);
+    board3.restore_state(state);
+
+    // Scores for the search count plies from the root
+    tablebase::set_tablebases(tables);
+    board3.from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+    assert!(tablebase::probe_score(&board3, 0) == Some(eval::mate_in(1)));
+    assert!(tablebase::probe_score(&board3, 4) == Some(eval::mate_in(5)));
+    board3.from_fen("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1");
+    assert!(tablebase::probe_score(&board3, 3) == Some(eval::mated_in(3)));
+
+    // The search only looks at the root move the tables pick
+    board3.from_fen("8/8/8/3k4/8/8/8/KQ6 w - - 0 1");
+    let (amove, _) = tablebase::probe_root(&mut board3).unwrap();
+    let mut limits = search::default_limits();
+    limits.depth = 1;
+    limits.multipv = 3;
+    let result = search::search(&mut board3, &limits, &search::default_options(), &AtomicBool::new(false), |_| {});
+    assert!(result.lines.len() == 1 && result.best_move() == Some(amove));
+}
+
+// Written by tbgen --syzygy KQvK KRvK KPvK KPvKP and, for the tables their
+// probes reach, --syzygy --wdl KBvK KNvK KQvKP KRvKP KBvKP KNvKP. Built in, so
+// the tests find them from any directory.
+const _SYZYGY_FIXTURES : [(&str, &[u8]); 14] = [
+    ("KBvK.rtbw", include_bytes!("fixtures/syzygy/KBvK.rtbw")),
+    ("KBvKP.rtbw", include_bytes!("fixtures/syzygy/KBvKP.rtbw")),
+    ("KNvK.rtbw", include_bytes!("fixtures/syzygy/KNvK.rtbw")),
+    ("KNvKP.rtbw", include_bytes!("fixtures/syzygy/KNvKP.rtbw")),
+    ("KPvK.rtbw", include_bytes!("fixtures/syzygy/KPvK.rtbw")),
+    ("KPvK.rtbz", include_bytes!("fixtures/syzygy/KPvK.rtbz")),
+    ("KPvKP.rtbw", include_bytes!("fixtures/syzygy/KPvKP.rtbw")),
+    ("KPvKP.rtbz", include_bytes!("fixtures/syzygy/KPvKP.rtbz")),
+    ("KQvK.rtbw", include_bytes!("fixtures/syzygy/KQvK.rtbw")),
+    ("KQvK.rtbz", include_bytes!("fixtures/syzygy/KQvK.rtbz")),
+    ("KQvKP.rtbw", include_bytes!("fixtures/syzygy/KQvKP.rtbw")),
+    ("KRvK.rtbw", include_bytes!("fixtures/syzygy/KRvK.rtbw")),
+    ("KRvK.rtbz", include_bytes!("fixtures/syzygy/KRvK.rtbz")),
+    ("KRvKP.rtbw", include_bytes!("fixtures/syzygy/KRvKP.rtbw")),
+];
+
+// A fresh directory for tables under the system's temporary one
+fn _syzygy_dir(name : &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("syzygy-{}-{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// The fixtures are checked against the KQK table _test_tablebase generates.
+// With SYZYGY_PATH set to official 3 and 4 piece tables the results that
+// follow from the rules alone are checked against those too.
+fn _test_syzygy() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+    board3.from_fen("8/8/8/3k4/8/8/8/KQ6 w - - 0 1");
+    assert!(syzygy::table_name(&board3) == "KQvK");
+    board3.from_fen("8/8/8/3k4/8/2b5/8/KR6 w - - 0 1");
+    assert!(syzygy::table_name(&board3) == "KRvKB");
+
+    let fixtures = _syzygy_dir("fixtures");
+    assert!(syzygy::set_path(&fixtures.to_string_lossy()) == 0);
+    assert!(syzygy::max_pieces() == 0);
+    assert!(syzygy::probe_wdl(&mut board3).is_none());
+    for (name, bytes) in _SYZYGY_FIXTURES.iter() {
+        fs::write(fixtures.join(name), bytes).unwrap();
+    }
+    assert!(syzygy::set_path(&fixtures.to_string_lossy()) == 10);
+    assert!(syzygy::max_pieces() == 4);
+    _check_syzygy_results(&mut board3);
+
+    board3.from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+    assert!(syzygy::probe_dtz(&mut board3) == Some(1));
+    let best = syzygy::filter_root_moves(&mut board3).unwrap();
+    assert!(best.len() == 1 && board::move_to_string(best[0]) == "g1g8");
+    // DTZ tables store white to move, black's values come from white's replies:
+    // Kb8 is forced and Qg8 mates
+    board3.from_fen("k7/8/1K6/8/8/8/8/6Q1 b - - 0 1");
+    assert!(syzygy::probe_dtz(&mut board3) == Some(-2));
+    board3.from_fen("8/8/8/8/8/2K5/1P6/1k6 w - - 0 1");
+    assert!(syzygy::probe_dtz(&mut board3) == Some(1)); // the pawn move keeps the win
+
+    // Each result and distance follows from the best move's one ply on. KPvKP
+    // is symmetric, so black to move is read from white's side of the table.
+    for file in 0..8 {
+        for (white_pawn, black_pawn, side) in [(6, 4, "w"), (6, 4, "b"), (3, 1, "w"), (3, 1, "b"), (2, 1, "w"), (4, 6, "b")].iter() {
+            let mut rows = [['1'; 8]; 8];
+            rows[*white_pawn][file] = 'P';
+            rows[*black_pawn][(file + 1) % 8] = 'p';
+            rows[7][(file + 4) % 8] = 'K';
+            rows[0][(file + 3) % 8] = 'k';
+            let placement : Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+            board3.from_fen(&format!("{} {} - - 0 1", placement.join("/"), side));
+            _check_syzygy_ply(&mut board3);
+        }
+        let mut rows = [['1'; 8]; 8];
+        rows[5][file] = 'P';
+        rows[7][(file + 2) % 8] = 'K';
+        rows[2][(file + 5) % 8] = 'k';
+        let placement : Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+        for side in ["w", "b"].iter() {
+            board3.from_fen(&format!("{} {} - - 0 1", placement.join("/"), side));
+            _check_syzygy_ply(&mut board3);
+        }
+    }
+
+    // KRvK again with every result past the fifty move rule: wins cursed and
+    // losses blessed, a hundred plies further from zeroing
+    let positions = ["8/8/8/4k3/8/8/8/R3K3 w - - 0 1", "8/8/3k4/8/8/8/8/R3K3 b - - 0 1"];
+    let mut fifty_move_wins = Vec::new();
+    for fen in positions.iter() {
+        board3.from_fen(fen);
+        let root_moves = syzygy::root_moves(&mut board3).unwrap();
+        assert!(root_moves.iter().all(|root| root.rank <= 0 || root.rank == syzygy::MAX_DTZ));
+        fifty_move_wins.push((syzygy::probe_dtz(&mut board3).unwrap(), syzygy::filter_root_moves(&mut board3).unwrap()));
+    }
+    let mut solver = retrograde::Solver::new();
+    solver.solve("KRvK").unwrap();
+    let cursed = _syzygy_dir("cursed");
+    for (extension, dtz) in [("rtbw", false), ("rtbz", true)].iter() {
+        let value = |squares : &[usize], black_to_move : bool| {
+            solver.value("KRvK", squares, black_to_move).map(|value| if *dtz { value + 100 * value.signum() } else { value.signum() })
+        };
+        fs::write(cursed.join(format!("KRvK.{}", extension)), syzygy_writer::write_table("KRvK", *dtz, &value).unwrap()).unwrap();
+    }
+    assert!(syzygy::set_path(&cursed.to_string_lossy()) == 1);
+    for (fen, (dtz, best)) in positions.iter().zip(fifty_move_wins.iter()) {
+        board3.from_fen(fen);
+        let (wdl, score) = if *dtz > 0 { (syzygy::Wdl::CursedWin, 1) } else { (syzygy::Wdl::BlessedLoss, -1) };
+        assert!(syzygy::probe_wdl(&mut board3) == Some(wdl) && syzygy::probe_score(&mut board3, 0) == Some(score), "{}", fen);
+        assert!(syzygy::probe_dtz(&mut board3) == Some(dtz + 100 * dtz.signum()), "{}", fen);
+        // Cursed wins rank below every win inside the rule and blessed losses
+        // above every loss, by distance, so only the longest of the losses stay
+        let root_moves = syzygy::root_moves(&mut board3).unwrap();
+        assert!(root_moves.iter().all(|root| root.rank.abs() < syzygy::MAX_DTZ), "{}", fen);
+        let filtered = syzygy::filter_root_moves(&mut board3).unwrap();
+        assert!(if *dtz > 0 { filtered == *best } else { !filtered.is_empty() && filtered.len() < best.len() && filtered.iter().all(|amove| best.contains(amove)) }, "{}", fen);
+    }
+
+    // Official tables
+    if let Ok(path) = env::var("SYZYGY_PATH") {
+        assert!(syzygy::set_path(&path) > 0 && syzygy::max_pieces() >= 4, "no 3 and 4 piece tables in {}", path);
+        _check_syzygy_results(&mut board3);
+    }
+    syzygy::set_path("");
+    for dir in [fixtures, cursed].iter() {
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+// Results of the tables that follow from the rules, whoever generated them
+fn _check_syzygy_results(board3 : &mut board::ChessBoard) {
+    board3.from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+    assert!(syzygy::probe_wdl(board3) == Some(syzygy::Wdl::Win));
+    board3.from_fen("k7/1Q6/8/8/8/8/8/K7 b - - 0 1");
+    assert!(syzygy::probe_wdl(board3) == Some(syzygy::Wdl::Draw)); // the queen is taken
+    board3.from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1");
+    assert!(syzygy::probe_wdl(board3) == Some(syzygy::Wdl::Draw)); // stalemate
+    board3.from_fen("8/8/8/3k4/8/8/8/KR6 w KQ - 0 1");
+    assert!(syzygy::probe_wdl(board3).is_none()); // castling rights
+
+    // A rook pawn draws with the defending king in the corner, a knight pawn wins
+    board3.from_fen("k7/8/8/8/8/8/P7/K7 w - - 0 1");
+    assert!(syzygy::probe_wdl(board3) == Some(syzygy::Wdl::Draw));
+    board3.from_fen("1k6/8/8/8/8/8/1P6/K7 w - - 0 1");
+    assert!(syzygy::probe_wdl(board3) == Some(syzygy::Wdl::Win));
+
+    // KPK against the bitbase, the pawn on every square and either color
+    for pawn in 8..56 {
+        for white_king in [0, 19, 36, 63].iter() {
+            for black_king in 0..64 {
+                for side in ["w", "b"].iter() {
+                    if black_king == pawn || black_king == *white_king || pawn == *white_king {
+                        continue;
+                    }
+                    let mut rows = [['1'; 8]; 8];
+                    rows[7 - pawn / 8][pawn % 8] = 'P';
+                    rows[7 - white_king / 8][white_king % 8] = 'K';
+                    rows[7 - black_king / 8][black_king % 8] = 'k';
+                    let placement : Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+                    let fen = format!("{} {} - - 0 1", placement.join("/"), side);
+                    board3.from_fen(&fen);
+                    let wins = match kpk::probe_board(board3) {
+                        Some(wins) => wins,
+                        None => continue,
+                    };
+                    board3.set_protagonist(if *side == "w" { board::BLACK } else { board::WHITE });
+                    if board3.protagonist_in_check() {
+                        continue;
+                    }
+                    let expected = if !wins { syzygy::Wdl::Draw } else if *side == "w" { syzygy::Wdl::Win } else { syzygy::Wdl::Loss };
+                    for fen in [fen.clone(), _mirror_fen(&fen)].iter() {
+                        board3.from_fen(fen);
+                        assert!(syzygy::probe_wdl(board3) == Some(expected), "WDL disagrees with the bitbase for {}", fen);
+                    }
+                }
+            }
+        }
+    }
+
+    // Every KQK position with the white king on a1 agrees with the generated table
+    for queen in 1..64 {
+        for king in 0..64 {
+            for side in ["w", "b"].iter() {
+                let mut rows = [['1'; 8]; 8];
+                if king == queen || king == 0 {
+                    continue;
+                }
+                rows[7][0] = 'K';
+                rows[queen / 8][queen % 8] = 'Q';
+                rows[king / 8][king % 8] = 'k';
+                let placement : Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+                board3.from_fen(&format!("{} {} - - 0 1", placement.join("/"), side));
+                let expected = match tablebase::probe_score(board3, 0) {
+                    Some(score) => score,
+                    None => continue, // not a legal position
+                };
+                let wdl = syzygy::probe_wdl(board3).unwrap();
+                let dtz = syzygy::probe_dtz(board3).unwrap();
+                assert!((expected > 0) == (wdl >= syzygy::Wdl::CursedWin) && (expected < 0) == (wdl <= syzygy::Wdl::BlessedLoss),
+                        "WDL disagrees for {}", board3.to_fen());
+                assert!(dtz.signum() == expected.signum(), "DTZ disagrees for {}", board3.to_fen());
+            }
+        }
+    }
+}
+
+// The table's result is the best move's one ply on, and the distance to
+// zeroing one more than the best move's, or 1 when the move zeroes or mates
+fn _check_syzygy_ply(board : &mut board::ChessBoard) {
+    let fen = board.to_fen();
+    let wdl = syzygy::probe_wdl(board).unwrap();
+    let dtz = syzygy::probe_dtz(board).unwrap();
+    let moves = board.get_legal_moves();
+    let mut best = if moves.is_empty() && !board.protagonist_in_check() { syzygy::Wdl::Draw } else { syzygy::Wdl::Loss };
+    let mut shortest_win = i32::MAX;
+    let mut longest_loss = 1;
+    for amove in moves {
+        let zeroing = board.board[amove.1.0][amove.1.1] != 0 || board::clear_piece_color(board.board[amove.0.0][amove.0.1]) == board::PAWN;
+        let state = board.save_state();
+        let captured_piece = board.make(amove.0, amove.1).unwrap();
+        let reply = syzygy::probe_wdl(board).unwrap();
+        let reply_dtz = if zeroing { 0 } else { syzygy::probe_dtz(board).unwrap() };
+        let mates = board.protagonist_in_check() && board.get_legal_moves().is_empty();
+        board.unmake(amove.0, amove.1, captured_piece);
+        board.restore_state(state);
+        let value = match reply {
+            syzygy::Wdl::Win => syzygy::Wdl::Loss,
+            syzygy::Wdl::Loss => syzygy::Wdl::Win,
+            other => other,
+        };
+        if value > best {
+            best = value;
+        }
+        if value == syzygy::Wdl::Win {
+            shortest_win = shortest_win.min(if zeroing || mates { 1 } else { 1 - reply_dtz });
+        } else if value == syzygy::Wdl::Loss && !zeroing {
+            longest_loss = longest_loss.max(reply_dtz + 1);
+        }
+    }
+    let expected = match best {
+        syzygy::Wdl::Win => shortest_win,
+        syzygy::Wdl::Loss => -longest_loss,
+        _ => 0,
+    };
+    assert!(wdl == best && dtz == expected, "{}: {:?} and {} against {:?} and {} one ply on", fen, wdl, dtz, best, expected);
+}
+
+fn _test_polyglot() {
+    // Keys from the Polyglot book format description
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let known = [
+        ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0x463b96181691fc9c),
+        ("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1", 0x823c9b50fd114196),
+        ("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2", 0x0756b94461c50fb0),
+        ("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2", 0x662fafb965db29d4),
+        ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", 0x22a48b5a8e47ff78),
+        ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3", 0x652a607ca3f242c1),
+        ("rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4", 0x00fdd303c946bdd9),
+        ("rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3", 0x3c8123ea7b067637),
+        ("rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4", 0x5c3f9b829b279560),
+    ];
+    for (fen, expected) in known.iter() {
+        board.from_fen(fen);
+        assert!(polyglot::key(&board) == *expected, "{}: key {:016x}", fen, polyglot::key(&board));
+    }
+    // The internal board sets en passant after every double push
+    board.from_fen(board::START_FEN);
+    board.make((6, 4), (4, 4, 0));
+    assert!(polyglot::key(&board) == 0x823c9b50fd114196);
+
+    // Castling is the king taking its rook, promotions carry their piece
+    board.from_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1");
+    let castle = ((7, 4), (7, 6, 0));
+    assert!(polyglot::encode_move(&board, castle) == 0x0107);
+    assert!(polyglot::decode_move(&mut board, 0x0107) == Some(castle));
+    let long_castle = ((7, 4), (7, 2, 0));
+    let raw_move = polyglot::encode_move(&board, long_castle);
+    assert!(raw_move == 0x0100 && polyglot::decode_move(&mut board, raw_move) == Some(long_castle));
+    let promotion = ((1, 1), (0, 0, board::KNIGHT));
+    let raw_move = polyglot::encode_move(&board, promotion);
+    assert!(raw_move == 0x1C78 && polyglot::decode_move(&mut board, raw_move) == Some(promotion));
+    assert!(polyglot::decode_move(&mut board, 0x0000).is_none());
+
+    // Built from games: 1. e4 twice, a win and a draw, 1. d4 once, a loss
+    let mut games = Vec::new();
+    for (first, result) in [("e4", "1-0"), ("e4", "1/2-1/2"), ("d4", "0-1")].iter() {
+        let mut record = game::new_game();
+        assert!(record.play_san(first) && record.play_san("Nf6"));
+        record.result = result.to_string();
+        games.push(record);
+    }
+    let book = polyglot::build(&games, &polyglot::BuildOptions{ max_ply: 1, min_games: 1 });
+    assert!(book.len() == 1, "{} entries", book.len());
+    board.from_fen(board::START_FEN);
+    let e4 = ((6, 4), (4, 4, 0));
+    assert!(book.moves(&mut board) == vec![(e4, 3)]);
+    let book = polyglot::build(&games, &polyglot::BuildOptions{ max_ply: 2, min_games: 2 });
+    assert!(book.len() == 2);
+    board.make((6, 4), (4, 4, 0));
+    // Black lost one of them and drew the other
+    assert!(book.moves(&mut board) == vec![(((0, 6), (2, 5, 0)), 1)]);
+    assert!(polyglot::build(&games, &polyglot::BuildOptions{ max_ply: 2, min_games: 3 }).len() == 0);
+
+    // Bytes round trip and selection
+    let d4 = ((6, 3), (4, 3, 0));
+    board.from_fen(board::START_FEN);
+    let key = polyglot::key(&board);
+    let entries = vec![
+        polyglot::BookEntry{ key, raw_move: polyglot::encode_move(&board, d4), weight: 1, learn: 0 },
+        polyglot::BookEntry{ key, raw_move: polyglot::encode_move(&board, e4), weight: 3, learn: 0 },
+        polyglot::BookEntry{ key: key ^ 1, raw_move: 0, weight: 5, learn: 0 },
+    ];
+    let book = polyglot::Book::from_bytes(&polyglot::Book::new(entries).to_bytes()).unwrap();
+    assert!(book.entries(key).len() == 2 && book.entries(key)[0].weight == 3);
+    assert!(book.pick(&mut board, polyglot::Selection::Best, 0) == Some(e4));
+    assert!(book.pick(&mut board, polyglot::Selection::Weighted, 2) == Some(e4));
+    assert!(book.pick(&mut board, polyglot::Selection::Weighted, 3) == Some(d4));
+    assert!(polyglot::Book::from_bytes(&[0u8; 15]).is_err());
+}
+
+// Plays a line out and reports whether every move was legal
+fn _legal_line(board : &mut board::ChessBoard, line : &[Move]) -> bool {
+    for amove in line {
+        if !board.get_legal_moves().contains(amove) {
+            return false;
+        }
+        board.make(amove.0, amove.1);
+    }
+    true
+}
+
+fn _test_search() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let stop = AtomicBool::new(false);
+    let mut limits = search::default_limits();
+    let options = search::default_options();
+
+    // Mate in one, and a ladder mate in two with its whole line
+    board.from_fen("r3k2r/pp3p2/2n1p3/2pp1b2/6nq/1P1PPN2/PBP2PB1/R2QR1K1 b kq - 1 15");
+    limits.depth = 2;
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(eval::mate_in(1)), "{:?}", result.lines);
+    assert!(result.lines[0].moves.len() == 1);
+
+    let fen = "7k/8/8/8/8/8/8/RR5K w - - 0 1";
+    board.from_fen(fen);
+    limits.depth = 4;
+    let mut reports = 0;
+    let result = search::search(&mut board, &limits, &options, &stop, |info| {
+        assert!(info.multipv == 1 && info.depth as usize == reports + 1);
+        reports += 1;
+    });
+    assert!(reports == 4 && result.depth == 4);
+    assert!(result.score() == Some(eval::mate_in(3)), "{:?}", result.lines);
+    let line = result.lines[0].moves.clone();
+    assert!(line.len() == 3 && _legal_line(&mut board, &line));
+    assert!(board.get_legal_moves().is_empty() && board.protagonist_in_check());
+
+    // MultiPV: distinct first moves, best first, every line legal
+    board.from_fen(board::START_FEN);
+    limits.depth = 3;
+    limits.multipv = 3;
+    let mut seen = Vec::new();
+    let result = search::search(&mut board, &limits, &options, &stop, |info| seen.push((info.depth, info.multipv)));
+    assert!(seen.len() == 9 && seen[8] == (3, 3));
+    assert!(result.lines.len() == 3);
+    for (index, line) in result.lines.iter().enumerate() {
+        assert!(line.moves.len() == 3);
+        assert!(result.lines[..index].iter().all(|other| other.moves[0] != line.moves[0] && other.score >= line.score));
+        board.from_fen(board::START_FEN);
+        assert!(_legal_line(&mut board, &line.moves));
+    }
+
+    // More lines than moves, and a search stopped before it started
+    board.from_fen("7k/8/8/8/8/8/6q1/7K w - - 0 1");
+    limits.multipv = 5;
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.lines.len() == 1 && result.best_move() == Some(((7, 7), (6, 6, 0))));
+    board.from_fen(board::START_FEN);
+    let stopped = AtomicBool::new(true);
+    let result = search::search(&mut board, &search::default_limits(), &options, &stopped, |_| panic!("nothing finished"));
+    assert!(result.depth == 0 && result.best_move().is_some());
+
+    // Pruning finds the same mate, and searches fewer nodes than full width
+    board.from_fen("7k/8/8/8/8/8/8/RR5K w - - 0 1");
+    limits.depth = 4;
+    limits.multipv = 1;
+    let pruned = search::search(&mut board, &limits, &options, &stop, |_| {});
+    let full = search::search(&mut board, &limits, &search::plain_alpha_beta(), &stop, |_| {});
+    assert!(pruned.score() == full.score() && pruned.score() == Some(eval::mate_in(3)));
+    board.from_fen(board::START_FEN);
+    limits.depth = 4;
+    let pruned = search::search(&mut board, &limits, &options, &stop, |_| {});
+    let full = search::search(&mut board, &limits, &search::plain_alpha_beta(), &stop, |_| {});
+    assert!(pruned.nodes < full.nodes, "{} against {} nodes", pruned.nodes, full.nodes);
+
+    // Node limits are checked every so often
+    limits.depth = search::MAX_DEPTH;
+    limits.multipv = 1;
+    limits.nodes = Some(3000);
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.nodes < 3000 + 1024 && result.best_move().is_some());
+}
+
+fn _test_mate_search() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let stop = AtomicBool::new(false);
+    let mate_in_3 = "6k1/pp3p1p/2p3p1/3p1P2/3P1KPP/4Q3/P1q5/8 w - - 0 26";
+
+    // Extensions find the mate in 3 at depth 3, plain alpha-beta does not
+    board.from_fen(mate_in_3);
+    let mut limits = search::default_limits();
+    limits.depth = 3;
+    let result = search::search(&mut board, &limits, &search::default_options(), &stop, |_| {});
+    assert!(result.score() == Some(eval::mate_in(5)), "{:?}", result.lines);
+    let result = search::search(&mut board, &limits, &search::plain_alpha_beta(), &stop, |_| {});
+    assert!(!eval::is_mate_score(result.score().unwrap()));
+
+    // Proved with checks only and with every move, disproved in two
+    for checks_only in [true, false].iter() {
+        match search::mate_search(&mut board, 3, *checks_only, &stop) {
+            search::MateResult::Mate(line) => {
+                assert!(line.len() == 5 && line[0] == ((5, 4), (0, 4, 0)), "{:?}", line);
+                assert!(_legal_line(&mut board, &line));
+                assert!(board.get_legal_moves().is_empty() && board.protagonist_in_check());
+                board.from_fen(mate_in_3);
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+    assert!(search::mate_search(&mut board, 2, false, &stop) == search::MateResult::NoMate);
+    assert!(board.to_fen() == mate_in_3);
+
+    // Stalemating is not mating, and a quiet first move can be needed
+    board.from_fen("7k/5Q2/6K1/8/8/8/8/8 w - - 0 1");
+    match search::mate_search(&mut board, 1, false, &stop) {
+        search::MateResult::Mate(line) => assert!(line.len() == 1),
+        other => panic!("{:?}", other),
+    }
+    board.from_fen("k7/8/1K6/8/8/8/8/1R6 w - - 0 1");
+    assert!(search::mate_search(&mut board, 2, true, &stop) == search::MateResult::NoMate);
+    match search::mate_search(&mut board, 2, false, &stop) {
+        search::MateResult::Mate(line) => assert!(line.len() == 3, "{:?}", line),
+        other => panic!("{:?}", other),
+    }
+    let stopped = AtomicBool::new(true);
+    board.from_fen(board::START_FEN);
+    assert!(search::mate_search(&mut board, 4, false, &stopped) == search::MateResult::Stopped);
+}
+
+fn _test_transposition() {
+    let table = transposition::Table::new(1);
+    assert!(table.len() == 65536);
+    let amove = ((1, 4), (0, 4, board::QUEEN));
+    assert!(table.probe(12345).is_none());
+    table.store(12345, Some(amove), -77, 5, transposition::Bound::Lower);
+    let entry = table.probe(12345).unwrap();
+    assert!(entry.amove == Some(amove) && entry.score == -77 && entry.depth == 5 && entry.bound == transposition::Bound::Lower);
+    // Another key in the same slot is not taken for this one
+    assert!(table.probe(12345 + 65536).is_none());
+
+    // A shallower bound does not push out a deeper one from the same search,
+    // and a fail low keeps the move known
+    table.store(12345, None, 10, 3, transposition::Bound::Upper);
+    assert!(table.probe(12345).unwrap().depth == 5);
+    table.store(12345, None, 10, 6, transposition::Bound::Upper);
+    let entry = table.probe(12345).unwrap();
+    assert!(entry.depth == 6 && entry.amove == Some(amove) && entry.score == 10);
+    table.new_search();
+    table.store(12345, None, eval::mate_in(7), 1, transposition::Bound::Exact);
+    assert!(table.probe(12345).unwrap().score == eval::mate_in(7));
+    table.clear();
+    assert!(table.probe(12345).is_none());
+
+    // The key kept by make and unmake matches one built from scratch, through
+    // castling, en passant and promotions
+    let stuff = [[0u8; 8]; 8];
+    let mut board3 = board::build_board(stuff);
+    let positions = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+    for fen in positions.iter() {
+        board3.from_fen(fen);
+        let key = zobrist::hash(&board3);
+        _check_keys(&mut board3, 2);
+        assert!(zobrist::hash(&board3) == key);
+    }
+}
+
+fn _check_keys(board3 : &mut board::ChessBoard, depth : u16) {
+    let mut fresh = board::build_board([[0u8; 8]; 8]);
+    fresh.from_fen(&board3.to_fen());
+    assert!(zobrist::hash(board3) == zobrist::hash(&fresh), "Stale key in {}", board3.to_fen());
+    if depth == 0 {
+        return;
+    }
+    for amove in board3.get_all_moves() {
+        let state = board3.save_state();
+        if let Some(captured_piece) = board3.make(amove.0, amove.1) {
+            _check_keys(board3, depth - 1);
+            board3.unmake(amove.0, amove.1, captured_piece);
+        }
+        board3.restore_state(state);
+    }
+}
+
+fn _test_parallel_search() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let stop = AtomicBool::new(false);
+    let mut limits = search::default_limits();
+    let options = search::default_options();
+
+    // One thread gives the same result every time
+    board.from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+    limits.depth = 4;
+    let first = search::search(&mut board, &limits, &options, &stop, |_| {});
+    let second = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(first.nodes == second.nodes && first.lines[0].moves == second.lines[0].moves && first.score() == second.score());
+
+    // More threads share one table and still find the mate, helpers' nodes
+    // counted in
+    let table = transposition::Table::new(4);
+    board.from_fen("7k/8/8/8/8/8/8/RR5K w - - 0 1");
+    let mut reports = 0;
+    let result = search::parallel_search(&mut board, &limits, &options, 4, &table, &stop, |info| {
+        assert!(info.depth as usize == reports + 1);
+        reports += 1;
+    });
+    assert!(reports == 4 && result.depth == 4);
+    assert!(result.score() == Some(eval::mate_in(3)), "{:?}", result.lines);
+    assert!(_legal_line(&mut board, &result.lines[0].moves));
+    assert!(board.get_legal_moves().is_empty() && board.protagonist_in_check());
+
+    // A stop reaches the helpers too
+    let stopped = AtomicBool::new(true);
+    board.from_fen(board::START_FEN);
+    let result = search::parallel_search(&mut board, &search::default_limits(), &options, 3, &table, &stopped, |_| {});
+    assert!(result.best_move().is_some() && result.depth == 0);
+}
+
+fn _test_null_move() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2";
+    board.from_fen(fen);
+    let state = board.save_state();
+    board.make_null();
+    assert!(board.protagonist == board::BLACK && board.ep.is_none());
+    assert!(board.to_fen() == "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 1 2", "{}", board.to_fen());
+    assert!(board.get_legal_moves().len() == 29);
+    board.unmake_null();
+    board.restore_state(state);
+    assert!(board.to_fen() == fen);
+    assert!(board.get_legal_moves().len() == 29);
+}
+
+fn _test_clock() {
+    let control = clock::TimeControl{ time_left: Duration::from_millis(60030), increment: Duration::from_millis(400),
+                                      moves_to_go: None, overhead: Duration::from_millis(30) };
+    let budget = clock::budget(&control);
+    assert!(budget.soft == Duration::from_millis(60000 / 30 + 300) && budget.hard == budget.soft * 4, "{:?}", budget);
+    // The last move before the time control may take most of what is left,
+    // but not all of it, and the overhead is never spent
+    let last = clock::budget(&clock::TimeControl{ moves_to_go: Some(1), ..control });
+    assert!(last.hard == Duration::from_millis(48000) && last.soft == last.hard, "{:?}", last);
+    let flagging = clock::budget(&clock::TimeControl{ time_left: Duration::from_millis(20), increment: Duration::from_millis(0), ..control });
+    assert!(flagging.hard == Duration::from_millis(0));
+    // Unless an increment leaves something to think with
+    let flagging = clock::budget(&clock::TimeControl{ time_left: Duration::from_millis(20), ..control });
+    assert!(flagging.hard == Duration::from_micros(370 * 1000 / 4) && flagging.soft == flagging.hard, "{:?}", flagging);
+
+    let e2e4 = ((6, 4), (4, 4, 0));
+    let d2d4 = ((6, 3), (4, 3, 0));
+    let roomy = clock::TimeBudget{ soft: Duration::from_secs(100), hard: Duration::from_secs(400) };
+    // A change of mind or a falling score stretches the soft limit, a best
+    // move standing for long enough shrinks it
+    let mut manager = clock::TimeManager::new(roomy);
+    assert!(manager.keep_going(1, e2e4, 30, 20) && manager.scale() == 1.0);
+    assert!(manager.keep_going(2, d2d4, 30, 20) && manager.scale() > 1.5);
+    assert!(manager.keep_going(3, d2d4, 30, 20) && manager.scale() > 1.0);
+    assert!(manager.keep_going(4, d2d4, -20, 20) && manager.scale() > 1.5);
+    for depth in 5..9 {
+        assert!(manager.keep_going(depth, d2d4, -20, 20));
+    }
+    assert!(manager.scale() < 0.5 && manager.soft_limit() < roomy.soft);
+
+    // A forced move and a mate seen to the end are played at once, a mate
+    // deeper than the search not yet
+    let mut manager = clock::TimeManager::new(roomy);
+    assert!(!manager.keep_going(1, e2e4, 0, 1));
+    let mut manager = clock::TimeManager::new(roomy);
+    assert!(manager.keep_going(2, e2e4, eval::mate_in(5), 20));
+    assert!(!manager.keep_going(5, e2e4, eval::mate_in(5), 20));
+    // Out of time
+    let mut manager = clock::TimeManager::new(clock::TimeBudget{ soft: Duration::from_millis(0), hard: Duration::from_millis(0) });
+    assert!(manager.out_of_time() && !manager.keep_going(1, e2e4, 0, 20));
+
+    // The search stops on its own, with a move, within the hard limit
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    board.from_fen(board::START_FEN);
+    let stop = AtomicBool::new(false);
+    let mut limits = search::default_limits();
+    limits.clock = Some(clock::TimeBudget{ soft: Duration::from_millis(50), hard: Duration::from_millis(200) });
+    let started = std::time::Instant::now();
+    let result = search::search(&mut board, &limits, &search::default_options(), &stop, |_| {});
+    assert!(result.best_move().is_some() && started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+    board.from_fen("7k/8/8/8/8/8/6q1/7K w - - 0 1");
+    limits.clock = Some(roomy);
+    let result = search::search(&mut board, &limits, &search::default_options(), &stop, |_| {});
+    assert!(result.depth == 1 && result.best_move() == Some(((7, 7), (6, 6, 0))));
+}
+
+fn _test_ponder() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    board.from_fen(board::START_FEN);
+    let stop = AtomicBool::new(false);
+
+    // Out of time from the start, but pondering until the ponderhit, after
+    // which the clock runs out at once and the search ends with its move
+    let mut limits = uci::parse_go(&["ponder", "wtime", "1000", "btime", "1000"], true, Duration::from_millis(1000));
+    let ponder = limits.ponder.clone().unwrap();
+    assert!(ponder.load(std::sync::atomic::Ordering::Relaxed) && limits.clock.unwrap().hard == Duration::from_millis(0));
+    limits.depth = search::MAX_DEPTH;
+    let hit = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        ponder.store(false, std::sync::atomic::Ordering::Relaxed);
+    });
+    let started = std::time::Instant::now();
+    let result = search::search(&mut board, &limits, &search::default_options(), &stop, |_| {});
+    hit.join().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(300) && result.depth >= 1 && result.best_move().is_some());
+
+    // The reply to ponder on comes from the line, or from the table when the
+    // line stops at the best move
+    let table = transposition::Table::new(1);
+    let e2e4 = ((6, 4), (4, 4, 0));
+    let c7c5 = ((1, 2), (3, 2, 0));
+    let result = search::SearchResult{ lines: vec![search::PvLine{ score: 20, moves: vec![e2e4, c7c5, ((7, 6), (5, 5, 0))] }], depth: 3, nodes: 100 };
+    assert!(uci::expected_moves(&mut board, &result, &table) == vec![e2e4, c7c5]);
+    let result = search::SearchResult{ lines: vec![search::PvLine{ score: 20, moves: vec![e2e4] }], depth: 3, nodes: 100 };
+    assert!(uci::expected_moves(&mut board, &result, &table) == vec![e2e4]);
+    board.make(e2e4.0, e2e4.1);
+    table.store(zobrist::hash(&board), Some(c7c5), 0, 2, transposition::Bound::Exact);
+    board.from_fen(board::START_FEN);
+    assert!(uci::expected_moves(&mut board, &result, &table) == vec![e2e4, c7c5]);
+    assert!(board.to_fen() == board::START_FEN);
+}
+
+fn _test_draws() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let stop = AtomicBool::new(false);
+    let mut limits = search::default_limits();
+    let mut options = search::default_options();
+
+    // Far behind, white has a perpetual check, worth the draw less contempt
+    board.from_fen("7k/6p1/8/8/8/1q6/1rr3PP/3Q3K w - - 0 1");
+    limits.depth = 5;
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(0) && result.best_move() == Some(((7, 3), (3, 7, 0))), "{:?}", result.lines);
+    options.contempt = 30;
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(-30) && result.best_move() == Some(((7, 3), (3, 7, 0))), "{:?}", result.lines);
+
+    // Every move draws by the fifty-move rule, unless it mates
+    limits.depth = 4;
+    board.from_fen("7k/8/8/8/8/8/8/R5K1 w - - 99 80");
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(-30), "{:?}", result.lines);
+    board.from_fen("7k/8/8/8/8/8/8/R5K1 w - - 0 80");
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score().unwrap() > 500);
+    board.from_fen("6k1/8/6K1/8/8/8/8/R7 w - - 99 80");
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(eval::mate_in(1)), "{:?}", result.lines);
+
+    // Positions of the game seen twice draw the third time, which black
+    // expecting to be weaker goes for
+    let history = uci::set_position(&mut board, &["startpos", "moves", "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"]).unwrap();
+    assert!(history.len() == 7 && history[0] == history[4] && history[0] != history[2]);
+    options.contempt = -100;
+    limits.history = history;
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(100), "{:?}", result.lines);
+    limits.history = uci::set_position(&mut board, &["startpos", "moves", "g1f3", "g8f6", "f3g1"]).unwrap();
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score().unwrap() < 100, "{:?}", result.lines);
+
+    // A double push no pawn can take en passant after does not make the position
+    // a new one, so at one ply black sees the third repetition through the history
+    let history = uci::set_position(&mut board, &["startpos", "moves", "e2e4", "e7e5", "b1c3", "b8c6", "c3b1", "c6b8", "b1c3", "b8c6", "c3b1"]).unwrap();
+    assert!(history[2] == history[6]);
+    limits.history = history;
+    limits.depth = 1;
+    options.contempt = -200;
+    let result = search::search(&mut board, &limits, &options, &stop, |_| {});
+    assert!(result.score() == Some(200), "{:?}", result.lines);
+}
+
+fn _test_uci() {
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    assert!(uci::set_position(&mut board, &["startpos", "moves", "e2e4", "e7e5", "g1f3"]).is_ok());
+    assert!(board.to_fen() == "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2", "{}", board.to_fen());
+    assert!(uci::set_position(&mut board, &["fen", "r3k2r/8/8/8/8/8/8/R3K2R", "w", "KQkq", "-", "0", "1", "moves", "e1g1", "a8a1"]).is_ok());
+    assert!(board.to_fen() == "4k2r/8/8/8/8/8/8/r4RK1 w k - 0 2", "{}", board.to_fen());
+    assert!(uci::set_position(&mut board, &["startpos", "moves", "e2e5"]).is_err());
+    assert!(uci::set_position(&mut board, &["fen"]).is_err());
+
+    let overhead = Duration::from_millis(30);
+    let limits = uci::parse_go(&["depth", "5", "nodes", "1000"], true, overhead);
+    assert!(limits.depth == 5 && limits.nodes == Some(1000) && limits.movetime.is_none() && limits.clock.is_none());
+    let limits = uci::parse_go(&["wtime", "60000", "btime", "30030", "winc", "1000", "binc", "2000", "movestogo", "10"], false, overhead);
+    assert!(limits.movetime.is_none() && limits.clock.unwrap().soft == Duration::from_millis(30000 / 10 + 1500), "{:?}", limits.clock);
+    let limits = uci::parse_go(&["wtime", "60000", "btime", "30000", "movetime", "500"], true, overhead);
+    assert!(limits.movetime == Some(Duration::from_millis(500)) && limits.clock.is_none());
+    let limits = uci::parse_go(&["mate", "3"], true, overhead);
+    assert!(limits.mate == Some(3) && limits.depth == 6);
+    let limits = uci::parse_go(&["infinite"], true, overhead);
+    assert!(limits.depth == search::MAX_DEPTH && limits.movetime.is_none() && limits.clock.is_none());
+
+    let info = search::SearchInfo{ depth: 3, multipv: 2, score: eval::mated_in(4), pv: vec![((6, 4), (4, 4, 0)), ((1, 4), (3, 4, 0))],
+                                   nodes: 5000, elapsed: Duration::from_millis(250) };
+    assert!(uci::info_line(&info) == "info depth 3 multipv 2 score mate -2 nodes 5000 nps 20000 time 250 pv e2e4 e7e5", "{}", uci::info_line(&info));
+}
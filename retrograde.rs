@@ -0,0 +1,560 @@
+use syzygy_writer;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Retrograde solving of endings up to four pieces, pawns included, to write
+// Syzygy tables with. Squares are numbered a1 = 0 to h8 = 63 as in syzygy.rs.
+// Every position gets its distance to zeroing: plies to the next capture or
+// pawn move with best play, positive when the side to move wins, with mate
+// counted as a zeroing move. A mated side has -1, a mate in one 1 and a draw 0.
+pub const MAX_PIECES : usize = 4;
+
+const INVALID : i16 = i16::MIN;
+
+// Best result of the captures and pawn moves when there are none
+const NO_ZEROING : i8 = -3;
+
+// Piece kinds as the table files number them
+const PAWN : u8 = 1;
+const KNIGHT : u8 = 2;
+const BISHOP : u8 = 3;
+const ROOK : u8 = 4;
+const QUEEN : u8 = 5;
+const KING : u8 = 6;
+
+// Letters in the order they are written in a name
+const LETTERS : [(char, u8); 6] = [('K', KING), ('Q', QUEEN), ('R', ROOK), ('B', BISHOP), ('N', KNIGHT), ('P', PAWN)];
+
+// Rook directions first, then bishop ones, as (file, rank) steps
+const DIRECTIONS : [(i32, i32); 8] = [(0, 1), (1, 0), (0, -1), (-1, 0), (1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+#[derive(Clone, Copy, PartialEq)]
+struct Piece {
+    white: bool,
+    kind: u8,
+}
+
+#[derive(Clone, Copy)]
+struct Move {
+    slot: usize,
+    to: usize,
+    captured: Option<usize>,
+    promotion: u8,
+}
+
+// Where a capture or promotion leads: the ending, whether its colors are the
+// other way round, and for each of its pieces the slot it comes from
+struct Transition {
+    ending: usize,
+    flip: bool,
+    slots: Vec<usize>,
+}
+
+struct Ending {
+    // White king, black king, then the other white pieces and the other black ones
+    pieces: Vec<Piece>,
+    values: Vec<i16>,
+}
+
+pub struct Solver {
+    endings: Vec<Ending>,
+    names: HashMap<String, usize>,
+    king: [u64; 64],
+    knight: [u64; 64],
+    rays: [[u64; 8]; 64],
+}
+
+fn rank_of(square : usize) -> usize {
+    square >> 3
+}
+
+fn file_of(square : usize) -> usize {
+    square & 7
+}
+
+fn letter(kind : u8) -> char {
+    LETTERS.iter().find(|(_, other)| *other == kind).unwrap().0
+}
+
+// Both sides of a name like KPvKP, kings first and the pieces in letter order
+fn parse_name(name : &str) -> Result<Vec<Piece>, String> {
+    let sides : Vec<&str> = name.split('v').collect();
+    if sides.len() != 2 {
+        return Err(format!("{}: not a name like KPvKP", name));
+    }
+    let mut pieces = vec![Piece{ white: true, kind: KING }, Piece{ white: false, kind: KING }];
+    for (side, white) in sides.iter().zip([true, false].iter()) {
+        let mut kinds = Vec::new();
+        for character in side.chars() {
+            match LETTERS.iter().find(|(other, _)| *other == character) {
+                Some((_, kind)) => kinds.push(*kind),
+                None => return Err(format!("{}: no piece is called {}", name, character)),
+            }
+        }
+        if kinds.iter().filter(|kind| **kind == KING).count() != 1 || kinds[0] != KING {
+            return Err(format!("{}: each side needs one king, written first", name));
+        }
+        let order = |kind : &u8| LETTERS.iter().position(|(_, other)| other == kind).unwrap();
+        if kinds.windows(2).any(|pair| order(&pair[0]) > order(&pair[1])) {
+            return Err(format!("{}: pieces out of order", name));
+        }
+        pieces.extend(kinds[1..].iter().map(|kind| Piece{ white: *white, kind: *kind }));
+    }
+    if pieces.len() > MAX_PIECES {
+        return Err(format!("{}: more than {} pieces", name, MAX_PIECES));
+    }
+    Ok(pieces)
+}
+
+// The name for pieces in any order, with the given color first
+fn side_name(pieces : &[Piece], white : bool) -> String {
+    let mut kinds : Vec<u8> = pieces.iter().filter(|piece| piece.white == white).map(|piece| piece.kind).collect();
+    kinds.sort_by_key(|kind| LETTERS.iter().position(|(_, other)| other == kind).unwrap());
+    kinds.iter().map(|kind| letter(*kind)).collect()
+}
+
+// The stronger side first: more pieces, then the better ones
+fn canonical_name(pieces : &[Piece]) -> String {
+    let (white, black) = (side_name(pieces, true), side_name(pieces, false));
+    let strength = |side : &str| (side.len(), side.chars().map(|letter| 6 - LETTERS.iter().position(|(other, _)| *other == letter).unwrap()).collect::<Vec<usize>>());
+    if strength(&black) > strength(&white) { format!("{}v{}", black, white) } else { format!("{}v{}", white, black) }
+}
+
+// Squares by slot, then the side to move
+fn index(squares : &[usize], black_to_move : bool) -> usize {
+    let mut index = (black_to_move as usize) << (6 * squares.len());
+    for (slot, square) in squares.iter().enumerate() {
+        index |= square << (6 * slot);
+    }
+    index
+}
+
+fn wdl(value : i16) -> i8 {
+    2 * value.signum() as i8
+}
+
+impl Solver {
+    pub fn new() -> Solver {
+        let mut solver = Solver{
+            endings: Vec::new(),
+            names: HashMap::new(),
+            king: [0; 64],
+            knight: [0; 64],
+            rays: [[0; 8]; 64],
+        };
+        let step = |square : usize, file : i32, rank : i32| {
+            let (file, rank) = (file_of(square) as i32 + file, rank_of(square) as i32 + rank);
+            if (0..8).contains(&file) && (0..8).contains(&rank) { Some((rank * 8 + file) as usize) } else { None }
+        };
+        for square in 0..64 {
+            for (file, rank) in DIRECTIONS.iter() {
+                if let Some(target) = step(square, *file, *rank) {
+                    solver.king[square] |= 1 << target;
+                }
+            }
+            for (file, rank) in [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)].iter() {
+                if let Some(target) = step(square, *file, *rank) {
+                    solver.knight[square] |= 1 << target;
+                }
+            }
+            for (direction, (file, rank)) in DIRECTIONS.iter().enumerate() {
+                let mut target = square;
+                while let Some(next) = step(target, *file, *rank) {
+                    solver.rays[square][direction] |= 1 << next;
+                    target = next;
+                }
+            }
+        }
+        solver
+    }
+
+    pub fn contains(& self, name : &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    // Sliding along the directions up to and including the first piece
+    fn slide(& self, square : usize, occupied : u64, directions : &[usize]) -> u64 {
+        let mut attacks = 0;
+        for direction in directions {
+            let ray = self.rays[square][*direction];
+            let blockers = ray & occupied;
+            if blockers == 0 {
+                attacks |= ray;
+                continue;
+            }
+            let (file, rank) = DIRECTIONS[*direction];
+            let blocker = if rank > 0 || (rank == 0 && file > 0) { blockers.trailing_zeros() } else { 63 - blockers.leading_zeros() };
+            attacks |= ray & !self.rays[blocker as usize][*direction];
+        }
+        attacks
+    }
+
+    fn attacks(& self, piece : Piece, square : usize, occupied : u64) -> u64 {
+        match piece.kind {
+            PAWN => {
+                let (left, right) = if piece.white { (square + 7, square + 9) } else { (square.wrapping_sub(9), square.wrapping_sub(7)) };
+                (if file_of(square) > 0 && left < 64 { 1 << left } else { 0 })
+                    | (if file_of(square) < 7 && right < 64 { 1 << right } else { 0 })
+            },
+            KNIGHT => self.knight[square],
+            BISHOP => self.slide(square, occupied, &[4, 5, 6, 7]),
+            ROOK => self.slide(square, occupied, &[0, 1, 2, 3]),
+            QUEEN => self.slide(square, occupied, &[0, 1, 2, 3, 4, 5, 6, 7]),
+            _ => self.king[square],
+        }
+    }
+
+    // Pieces taken off the board stand on square 64
+    fn in_check(& self, pieces : &[Piece], squares : &[usize], white : bool) -> bool {
+        let king = squares[if white { 0 } else { 1 }];
+        let occupied = squares.iter().filter(|square| **square < 64).fold(0u64, |bits, square| bits | 1 << square);
+        (0..pieces.len()).any(|slot| pieces[slot].white != white && squares[slot] < 64
+                                     && self.attacks(pieces[slot], squares[slot], occupied) & (1 << king) != 0)
+    }
+
+    // Pseudo-legal moves, the side to move's king may be left in check
+    fn moves(& self, pieces : &[Piece], squares : &[usize], white : bool, list : &mut Vec<Move>) {
+        list.clear();
+        let occupied = squares.iter().fold(0u64, |bits, square| bits | 1 << square);
+        let own = (0..pieces.len()).filter(|slot| pieces[*slot].white == white).fold(0u64, |bits, slot| bits | 1 << squares[slot]);
+        let slot_on = |square : usize| (0..pieces.len()).find(|slot| squares[*slot] == square);
+        for slot in 0..pieces.len() {
+            let piece = pieces[slot];
+            if piece.white != white {
+                continue;
+            }
+            let from = squares[slot];
+            if piece.kind != PAWN {
+                let mut targets = self.attacks(piece, from, occupied) & !own;
+                while targets != 0 {
+                    let to = targets.trailing_zeros() as usize;
+                    targets &= targets - 1;
+                    list.push(Move{ slot, to, captured: slot_on(to), promotion: 0 });
+                }
+                continue;
+            }
+            let mut pawn_moves = Vec::new();
+            let forward = if white { from + 8 } else { from - 8 };
+            if occupied & (1 << forward) == 0 {
+                pawn_moves.push((forward, None));
+                let double = if white { from + 16 } else { from.wrapping_sub(16) };
+                if rank_of(from) == if white { 1 } else { 6 } && occupied & (1 << double) == 0 {
+                    pawn_moves.push((double, None));
+                }
+            }
+            let mut targets = self.attacks(piece, from, occupied) & occupied & !own;
+            while targets != 0 {
+                let to = targets.trailing_zeros() as usize;
+                targets &= targets - 1;
+                pawn_moves.push((to, slot_on(to)));
+            }
+            for (to, captured) in pawn_moves {
+                if rank_of(to) == 0 || rank_of(to) == 7 {
+                    for promotion in [QUEEN, ROOK, BISHOP, KNIGHT].iter() {
+                        list.push(Move{ slot, to, captured, promotion: *promotion });
+                    }
+                } else {
+                    list.push(Move{ slot, to, captured, promotion: 0 });
+                }
+            }
+        }
+    }
+
+    fn has_legal_move(& self, pieces : &[Piece], squares : &[usize], white : bool) -> bool {
+        let mut list = Vec::new();
+        self.moves(pieces, squares, white, &mut list);
+        list.iter().any(|amove| {
+            let mut after = squares.to_vec();
+            after[amove.slot] = amove.to;
+            if let Some(captured) = amove.captured {
+                after[captured] = 64;
+            }
+            !self.in_check(pieces, &after, white)
+        })
+    }
+
+    // Every capture and promotion of the pieces, keyed by the captured slot,
+    // the promoted slot and the new piece, with the ending it leads to
+    fn successors(pieces : &[Piece]) -> Vec<(usize, Vec<(usize, Piece)>)> {
+        let count = pieces.len();
+        let mut result = Vec::new();
+        for captured in 2..=count {
+            for promoted in 0..=count {
+                for promotion in [0, QUEEN, ROOK, BISHOP, KNIGHT].iter() {
+                    let promotes = promoted < count && pieces[promoted].kind == PAWN;
+                    if (*promotion != 0) != promotes || (captured == count && !promotes) || (promoted < count && !promotes)
+                        || (captured < count && promoted < count && (captured == promoted || pieces[captured].white == pieces[promoted].white)) {
+                        continue;
+                    }
+                    let child = (0..count).filter(|slot| *slot != captured).map(|slot| {
+                        (slot, if slot == promoted { Piece{ white: pieces[slot].white, kind: *promotion } } else { pieces[slot] })
+                    }).collect();
+                    result.push((Solver::key(count, captured, promoted, *promotion), child));
+                }
+            }
+        }
+        result
+    }
+
+    fn key(count : usize, captured : usize, promoted : usize, promotion : u8) -> usize {
+        (captured * (count + 1) + promoted) * 7 + promotion as usize
+    }
+
+    // Solves the ending after every smaller one it leads to
+    pub fn solve(&mut self, name : &str) -> Result<(), String> {
+        if self.contains(name) {
+            return Ok(());
+        }
+        let pieces = parse_name(name)?;
+        for (_, child) in Solver::successors(&pieces) {
+            let child : Vec<Piece> = child.iter().map(|(_, piece)| *piece).collect();
+            let white = side_name(&child, true);
+            let black = side_name(&child, false);
+            if !self.contains(&format!("{}v{}", white, black)) && !self.contains(&format!("{}v{}", black, white)) {
+                self.solve(&canonical_name(&child))?;
+            }
+        }
+
+        let count = pieces.len();
+        let mut transitions : Vec<Option<Transition>> = (0..Solver::key(count, count + 1, 0, 0)).map(|_| None).collect();
+        for (key, child) in Solver::successors(&pieces) {
+            let child_pieces : Vec<Piece> = child.iter().map(|(_, piece)| *piece).collect();
+            let (white, black) = (side_name(&child_pieces, true), side_name(&child_pieces, false));
+            let (ending, flip) = match self.names.get(&format!("{}v{}", white, black)) {
+                Some(ending) => (*ending, false),
+                None => (self.names[&format!("{}v{}", black, white)], true),
+            };
+            let mut used = vec![false; child.len()];
+            let mut slots = Vec::new();
+            for piece in self.endings[ending].pieces.iter() {
+                let i = (0..child.len()).find(|i| !used[*i] && child[*i].1.kind == piece.kind && (child[*i].1.white != flip) == piece.white).unwrap();
+                used[i] = true;
+                slots.push(child[i].0);
+            }
+            transitions[key] = Some(Transition{ ending, flip, slots });
+        }
+
+        // Pawn moves lead forward, so positions with the pawns further on are solved first
+        let pawn_slots : Vec<usize> = (0..count).filter(|slot| pieces[*slot].kind == PAWN).collect();
+        let other_slots : Vec<usize> = (0..count).filter(|slot| pieces[*slot].kind != PAWN).collect();
+        let mut placements : Vec<Vec<usize>> = vec![Vec::new()];
+        for _ in &pawn_slots {
+            placements = placements.iter().flat_map(|placement| {
+                (8..56).filter(move |square| !placement.contains(square)).map(move |square| {
+                    let mut longer = placement.clone();
+                    longer.push(square);
+                    longer
+                })
+            }).collect();
+        }
+        let progress = |placement : &Vec<usize>| -> usize {
+            placement.iter().zip(pawn_slots.iter()).map(|(square, slot)| if pieces[*slot].white { rank_of(*square) } else { 7 - rank_of(*square) }).sum()
+        };
+        placements.sort_by_key(|placement| std::cmp::Reverse(progress(placement)));
+
+        let mut values = vec![INVALID; 2 << (6 * count)];
+        let mut moves_left = vec![0u8; values.len()];
+        let mut zeroing = vec![NO_ZEROING; values.len()];
+        let mut list = Vec::new();
+        let mut squares = vec![0; count];
+        for placement in placements {
+            for (square, slot) in placement.iter().zip(pawn_slots.iter()) {
+                squares[*slot] = *square;
+            }
+
+            // Mates, stalemates and what the captures and pawn moves lead to
+            let (mut mated, mut decided) = (Vec::new(), Vec::new());
+            for combination in 0..2usize << (6 * other_slots.len()) {
+                for (i, slot) in other_slots.iter().enumerate() {
+                    squares[*slot] = (combination >> (6 * i)) & 63;
+                }
+                let black_to_move = combination >> (6 * other_slots.len()) != 0;
+                let white = !black_to_move;
+                let position = index(&squares, black_to_move);
+                let occupied = squares.iter().fold(0u64, |bits, square| bits | 1 << square);
+                if occupied.count_ones() as usize != count || self.in_check(&pieces, &squares, !white) {
+                    continue;
+                }
+                self.moves(&pieces, &squares, white, &mut list);
+                let (mut legal, mut quiet, mut best) = (0, 0, NO_ZEROING);
+                for amove in list.iter() {
+                    let mut after = squares.clone();
+                    after[amove.slot] = amove.to;
+                    if let Some(captured) = amove.captured {
+                        after[captured] = 64;
+                    }
+                    if self.in_check(&pieces, &after, white) {
+                        continue;
+                    }
+                    legal += 1;
+                    if amove.captured.is_none() && pieces[amove.slot].kind != PAWN {
+                        quiet += 1;
+                        continue;
+                    }
+                    let value = if amove.captured.is_none() && amove.promotion == 0 {
+                        self.push_value(&pieces, &values, &squares, &after, amove, &transitions)
+                    } else {
+                        let key = Solver::key(count, amove.captured.unwrap_or(count), if amove.promotion != 0 { amove.slot } else { count }, amove.promotion);
+                        -self.successor_value(transitions[key].as_ref().unwrap(), &after, black_to_move)
+                    };
+                    best = best.max(value);
+                }
+                values[position] = if legal == 0 {
+                    if self.in_check(&pieces, &squares, white) { -1 } else { 0 }
+                } else if best == 2 {
+                    1
+                } else if quiet == 0 {
+                    if best == -2 { -1 } else { 0 }
+                } else {
+                    0
+                };
+                moves_left[position] = quiet;
+                zeroing[position] = best;
+                if legal == 0 && values[position] != 0 {
+                    mated.push(position);
+                } else if values[position] != 0 {
+                    decided.push(position);
+                }
+            }
+
+            // The rest one ply at a time from the decided positions back to the
+            // moves leading there: a loss makes its predecessors wins, a win
+            // makes a predecessor lost once all its other moves are wins too.
+            // Mating counts like a zeroing move, so mates go one round early.
+            let mut plies = 0;
+            let mut solved = mated;
+            let mut before = squares.clone();
+            while !solved.is_empty() || plies == 0 {
+                let mut next = Vec::new();
+                for position in solved {
+                    let black_to_move = position >> (6 * count) != 0;
+                    for (slot, square) in before.iter_mut().enumerate() {
+                        *square = (position >> (6 * slot)) & 63;
+                    }
+                    let occupied = before.iter().fold(0u64, |bits, square| bits | 1 << square);
+                    for slot in other_slots.iter().copied() {
+                        if pieces[slot].white != black_to_move {
+                            continue;
+                        }
+                        let to = before[slot];
+                        let mut origins = self.attacks(pieces[slot], to, occupied) & !occupied;
+                        while origins != 0 {
+                            before[slot] = origins.trailing_zeros() as usize;
+                            origins &= origins - 1;
+                            let previous = index(&before, !black_to_move);
+                            if values[previous] != 0 {
+                                continue;
+                            }
+                            if values[position] < 0 {
+                                values[previous] = plies + 1;
+                                next.push(previous);
+                            } else {
+                                moves_left[previous] -= 1;
+                                if moves_left[previous] == 0 && zeroing[previous] != 0 && zeroing[previous] != 2 {
+                                    values[previous] = -(plies + 1);
+                                    next.push(previous);
+                                }
+                            }
+                        }
+                        before[slot] = to;
+                    }
+                }
+                if plies == 0 {
+                    next.append(&mut decided);
+                }
+                solved = next;
+                plies += 1;
+            }
+        }
+
+        if values.iter().any(|value| *value != INVALID && value.abs() > 100) {
+            return Err(format!("{}: some results depend on the fifty move rule", name));
+        }
+        self.names.insert(name.to_string(), self.endings.len());
+        self.endings.push(Ending{ pieces, values });
+        Ok(())
+    }
+
+    // What a capture or promotion leads to, for the side to move after it
+    fn successor_value(& self, transition : &Transition, after : &[usize], black_to_move_before : bool) -> i8 {
+        let flip = if transition.flip { 56 } else { 0 };
+        let squares : Vec<usize> = transition.slots.iter().map(|slot| after[*slot] ^ flip).collect();
+        let ending = &self.endings[transition.ending];
+        wdl(ending.values[index(&squares, !black_to_move_before ^ transition.flip)])
+    }
+
+    // A pawn push for the side that makes it. After a double push the other
+    // side may have en passant captures, which the tables leave out.
+    fn push_value(& self, pieces : &[Piece], values : &[i16], squares : &[usize], after : &[usize], amove : &Move, transitions : &[Option<Transition>]) -> i8 {
+        let white = pieces[amove.slot].white;
+        let reply = wdl(values[index(after, white)]);
+        if amove.to.abs_diff(squares[amove.slot]) != 16 {
+            return -reply;
+        }
+        let count = pieces.len();
+        let passed = (amove.to + squares[amove.slot]) / 2;
+        let mut best = NO_ZEROING;
+        for slot in 0..count {
+            if pieces[slot].kind != PAWN || pieces[slot].white == white || rank_of(after[slot]) != rank_of(amove.to)
+                || file_of(after[slot]).abs_diff(file_of(amove.to)) != 1 {
+                continue;
+            }
+            let mut captured = after.to_vec();
+            captured[slot] = passed;
+            captured[amove.slot] = 64;
+            if self.in_check(pieces, &captured, !white) {
+                continue;
+            }
+            let key = Solver::key(count, amove.slot, count, 0);
+            best = best.max(-self.successor_value(transitions[key].as_ref().unwrap(), &captured, white));
+        }
+        if best == NO_ZEROING {
+            return -reply;
+        }
+        if self.has_legal_move(pieces, after, !white) {
+            best = best.max(reply);
+        }
+        -best
+    }
+
+    // The distance to zeroing of a solved ending's position, the squares given in
+    // the order of its name: white king, white pieces, black king, black pieces.
+    // None for positions that cannot occur.
+    pub fn value(& self, name : &str, squares : &[usize], black_to_move : bool) -> Option<i32> {
+        let ending = &self.endings[*self.names.get(name)?];
+        let white_count = ending.pieces.iter().filter(|piece| piece.white).count();
+        let mut by_slot = vec![0; squares.len()];
+        for (i, square) in squares.iter().enumerate() {
+            let slot = if i == 0 { 0 } else if i < white_count { i + 1 } else if i == white_count { 1 } else { i };
+            by_slot[slot] = *square;
+        }
+        match ending.values[index(&by_slot, black_to_move)] {
+            INVALID => None,
+            value => Some(value as i32),
+        }
+    }
+
+    // Writes NAME.rtbw, and NAME.rtbz when dtz is set, for a solved ending.
+    // Returns the paths written.
+    pub fn write(& self, name : &str, dir : &str, dtz : bool) -> Result<Vec<String>, String> {
+        if !self.contains(name) {
+            return Err(format!("{} is not solved", name));
+        }
+        let mut paths = Vec::new();
+        for (extension, table_dtz) in [("rtbw", false), ("rtbz", true)].iter() {
+            if *table_dtz && !dtz {
+                continue;
+            }
+            let value = |squares : &[usize], black_to_move : bool| {
+                self.value(name, squares, black_to_move).map(|value| if *table_dtz { value } else { wdl(value as i16) as i32 })
+            };
+            let bytes = syzygy_writer::write_table(name, *table_dtz, &value)?;
+            let path = Path::new(dir).join(format!("{}.{}", name, extension));
+            fs::write(&path, bytes).map_err(|error| format!("{}: {}", path.to_string_lossy(), error))?;
+            paths.push(path.to_string_lossy().to_string());
+        }
+        Ok(paths)
+    }
+}
@@ -0,0 +1,191 @@
+use board;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+fn piece_letter(piece : u8) -> &'static str {
+    match board::clear_piece_color(piece) {
+        board::KING => "K",
+        board::QUEEN => "Q",
+        board::ROOK => "R",
+        board::BISHOP => "B",
+        board::KNIGHT => "N",
+        _ => "",
+    }
+}
+
+fn letter_to_piece(letter : char) -> Option<u8> {
+    match letter {
+        'K' => Some(board::KING),
+        'Q' => Some(board::QUEEN),
+        'R' => Some(board::ROOK),
+        'B' => Some(board::BISHOP),
+        'N' => Some(board::KNIGHT),
+        _ => None,
+    }
+}
+
+fn col_to_letter(col : usize) -> char {
+    (b'a' + col as u8) as char
+}
+
+fn row_to_digit(row : usize) -> char {
+    (b'8' - row as u8) as char
+}
+
+// Must be called before the move is made, the board is left as it was found
+pub fn move_to_san(board : &mut board::ChessBoard, amove : ((usize, usize),(usize, usize, u8))) -> String {
+    let source = amove.0;
+    let dest = amove.1;
+    let piece = board.board[source.0][source.1];
+    let mut san = String::new();
+
+    if board::clear_piece_color(piece) == board::KING && source.1.abs_diff(dest.1) > 1 {
+        if dest.1 > source.1 {
+            san.push_str("O-O");
+        } else {
+            san.push_str("O-O-O");
+        }
+    } else {
+        let is_capture = board.board[dest.0][dest.1] != 0b0000 || source.1 != dest.1 && board::clear_piece_color(piece) == board::PAWN;
+        if board::clear_piece_color(piece) == board::PAWN {
+            if is_capture {
+                san.push(col_to_letter(source.1));
+            }
+        } else {
+            san.push_str(piece_letter(piece));
+
+            // Disambiguate against other pieces of the same kind reaching the same square
+            let mut ambiguous = false;
+            let mut same_file = false;
+            let mut same_rank = false;
+            for other in board.get_legal_moves() {
+                if other.0 != source && (other.1.0, other.1.1) == (dest.0, dest.1) && board.board[other.0.0][other.0.1] == piece {
+                    ambiguous = true;
+                    if other.0.1 == source.1 {
+                        same_file = true;
+                    }
+                    if other.0.0 == source.0 {
+                        same_rank = true;
+                    }
+                }
+            }
+            if ambiguous {
+                if !same_file {
+                    san.push(col_to_letter(source.1));
+                } else if !same_rank {
+                    san.push(row_to_digit(source.0));
+                } else {
+                    san.push(col_to_letter(source.1));
+                    san.push(row_to_digit(source.0));
+                }
+            }
+        }
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&board::square_to_alphanumeric((dest.0, dest.1)));
+        if dest.2 != 0 {
+            san.push('=');
+            san.push_str(piece_letter(dest.2));
+        }
+    }
+
+    // Check and mate markers
+    let state = board.save_state();
+    match board.make(source, dest) {
+        None => {
+            // Illegal move, already unmade
+        },
+        Some(captured_piece) => {
+            if board.protagonist_in_check() {
+                if board.get_legal_moves().is_empty() {
+                    san.push('#');
+                } else {
+                    san.push('+');
+                }
+            }
+            board.unmake(source, dest, captured_piece);
+        }
+    }
+    board.restore_state(state);
+    san
+}
+
+// Accepts over-disambiguated moves, a missing '=' before the promotion piece and trailing annotations
+pub fn san_to_move(board : &mut board::ChessBoard, san : &str) -> Option<Move> {
+    let text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+    let text = text.trim_end_matches("e.p.").trim();
+    let legal_moves = board.get_legal_moves();
+
+    if text == "O-O" || text == "0-0" || text == "O-O-O" || text == "0-0-0" {
+        let kingside = text.len() == 3;
+        for amove in legal_moves {
+            let piece = board.board[amove.0.0][amove.0.1];
+            if board::clear_piece_color(piece) == board::KING && amove.0.1.abs_diff(amove.1.1) == 2 && (amove.1.1 > amove.0.1) == kingside {
+                return Some(amove);
+            }
+        }
+        return None;
+    }
+
+    let mut chars : Vec<char> = text.chars().filter(|c| *c != 'x' && *c != '-' && *c != ':').collect();
+    if chars.is_empty() {
+        return None;
+    }
+
+    let mut piece = board::PAWN;
+    if let Some(letter_piece) = letter_to_piece(chars[0]) {
+        piece = letter_piece;
+        chars.remove(0);
+    }
+
+    let mut promotion = 0b0000;
+    if chars.len() > 2 {
+        if let Some(promo_piece) = letter_to_piece(chars[chars.len() - 1]) {
+            promotion = promo_piece;
+            chars.pop();
+            if chars[chars.len() - 1] == '=' {
+                chars.pop();
+            }
+        }
+    }
+
+    if chars.len() < 2 {
+        return None;
+    }
+    let dest_text : String = chars[chars.len() - 2..].iter().collect();
+    let dest = board::alphanumeric_to_square(&dest_text)?;
+
+    let mut from_col = None;
+    let mut from_row = None;
+    for c in &chars[..chars.len() - 2] {
+        if ('a'..='h').contains(c) {
+            from_col = Some((*c as u8 - b'a') as usize);
+        } else if ('1'..='8').contains(c) {
+            from_row = Some((b'8' - *c as u8) as usize);
+        } else {
+            return None;
+        }
+    }
+
+    let mut found = None;
+    for amove in legal_moves {
+        let moving = board::clear_piece_color(board.board[amove.0.0][amove.0.1]);
+        if moving != piece || (amove.1.0, amove.1.1) != dest || amove.1.2 != promotion {
+            continue;
+        }
+        if from_col.is_some() && from_col != Some(amove.0.1) {
+            continue;
+        }
+        if from_row.is_some() && from_row != Some(amove.0.0) {
+            continue;
+        }
+        if found.is_some() {
+            // Ambiguous
+            return None;
+        }
+        found = Some(amove);
+    }
+    found
+}
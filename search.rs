@@ -0,0 +1,849 @@
+use board;
+use clock;
+use eval;
+use syzygy;
+use tablebase;
+use transposition;
+use zobrist;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+// Iterative deepening alpha-beta with a quiescence search at the leaves. Every
+// node keeps the line below it, so each iteration ends with a full principal
+// variation for every root move it reports. Null moves, late move reductions
+// and futility pruning thin out the tree, and aspiration windows narrow the
+// root search; SearchOptions turns each of them off.
+//
+// More threads search the same root in Lazy SMP fashion: each has its own
+// board and stacks, and they only meet in the shared transposition table,
+// where the helpers' results steer the main thread's search.
+//
+// Repetitions and the fifty-move rule are draws, scored by the contempt
+// option: above zero the engine expects to be the stronger side and steers
+// clear of them.
+
+pub const MAX_PLY : usize = 128;
+pub const MAX_DEPTH : u16 = 64;
+
+const INFINITY : i32 = eval::MATE_SCORE + 1;
+
+// Mates and the tablebase wins below them both count plies from the root
+const WIN_BOUND : i32 = syzygy::TB_WIN - eval::MAX_MATE_PLY;
+
+// Time and node limits are only checked this often
+const CHECK_INTERVAL : u64 = 1024;
+
+// Move ordering values of the pieces, by their colorless code
+const ORDER_VALUES : [i32; 8] = [0, 10, 9, 1, 5, 3, 0, 3];
+
+// Stands for the passed turn in the path
+const NULL_MOVE : Move = ((0, 0), (0, 0, 0));
+
+const NULL_MOVE_MIN_DEPTH : u16 = 3;
+const REVERSE_FUTILITY_MAX_DEPTH : u16 = 6;
+const REVERSE_FUTILITY_MARGIN : i32 = 90;
+// By remaining depth
+const FUTILITY_MARGINS : [i32; 4] = [0, 150, 300, 450];
+const LMR_MIN_DEPTH : u16 = 3;
+// Moves searched in full before the later ones are reduced
+const LMR_FULL_MOVES : usize = 3;
+const ASPIRATION_MIN_DEPTH : u16 = 5;
+const ASPIRATION_WINDOW : i32 = 25;
+const SINGULAR_MIN_DEPTH : u16 = 6;
+const SINGULAR_MARGIN : i32 = 50;
+
+// Each can be turned off to measure what it is worth
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility: bool,
+    pub futility: bool,
+    pub aspiration: bool,
+    pub check_extensions: bool,
+    pub singular_extensions: bool,
+    pub one_reply_extensions: bool,
+    // What a draw costs the side to move at the root, in centipawns
+    pub contempt: i32,
+}
+
+pub fn default_options() -> SearchOptions {
+    SearchOptions{ null_move: true, late_move_reductions: true, reverse_futility: true, futility: true, aspiration: true,
+                   check_extensions: true, singular_extensions: true, one_reply_extensions: true, contempt: 0 }
+}
+
+// Full width alpha-beta without extensions, for comparisons
+pub fn plain_alpha_beta() -> SearchOptions {
+    SearchOptions{ null_move: false, late_move_reductions: false, reverse_futility: false, futility: false, aspiration: false,
+                   check_extensions: false, singular_extensions: false, one_reply_extensions: false, contempt: 0 }
+}
+
+#[derive(Clone)]
+pub struct SearchLimits {
+    pub depth: u16,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // Number of best root moves to report, each with its own line
+    pub multipv: usize,
+    // Look for a mate in this many moves first
+    pub mate: Option<u16>,
+    // Playing on a clock, the time manager decides when to stop
+    pub clock: Option<clock::TimeBudget>,
+    // Pondering while set: no time or node limit applies until it is cleared
+    // on a ponderhit, when the clock starts and the search carries on
+    pub ponder: Option<Arc<AtomicBool>>,
+    // Hashes of the game's positions before the root, oldest first, for
+    // repetitions of them
+    pub history: Vec<u64>,
+}
+
+pub fn default_limits() -> SearchLimits {
+    SearchLimits{ depth: MAX_DEPTH, nodes: None, movetime: None, multipv: 1, mate: None, clock: None, ponder: None, history: Vec::new() }
+}
+
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    // From the side to move at the root's point of view
+    pub score: i32,
+    pub moves: Vec<Move>,
+}
+
+// One line of one finished iteration
+pub struct SearchInfo {
+    pub depth: u16,
+    // 1 for the best line
+    pub multipv: usize,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+pub struct SearchResult {
+    // Best first, from the deepest iteration finished
+    pub lines: Vec<PvLine>,
+    pub depth: u16,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    pub fn best_move(& self) -> Option<Move> {
+        self.lines.first().and_then(|line| line.moves.first().copied())
+    }
+
+    pub fn score(& self) -> Option<i32> {
+        self.lines.first().map(|line| line.score)
+    }
+}
+
+struct Searcher<'a> {
+    limits: &'a SearchLimits,
+    options: &'a SearchOptions,
+    stop: &'a AtomicBool,
+    table: &'a transposition::Table,
+    started: Instant,
+    // When the time limits started to run, later than the start on a ponderhit
+    timer: Instant,
+    pondering: bool,
+    clock: Option<clock::TimeManager>,
+    nodes: u64,
+    // Nodes of every thread, added to as each passes a check
+    all_nodes: &'a AtomicU64,
+    counted: u64,
+    stopped: bool,
+    // Extensions stop at twice the iteration's depth
+    root_depth: u16,
+    // pv[ply] is the best line found from the node at that ply
+    pv: Vec<Vec<Move>>,
+    // Moves from the root to the current node, and the previous iteration's
+    // line to try first while the search is still on it
+    path: Vec<Move>,
+    hint: Vec<Move>,
+    // The game's positions then the path's, the root at root_index
+    keys: Vec<u64>,
+    root_index: usize,
+    // Evaluation parameters as they were when the search started
+    params: eval::ParamsSnapshot,
+}
+
+fn is_capture(board : &board::ChessBoard, amove : Move) -> bool {
+    board.board[amove.1.0][amove.1.1] != 0b0000
+        || (board.ep == Some((amove.1.0, amove.1.1)) && board::clear_piece_color(board.board[amove.0.0][amove.0.1]) == board::PAWN)
+}
+
+// Captures by most valuable victim then least valuable attacker, promotions next
+fn order_score(board : &board::ChessBoard, amove : Move) -> i32 {
+    let attacker = ORDER_VALUES[board::clear_piece_color(board.board[amove.0.0][amove.0.1]) as usize];
+    let mut score = 0;
+    if is_capture(board, amove) {
+        let victim = board.board[amove.1.0][amove.1.1];
+        let victim_value = if victim == 0b0000 { 1 } else { ORDER_VALUES[board::clear_piece_color(victim) as usize] };
+        score += 1000 + 10 * victim_value - attacker;
+    }
+    if amove.1.2 != 0b0000 {
+        score += 100 * ORDER_VALUES[amove.1.2 as usize];
+    }
+    score
+}
+
+fn order_moves(board : &board::ChessBoard, moves : &mut [Move], first : Option<Move>) {
+    moves.sort_by_cached_key(|amove| {
+        if Some(*amove) == first {
+            i32::MIN
+        } else {
+            -order_score(board, *amove)
+        }
+    });
+}
+
+// Null moves are unsafe without pieces, where zugzwang is common
+fn has_pieces(board : &board::ChessBoard) -> bool {
+    board.board.iter().flatten().any(|piece| {
+        *piece != 0b0000 && piece & board::WHITE == board.protagonist
+            && board::clear_piece_color(*piece) != board::PAWN && board::clear_piece_color(*piece) != board::KING
+    })
+}
+
+// Mate and tablebase win scores are stored as distances from the node rather
+// than the root, so they stay right wherever the position comes up again
+fn score_to_table(score : i32, ply : usize) -> i32 {
+    if score > WIN_BOUND {
+        score + ply as i32
+    } else if score < -WIN_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score : i32, ply : usize) -> i32 {
+    if score > WIN_BOUND {
+        score - ply as i32
+    } else if score < -WIN_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// Reduction of a late quiet move, growing with depth and move number
+fn reduction(depth : u16, move_number : usize) -> u16 {
+    let reduction = ((depth as f64).ln() * (move_number as f64).ln() / 2.0) as u16;
+    reduction.max(1).min(depth - 2)
+}
+
+impl<'a> Searcher<'a> {
+    fn out_of_limits(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        if self.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        } else if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.all_nodes.fetch_add(self.nodes - self.counted, Ordering::Relaxed);
+            self.counted = self.nodes;
+            if !self.still_pondering() {
+                let out_of_nodes = self.limits.nodes.is_some_and(|nodes| self.all_nodes.load(Ordering::Relaxed) >= nodes);
+                let out_of_time = self.limits.movetime.is_some_and(|movetime| self.timer.elapsed() >= movetime)
+                    || self.clock.as_ref().is_some_and(|clock| clock.out_of_time());
+                self.stopped = out_of_nodes || out_of_time;
+            }
+        }
+        self.stopped
+    }
+
+    // Starts the clock on the first look after a ponderhit
+    fn still_pondering(&mut self) -> bool {
+        if self.pondering && !self.limits.ponder.as_ref().is_some_and(|ponder| ponder.load(Ordering::Relaxed)) {
+            self.pondering = false;
+            self.timer = Instant::now();
+            if let Some(clock) = self.clock.as_mut() {
+                clock.start();
+            }
+        }
+        self.pondering
+    }
+
+    // Including what other threads have not yet added
+    fn total_nodes(& self) -> u64 {
+        self.all_nodes.load(Ordering::Relaxed) + self.nodes - self.counted
+    }
+
+    fn draw_score(& self, ply : usize) -> i32 {
+        if ply.is_multiple_of(2) { -self.options.contempt } else { self.options.contempt }
+    }
+
+    // A position seen before in the search is taken for a draw, as whatever
+    // repeated it can repeat it again. One from before the root has to have
+    // been seen twice. Captures, pawn moves and null moves end the search
+    // for it.
+    fn is_repetition(& self, key : u64, board : &board::ChessBoard, ply : usize) -> bool {
+        let since_null = self.path.iter().rev().position(|amove| *amove == NULL_MOVE).unwrap_or(self.keys.len());
+        let reach = (board.halfmove_clock as usize).min(since_null).min(self.keys.len());
+        let mut seen = 0;
+        for distance in (4..=reach).step_by(2) {
+            if self.keys[self.keys.len() - distance] == key {
+                if distance <= ply {
+                    return true;
+                }
+                seen += 1;
+                if seen == 2 {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Unless the move reaching the hundredth ply mated
+    fn is_fifty_move_draw(& self, board : &mut board::ChessBoard) -> bool {
+        board.halfmove_clock >= 100 && (!board.protagonist_in_check() || !board.get_legal_moves().is_empty())
+    }
+
+    fn hint_move(& self, ply : usize) -> Option<Move> {
+        if ply < self.hint.len() && self.path[..ply] == self.hint[..ply] {
+            Some(self.hint[ply])
+        } else {
+            None
+        }
+    }
+
+    fn update_pv(&mut self, ply : usize, amove : Move) {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        head[ply].clear();
+        head[ply].push(amove);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+
+    fn alpha_beta(&mut self, board : &mut board::ChessBoard, depth : u16, mut alpha : i32, beta : i32, ply : usize) -> i32 {
+        self.pv[ply].clear();
+        let key = zobrist::hash(board);
+        self.keys.truncate(self.root_index + ply);
+        if self.is_repetition(key, board, ply) || self.is_fifty_move_draw(board) {
+            return self.draw_score(ply);
+        }
+        if depth == 0 || ply >= MAX_PLY {
+            return self.quiesce(board, alpha, beta, ply);
+        }
+        self.keys.push(key);
+        self.nodes += 1;
+        if self.out_of_limits() {
+            return 0;
+        }
+        if let Some(score) = tablebase::probe_score(board, ply as i32) {
+            return score;
+        }
+        if let Some(score) = syzygy::probe_score(board, ply as i32) {
+            return score;
+        }
+
+        let pv_node = beta - alpha > 1;
+        let entry = self.table.probe(key);
+        if let Some(entry) = entry {
+            let score = score_from_table(entry.score, ply);
+            let usable = match entry.bound {
+                transposition::Bound::Exact => true,
+                transposition::Bound::Lower => score >= beta,
+                transposition::Bound::Upper => score <= alpha,
+            };
+            // An exact score at a PV node is searched again, so the line below
+            // it stays whole; a bound outside the window leaves no line anyway
+            let cuts = !pv_node || entry.bound != transposition::Bound::Exact;
+            if usable && cuts && entry.depth >= depth {
+                return score;
+            }
+        }
+
+        let in_check = board.protagonist_in_check();
+        let static_eval = if in_check || pv_node { None } else { Some(eval::eval_snapshot(&self.params, board)) };
+
+        if let Some(static_eval) = static_eval {
+            // So far above beta that a shallow search will not bring it back
+            let margin = REVERSE_FUTILITY_MARGIN * depth as i32;
+            if self.options.reverse_futility && depth <= REVERSE_FUTILITY_MAX_DEPTH && !eval::is_mate_score(beta) && static_eval - margin >= beta {
+                return static_eval - margin;
+            }
+
+            // Still at least beta after passing the turn
+            if self.options.null_move && depth >= NULL_MOVE_MIN_DEPTH && static_eval >= beta && self.path.last() != Some(&NULL_MOVE) && has_pieces(board) {
+                let reduced = depth.saturating_sub(3 + depth / 6);
+                let state = board.save_state();
+                board.make_null();
+                self.path.push(NULL_MOVE);
+                let score = -self.alpha_beta(board, reduced, -beta, -beta + 1, ply + 1);
+                self.path.pop();
+                board.unmake_null();
+                board.restore_state(state);
+                if self.stopped {
+                    return 0;
+                }
+                if score >= beta {
+                    return if eval::is_mate_score(score) { beta } else { score };
+                }
+            }
+        }
+        // Quiet moves that cannot lift the score to alpha
+        let futile = self.options.futility && (depth as usize) < FUTILITY_MARGINS.len() && !eval::is_mate_score(alpha)
+            && static_eval.is_some_and(|static_eval| static_eval + FUTILITY_MARGINS[depth as usize] <= alpha);
+
+        let can_extend = ply < 2 * self.root_depth as usize;
+        let hint = self.hint_move(ply).or(entry.and_then(|entry| entry.amove));
+        // Only the table's move, failing high at nearly this depth, can be singular
+        let singular_candidate = entry.filter(|entry| entry.bound == transposition::Bound::Lower && entry.depth + 3 >= depth)
+            .and_then(|entry| entry.amove.map(|amove| (amove, score_from_table(entry.score, ply))));
+        let singular_move = match singular_candidate {
+            Some((candidate, score)) if self.options.singular_extensions && can_extend && depth >= SINGULAR_MIN_DEPTH
+                && self.singular(board, candidate, score, depth, ply) => Some(candidate),
+            _ => None,
+        };
+        if self.stopped {
+            return 0;
+        }
+
+        let mut moves = board.get_all_moves();
+        order_moves(board, &mut moves, hint);
+        let mut legal_moves = 0;
+        let mut first_move = None; // the first legal move, and whether it was extended
+        let mut cut_off = false;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let original_alpha = alpha;
+        for amove in moves {
+            let quiet = amove.1.2 == 0b0000 && !is_capture(board, amove);
+            let state = board.save_state();
+            let mut score = -INFINITY;
+            match board.make(amove.0, amove.1) {
+                None => {
+                    // Illegal move, already unmade
+                },
+                Some(captured_piece) => {
+                    legal_moves += 1;
+                    let gives_check = board.protagonist_in_check();
+                    let calm = quiet && !in_check && !gives_check;
+                    let extended = singular_move == Some(amove) || (self.options.check_extensions && can_extend && gives_check);
+                    let new_depth = if extended { depth } else { depth - 1 };
+                    if legal_moves == 1 {
+                        first_move = Some((amove, extended));
+                    }
+                    self.path.push(amove);
+                    if futile && calm && legal_moves > 1 {
+                        best = best.max(static_eval.unwrap());
+                    } else if self.options.late_move_reductions && calm && !extended && depth >= LMR_MIN_DEPTH && legal_moves > LMR_FULL_MOVES {
+                        let reduced = depth - 1 - reduction(depth, legal_moves);
+                        score = -self.alpha_beta(board, reduced, -alpha - 1, -alpha, ply + 1);
+                        if score > alpha {
+                            score = -self.alpha_beta(board, new_depth, -beta, -alpha, ply + 1);
+                        }
+                    } else {
+                        score = -self.alpha_beta(board, new_depth, -beta, -alpha, ply + 1);
+                    }
+                    self.path.pop();
+                    board.unmake(amove.0, amove.1, captured_piece);
+                }
+            }
+            board.restore_state(state);
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some(amove);
+                    self.update_pv(ply, amove);
+                    if score >= beta {
+                        cut_off = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        // The only way out of check is known once every move was tried, and
+        // then searched again a ply deeper
+        if let Some((amove, false)) = first_move {
+            if self.options.one_reply_extensions && can_extend && in_check && legal_moves == 1 && !cut_off {
+                let score = self.search_move(board, amove, depth, original_alpha, beta, ply).unwrap();
+                if self.stopped {
+                    return 0;
+                }
+                best = score;
+                best_move = None;
+                if score > original_alpha {
+                    best_move = Some(amove);
+                    self.update_pv(ply, amove);
+                }
+            }
+        }
+
+        if legal_moves == 0 {
+            return if in_check { eval::mated_in(ply as i32) } else { self.draw_score(ply) };
+        }
+        let bound = if best >= beta {
+            transposition::Bound::Lower
+        } else if best > original_alpha {
+            transposition::Bound::Exact
+        } else {
+            transposition::Bound::Upper
+        };
+        self.table.store(key, best_move, score_to_table(best, ply), depth, bound);
+        best
+    }
+
+    // The move is singular when, searched at half depth, every other move
+    // falls well short of the table's score for it, and then worth a deeper look
+    fn singular(&mut self, board : &mut board::ChessBoard, candidate : Move, score : i32, depth : u16, ply : usize) -> bool {
+        let moves = board.get_all_moves();
+        if eval::is_mate_score(score) || !moves.contains(&candidate) {
+            return false;
+        }
+        let shallow = depth / 2;
+        let singular_beta = score - SINGULAR_MARGIN;
+        for amove in moves.into_iter().filter(|amove| *amove != candidate) {
+            if let Some(score) = self.search_move(board, amove, shallow, singular_beta - 1, singular_beta, ply) {
+                if self.stopped || score >= singular_beta {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // Score of one move searched to the depth given, None if it is illegal
+    fn search_move(&mut self, board : &mut board::ChessBoard, amove : Move, depth : u16, alpha : i32, beta : i32, ply : usize) -> Option<i32> {
+        let state = board.save_state();
+        let mut score = None;
+        if let Some(captured_piece) = board.make(amove.0, amove.1) {
+            self.path.push(amove);
+            score = Some(-self.alpha_beta(board, depth, -beta, -alpha, ply + 1));
+            self.path.pop();
+            board.unmake(amove.0, amove.1, captured_piece);
+        }
+        board.restore_state(state);
+        score
+    }
+
+    // Captures and promotions only, the side to move can stand pat
+    fn quiesce(&mut self, board : &mut board::ChessBoard, mut alpha : i32, beta : i32, ply : usize) -> i32 {
+        self.pv[ply].clear();
+        self.nodes += 1;
+        if self.out_of_limits() {
+            return 0;
+        }
+        let stand_pat = eval::eval_snapshot(&self.params, board);
+        if ply >= MAX_PLY || stand_pat >= beta {
+            return stand_pat;
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+
+        let mut moves : Vec<Move> = board.get_all_moves().into_iter()
+            .filter(|amove| amove.1.2 != 0b0000 || is_capture(board, *amove)).collect();
+        order_moves(board, &mut moves, None);
+        let mut best = stand_pat;
+        for amove in moves {
+            let state = board.save_state();
+            let mut score = -INFINITY;
+            if let Some(captured_piece) = board.make(amove.0, amove.1) {
+                score = -self.quiesce(board, -beta, -alpha, ply + 1);
+                board.unmake(amove.0, amove.1, captured_piece);
+            }
+            board.restore_state(state);
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, amove);
+                    if score >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    // Best of the root moves not excluded, with its line, failing soft outside the
+    // (alpha, beta) window. None if stopped before it was known, or when every move
+    // is excluded.
+    fn search_root(&mut self, board : &mut board::ChessBoard, depth : u16, window : (i32, i32), root_moves : &[Move],
+                   excluded : &[Move], previous : &[PvLine]) -> Option<PvLine> {
+        let (mut alpha, beta) = window;
+        let mut best : Option<PvLine> = None;
+        for amove in root_moves {
+            if excluded.contains(amove) {
+                continue;
+            }
+            self.hint = previous.iter().find(|line| line.moves.first() == Some(amove)).map_or(vec![*amove], |line| line.moves.clone());
+            let state = board.save_state();
+            let captured_piece = board.make(amove.0, amove.1).unwrap();
+            self.path.push(*amove);
+            let score = -self.alpha_beta(board, depth - 1, -beta, -alpha, 1);
+            self.path.pop();
+            board.unmake(amove.0, amove.1, captured_piece);
+            board.restore_state(state);
+            if self.stopped {
+                return None;
+            }
+            if best.as_ref().is_none_or(|line| score > line.score) {
+                let mut moves = vec![*amove];
+                moves.extend_from_slice(&self.pv[1]);
+                best = Some(PvLine{ score, moves });
+                alpha = alpha.max(score);
+                if score >= beta {
+                    break;
+                }
+            }
+        }
+        best
+    }
+
+    // Starts in a narrow window around the score expected from the last
+    // iteration, widening it on the side it failed until the score is inside
+    fn search_line(&mut self, board : &mut board::ChessBoard, depth : u16, expected : Option<i32>, root_moves : &[Move],
+                   excluded : &[Move], previous : &[PvLine]) -> Option<PvLine> {
+        self.root_depth = depth;
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match expected {
+            Some(score) if self.options.aspiration && depth >= ASPIRATION_MIN_DEPTH && !eval::is_mate_score(score) => (score - delta, score + delta),
+            _ => (-INFINITY, INFINITY),
+        };
+        loop {
+            let line = self.search_root(board, depth, (alpha, beta), root_moves, excluded, previous)?;
+            delta *= 2;
+            if line.score <= alpha && alpha > -INFINITY {
+                alpha = if delta > ASPIRATION_WINDOW * 16 { -INFINITY } else { (line.score - delta).max(-INFINITY) };
+            } else if line.score >= beta && beta < INFINITY {
+                beta = if delta > ASPIRATION_WINDOW * 16 { INFINITY } else { (line.score + delta).min(INFINITY) };
+            } else {
+                return Some(line);
+            }
+        }
+    }
+}
+
+// The root moves that keep the result the tables give, None when the root is
+// not covered. The search only sees wins and losses inside the tables after
+// a capture or pawn move, so at the root the distance to zeroing decides.
+fn table_root_moves(board : &mut board::ChessBoard) -> Option<Vec<Move>> {
+    match syzygy::filter_root_moves(board) {
+        Some(moves) if !moves.is_empty() => Some(moves),
+        _ => tablebase::probe_root(board).map(|(amove, _)| vec![amove]),
+    }
+}
+
+// Iterative deepening from the first depth given. An iteration cut short
+// only counts for the lines it finished.
+fn iterate<F : FnMut(&SearchInfo)>(searcher : &mut Searcher, board : &mut board::ChessBoard, first_depth : u16, mut on_info : F) -> SearchResult {
+    let limits = searcher.limits;
+    searcher.keys = limits.history.clone();
+    searcher.root_index = searcher.keys.len();
+    searcher.keys.push(zobrist::hash(board));
+    let mut root_moves = board.get_legal_moves();
+    if let Some(kept) = table_root_moves(board) {
+        root_moves.retain(|amove| kept.contains(amove));
+    }
+    order_moves(board, &mut root_moves, None);
+    let mut result = SearchResult{ lines: Vec::new(), depth: 0, nodes: 0 };
+    let wanted = limits.multipv.max(1).min(root_moves.len());
+
+    for depth in first_depth.min(limits.depth.max(1))..=limits.depth.max(1) {
+        let mut lines : Vec<PvLine> = Vec::new();
+        for index in 0..wanted {
+            let excluded : Vec<Move> = lines.iter().map(|line| line.moves[0]).collect();
+            let expected = result.lines.get(index).map(|line| line.score);
+            let line = match searcher.search_line(board, depth, expected, &root_moves, &excluded, &result.lines) {
+                Some(line) => line,
+                None => break,
+            };
+            on_info(&SearchInfo{ depth, multipv: index + 1, score: line.score, pv: line.moves.clone(),
+                                 nodes: searcher.total_nodes(), elapsed: searcher.started.elapsed() });
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            break;
+        }
+        // Lines the cut short iteration did not get to keep their older results
+        for line in &result.lines {
+            if lines.len() < wanted && !lines.iter().any(|other| other.moves[0] == line.moves[0]) {
+                lines.push(line.clone());
+            }
+        }
+        root_moves.sort_by_key(|amove| lines.iter().position(|line| line.moves[0] == *amove).unwrap_or(wanted));
+        let best = (lines[0].moves[0], lines[0].score);
+        result.lines = lines;
+        result.depth = depth;
+        if searcher.stopped {
+            break;
+        }
+        let legal_moves = root_moves.len();
+        let pondering = searcher.still_pondering();
+        if let Some(clock) = searcher.clock.as_mut() {
+            if !clock.keep_going(depth, best.0, best.1, legal_moves) && !pondering {
+                break;
+            }
+        }
+    }
+
+    // Stopped before the first iteration finished
+    if result.lines.is_empty() && !root_moves.is_empty() {
+        result.lines.push(PvLine{ score: 0, moves: vec![root_moves[0]] });
+    }
+    result.nodes = searcher.nodes;
+    result
+}
+
+fn new_searcher<'a>(limits : &'a SearchLimits, options : &'a SearchOptions, stop : &'a AtomicBool, table : &'a transposition::Table,
+                    all_nodes : &'a AtomicU64) -> Searcher<'a> {
+    let pondering = limits.ponder.as_ref().is_some_and(|ponder| ponder.load(Ordering::Relaxed));
+    Searcher{ limits, options, stop, table, started: Instant::now(), timer: Instant::now(), pondering,
+              clock: limits.clock.map(clock::TimeManager::new), nodes: 0, all_nodes, counted: 0,
+              stopped: false, root_depth: 0, pv: vec![Vec::new(); MAX_PLY + 1], path: Vec::new(), hint: Vec::new(), keys: Vec::new(), root_index: 0,
+              params: eval::params_snapshot() }
+}
+
+// Searches until the depth or another limit is reached, or stop is set, and
+// reports every line of every finished iteration. One thread and a table of
+// its own, so the same search always gives the same result.
+pub fn search<F : FnMut(&SearchInfo)>(board : &mut board::ChessBoard, limits : &SearchLimits, options : &SearchOptions, stop : &AtomicBool,
+                                       on_info : F) -> SearchResult {
+    let table = transposition::Table::new(transposition::DEFAULT_MEGABYTES);
+    parallel_search(board, limits, options, 1, &table, stop, on_info)
+}
+
+// The search on this many threads sharing the table. The calling thread
+// reports and decides when to stop; the helpers search the same root on
+// copies of the board, every other one a ply deeper, until it is done.
+pub fn parallel_search<F : FnMut(&SearchInfo)>(board : &mut board::ChessBoard, limits : &SearchLimits, options : &SearchOptions, threads : usize,
+                                                table : &transposition::Table, stop : &AtomicBool, on_info : F) -> SearchResult {
+    table.new_search();
+    let all_nodes = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
+    let mut helper_limits = default_limits();
+    helper_limits.depth = limits.depth;
+    helper_limits.history = limits.history.clone();
+    thread::scope(|scope| {
+        let helpers : Vec<thread::ScopedJoinHandle<u64>> = (1..threads.max(1)).map(|id| {
+            let mut board = board.clone();
+            let (helper_limits, done, all_nodes) = (&helper_limits, &done, &all_nodes);
+            scope.spawn(move || {
+                let mut searcher = new_searcher(helper_limits, options, done, table, all_nodes);
+                iterate(&mut searcher, &mut board, 1 + id as u16 % 2, |_| {}).nodes
+            })
+        }).collect();
+        let mut searcher = new_searcher(limits, options, stop, table, &all_nodes);
+        let mut result = iterate(&mut searcher, board, 1, on_info);
+        done.store(true, Ordering::Relaxed);
+        for helper in helpers {
+            result.nodes += helper.join().unwrap();
+        }
+        result
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MateResult {
+    // The mating line, the defence holding out longest at every turn
+    Mate(Vec<Move>),
+    // No mate in that many moves, or none by checks when only checks were tried
+    NoMate,
+    Stopped,
+}
+
+// Proves or disproves a forced mate for the side to move, trying every
+// defence against every attacking move
+struct MateSearcher<'a> {
+    checks_only: bool,
+    stop: &'a AtomicBool,
+    nodes: u64,
+    stopped: bool,
+}
+
+impl<'a> MateSearcher<'a> {
+    // A line mating in at most moves moves, attacker to move
+    fn attack(&mut self, board : &mut board::ChessBoard, moves : u16) -> Option<Vec<Move>> {
+        self.nodes += 1;
+        if self.stopped || (self.nodes.is_multiple_of(CHECK_INTERVAL) && self.stop.load(Ordering::Relaxed)) {
+            self.stopped = true;
+            return None;
+        }
+        let mut candidates = board.get_all_moves();
+        order_moves(board, &mut candidates, None);
+        for amove in candidates {
+            let state = board.save_state();
+            let mut line = None;
+            if let Some(captured_piece) = board.make(amove.0, amove.1) {
+                // The last move has to give check anyway
+                if board.protagonist_in_check() || (!self.checks_only && moves > 1) {
+                    line = self.defend(board, moves);
+                }
+                board.unmake(amove.0, amove.1, captured_piece);
+            }
+            board.restore_state(state);
+            if let Some(mut line) = line {
+                line.insert(0, amove);
+                return Some(line);
+            }
+        }
+        None
+    }
+
+    // After the attacker's move, the longest defence if every reply still
+    // gets mated within the moves left
+    fn defend(&mut self, board : &mut board::ChessBoard, moves : u16) -> Option<Vec<Move>> {
+        let replies = board.get_legal_moves();
+        if replies.is_empty() {
+            return if board.protagonist_in_check() { Some(Vec::new()) } else { None };
+        }
+        if moves == 1 {
+            return None;
+        }
+        let mut longest : Option<Vec<Move>> = None;
+        for reply in replies {
+            let state = board.save_state();
+            let captured_piece = board.make(reply.0, reply.1).unwrap();
+            // Shortest mate after this reply
+            let mut line = None;
+            for left in 1..moves {
+                line = self.attack(board, left);
+                if line.is_some() || self.stopped {
+                    break;
+                }
+            }
+            board.unmake(reply.0, reply.1, captured_piece);
+            board.restore_state(state);
+            let mut line = line?;
+            line.insert(0, reply);
+            if longest.as_ref().is_none_or(|longest| line.len() > longest.len()) {
+                longest = Some(line);
+            }
+        }
+        longest
+    }
+}
+
+// Looks for the shortest forced mate in at most moves moves, optionally
+// trying only checking moves for the attacker
+pub fn mate_search(board : &mut board::ChessBoard, moves : u16, checks_only : bool, stop : &AtomicBool) -> MateResult {
+    let mut searcher = MateSearcher{ checks_only, stop, nodes: 0, stopped: false };
+    for depth in 1..=moves {
+        let line = searcher.attack(board, depth);
+        if searcher.stopped {
+            return MateResult::Stopped;
+        }
+        if let Some(line) = line {
+            return MateResult::Mate(line);
+        }
+    }
+    MateResult::NoMate
+}
@@ -0,0 +1,1035 @@
+use board;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+
+// Probing of Syzygy tables: WDL (.rtbw) for win/draw/loss under the fifty move
+// rule and DTZ (.rtbz) for the distance to the next capture or pawn move.
+// Squares here are numbered a1 = 0 to h8 = 63 as in the table files, and
+// pieces use the file codes: pawn 1 to king 6, black ones with 8 added.
+pub const WDL_MAGIC : [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+pub const DTZ_MAGIC : [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// Flags of the compressed data of a table
+const FLAG_STM : u8 = 1;
+pub const FLAG_MAPPED : u8 = 2;
+pub const FLAG_WIN_PLIES : u8 = 4;
+pub const FLAG_LOSS_PLIES : u8 = 8;
+const FLAG_WIDE : u8 = 16;
+pub const FLAG_SINGLE_VALUE : u8 = 128;
+
+// Root move ranks, wins inside the fifty move rule get the top rank
+pub const MAX_DTZ : i32 = 1 << 18;
+
+// Above any evaluation and below the mate scores
+pub const TB_WIN : i32 = 20000;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+// A cursed win is a win the fifty move rule turns into a draw, a blessed loss the other way round
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value : i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            1 => Wdl::CursedWin,
+            2 => Wdl::Win,
+            _ => Wdl::Draw,
+        }
+    }
+
+    fn value(self) -> i32 {
+        self as i32 - 2
+    }
+
+    fn negate(self) -> Wdl {
+        Wdl::from_value(-self.value())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ok,
+    ChangeStm, // a DTZ table only stores the other side to move
+    ZeroingBestMove, // the best move is a capture or pawn move, not stored
+}
+
+pub struct RootMove {
+    pub amove: Move,
+    pub dtz: i32,
+    pub rank: i32,
+}
+
+// Index tables shared by every table
+struct Indices {
+    binomial: [[u64; 64]; 7],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [u64; 64],
+    map_kk: [[u64; 64]; 10],
+    map_pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+static INDICES : OnceLock<Indices> = OnceLock::new();
+
+// Decompression data for one side to move and leading pawn file. Positions in
+// the file are byte offsets into the table.
+#[derive(Clone, Default)]
+pub struct PairsData {
+    flags: u8,
+    pieces: Vec<u8>,
+    group_len: Vec<usize>,
+    pub group_idx: Vec<u64>, // one more than group_len, the last is the table size
+    block_size: u64,
+    span: u64,
+    sparse_index_size: u64,
+    num_blocks: u64,
+    block_length_size: u64,
+    min_sym_len: u8, // the value itself for single value tables
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    map_idx: [usize; 4],
+}
+
+pub struct Table {
+    pub bytes: Vec<u8>,
+    dtz: bool,
+    symmetric: bool,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    piece_count: usize,
+    pawn_count: [usize; 2], // leading color first
+    pub items: [Vec<PairsData>; 2], // by side to move, then by leading pawn file
+    map: usize,
+}
+
+struct Syzygy {
+    files: HashMap<String, PathBuf>,
+    tables: HashMap<String, Option<Arc<Table>>>,
+    max_pieces: usize,
+}
+
+static SYZYGY : RwLock<Option<Syzygy>> = RwLock::new(None);
+
+pub fn rank_of(square : usize) -> usize {
+    square >> 3
+}
+
+fn file_of(square : usize) -> usize {
+    square & 7
+}
+
+// Negative below the a1-h8 diagonal, zero on it
+fn off_diagonal(square : usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+fn init_indices() -> Indices {
+    let mut indices = Indices{
+        binomial: [[0; 64]; 7],
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+        map_pawns: [0; 64],
+        lead_pawn_idx: [[0; 64]; 6],
+        lead_pawns_size: [[0; 4]; 6],
+    };
+
+    let mut code = 0;
+    for square in 0..64 {
+        if off_diagonal(square) < 0 {
+            indices.map_b1h1h7[square] = code;
+            code += 1;
+        }
+    }
+
+    // The a1-d1-d4 triangle, squares on the diagonal last
+    let mut triangle = [false; 64];
+    let mut diagonal = Vec::new();
+    code = 0;
+    for (square, in_triangle) in triangle.iter_mut().enumerate().take(28) {
+        if off_diagonal(square) < 0 && file_of(square) <= 3 {
+            indices.map_a1d1d4[square] = code;
+            *in_triangle = true;
+            code += 1;
+        } else if off_diagonal(square) == 0 && file_of(square) <= 3 {
+            diagonal.push(square);
+        }
+    }
+    for square in diagonal {
+        indices.map_a1d1d4[square] = code;
+        triangle[square] = true;
+        code += 1;
+    }
+
+    // The 462 legal placements of two kings with the first in the triangle. With the
+    // first on the diagonal the second is not above it, and both on the diagonal come last.
+    let mut both_on_diagonal = Vec::new();
+    code = 0;
+    for index in 0..10 {
+        for (first, in_triangle) in triangle.iter().enumerate().take(28) {
+            if !in_triangle || indices.map_a1d1d4[first] != index as u64 {
+                continue;
+            }
+            for second in 0..64 {
+                let touching = rank_of(first).abs_diff(rank_of(second)) <= 1 && file_of(first).abs_diff(file_of(second)) <= 1;
+                if touching || (off_diagonal(first) == 0 && off_diagonal(second) > 0) {
+                    continue;
+                }
+                if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                    both_on_diagonal.push((index, second));
+                } else {
+                    indices.map_kk[index][second] = code;
+                    code += 1;
+                }
+            }
+        }
+    }
+    for (index, second) in both_on_diagonal {
+        indices.map_kk[index][second] = code;
+        code += 1;
+    }
+
+    indices.binomial[0][0] = 1;
+    for n in 1..64 {
+        for k in 0..7 {
+            if k > n {
+                break;
+            }
+            indices.binomial[k][n] = if k > 0 { indices.binomial[k - 1][n - 1] } else { 0 }
+                + if k < n { indices.binomial[k][n - 1] } else { 0 };
+        }
+    }
+
+    // Pawn squares a2-h7 to 47..0, the leading pawn is the one with the highest
+    // value: nearest the edge, then on the lowest rank
+    let mut available : i64 = 47;
+    for lead_pawns in 1..=5 {
+        for file in 0..4 {
+            let mut index = 0;
+            for rank in 1..=6 {
+                let square = rank * 8 + file;
+                if lead_pawns == 1 {
+                    indices.map_pawns[square] = available as u64;
+                    indices.map_pawns[square ^ 7] = (available - 1) as u64;
+                    available -= 2;
+                }
+                indices.lead_pawn_idx[lead_pawns][square] = index;
+                index += indices.binomial[lead_pawns - 1][indices.map_pawns[square] as usize];
+            }
+            indices.lead_pawns_size[lead_pawns][file] = index;
+        }
+    }
+    indices
+}
+
+fn indices() -> &'static Indices {
+    INDICES.get_or_init(init_indices)
+}
+
+// Little and big endian reads, zero past the end of the file
+fn read_u16(bytes : &[u8], offset : usize) -> u16 {
+    bytes.get(offset..offset + 2).map_or(0, |value| u16::from_le_bytes([value[0], value[1]]))
+}
+
+fn read_u32(bytes : &[u8], offset : usize) -> u32 {
+    bytes.get(offset..offset + 4).map_or(0, |value| u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+fn read_u32_be(bytes : &[u8], offset : usize) -> u32 {
+    bytes.get(offset..offset + 4).map_or(0, |value| u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+fn read_byte(bytes : &[u8], offset : usize) -> u8 {
+    bytes.get(offset).copied().unwrap_or(0)
+}
+
+// Children of a pair symbol, 12 bits each
+fn btree(bytes : &[u8], btree : usize, symbol : usize) -> (usize, usize) {
+    let entry = btree + 3 * symbol;
+    let (first, second, third) = (read_byte(bytes, entry) as usize, read_byte(bytes, entry + 1) as usize, read_byte(bytes, entry + 2) as usize);
+    (((second & 0xf) << 8) | first, (third << 4) | (second >> 4))
+}
+
+// Number of values a symbol expands to, less one
+fn set_symlen(bytes : &[u8], d : &mut PairsData, symbol : usize, visited : &mut Vec<bool>) -> u8 {
+    visited[symbol] = true;
+    let (left, right) = btree(bytes, d.btree, symbol);
+    if right == 0xfff {
+        return 0;
+    }
+    if !visited[left] {
+        d.symlen[left] = set_symlen(bytes, d, left, visited);
+    }
+    if !visited[right] {
+        d.symlen[right] = set_symlen(bytes, d, right, visited);
+    }
+    d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1)
+}
+
+// Pieces are grouped for the index: the leading pawns or first pieces, the
+// other side's pawns, then runs of identical pieces. The order byte says in
+// which order the groups make up the index.
+fn set_groups(table : &Table, d : &mut PairsData, order : [u8; 2], file : usize) {
+    let indices = indices();
+    let mut first_len : i32 = if table.has_pawns { 0 } else if table.has_unique_pieces { 3 } else { 2 };
+    d.group_len = vec![1];
+    for piece in 1..table.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[piece] == d.pieces[piece - 1] {
+            *d.group_len.last_mut().unwrap() += 1;
+        } else {
+            d.group_len.push(1);
+        }
+    }
+    let groups = d.group_len.len();
+    d.group_idx = vec![0; groups + 1];
+
+    let both_pawns = table.has_pawns && table.pawn_count[1] > 0;
+    let mut next = if both_pawns { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+    let mut index : u64 = 1;
+    let mut k = 0;
+    while next < groups || k == order[0] || k == order[1] {
+        if k == order[0] {
+            d.group_idx[0] = index;
+            index *= if table.has_pawns {
+                indices.lead_pawns_size[d.group_len[0]][file]
+            } else if table.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] {
+            d.group_idx[1] = index;
+            index *= indices.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = index;
+            index *= indices.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[groups] = index;
+}
+
+// Reads the Huffman code of the compressed data, returns where the next one starts
+fn set_sizes(bytes : &[u8], d : &mut PairsData, mut data : usize) -> usize {
+    d.flags = read_byte(bytes, data);
+    data += 1;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        d.min_sym_len = read_byte(bytes, data);
+        return data + 1;
+    }
+
+    let size = *d.group_idx.last().unwrap();
+    d.block_size = 1 << read_byte(bytes, data);
+    d.span = 1 << read_byte(bytes, data + 1);
+    d.sparse_index_size = size.div_ceil(d.span);
+    let padding = read_byte(bytes, data + 2) as u64;
+    d.num_blocks = read_u32(bytes, data + 3) as u64;
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = read_byte(bytes, data + 7) as usize;
+    d.min_sym_len = read_byte(bytes, data + 8);
+    data += 9;
+    d.lowest_sym = data;
+
+    // Canonical Huffman code: base64[i] is the lowest code of length
+    // min_sym_len + i, left aligned in 64 bits
+    let lengths = (max_sym_len + 1).saturating_sub(d.min_sym_len as usize).max(1);
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        d.base64[i] = d.base64[i + 1].wrapping_add(read_u16(bytes, d.lowest_sym + 2 * i) as u64)
+            .wrapping_sub(read_u16(bytes, d.lowest_sym + 2 * (i + 1)) as u64) / 2;
+    }
+    for i in 0..lengths {
+        d.base64[i] = d.base64[i].checked_shl((64 - i - d.min_sym_len as usize) as u32).unwrap_or(0);
+    }
+    data += lengths * 2;
+
+    let symbols = read_u16(bytes, data) as usize;
+    data += 2;
+    d.btree = data;
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for symbol in 0..symbols {
+        if !visited[symbol] {
+            d.symlen[symbol] = set_symlen(bytes, d, symbol, &mut visited);
+        }
+    }
+    data + symbols * 3 + (symbols & 1)
+}
+
+// The value stored at an index
+pub fn decompress_pairs(bytes : &[u8], d : &PairsData, index : u64) -> i32 {
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        return d.min_sym_len as i32;
+    }
+
+    // The sparse index points near the index, then whole blocks are skipped
+    let k = (index / d.span) as usize;
+    let mut block = read_u32(bytes, d.sparse_index + 6 * k) as usize;
+    let mut offset = read_u16(bytes, d.sparse_index + 6 * k + 4) as i64;
+    offset += (index % d.span) as i64 - (d.span / 2) as i64;
+    let block_length = |block : usize| read_u16(bytes, d.block_length + 2 * block) as i64;
+    while offset < 0 {
+        block -= 1;
+        offset += block_length(block) + 1;
+    }
+    while offset > block_length(block) {
+        offset -= block_length(block) + 1;
+        block += 1;
+    }
+
+    // Read symbols until the one that covers the offset
+    let mut pointer = d.data + block * d.block_size as usize;
+    let mut buffer = ((read_u32_be(bytes, pointer) as u64) << 32) | read_u32_be(bytes, pointer + 4) as u64;
+    pointer += 8;
+    let mut buffer_size = 64;
+    let min_sym_len = d.min_sym_len as usize;
+    let mut symbol;
+    loop {
+        let mut length = 0;
+        while buffer < d.base64[length] {
+            length += 1;
+        }
+        symbol = ((buffer - d.base64[length]) >> (64 - length - min_sym_len)) as usize;
+        symbol += read_u16(bytes, d.lowest_sym + 2 * length) as usize;
+        if offset < d.symlen[symbol] as i64 + 1 {
+            break;
+        }
+        offset -= d.symlen[symbol] as i64 + 1;
+        length += min_sym_len;
+        buffer = buffer.checked_shl(length as u32).unwrap_or(0);
+        buffer_size -= length as i32;
+        if buffer_size <= 32 {
+            buffer_size += 32;
+            buffer |= (read_u32_be(bytes, pointer) as u64) << (64 - buffer_size);
+            pointer += 4;
+        }
+    }
+
+    // Pairs expand to adjacent values, walk down to the one at the offset
+    while d.symlen[symbol] != 0 {
+        let (left, right) = btree(bytes, d.btree, symbol);
+        if offset < d.symlen[left] as i64 + 1 {
+            symbol = left;
+        } else {
+            offset -= d.symlen[left] as i64 + 1;
+            symbol = right;
+        }
+    }
+    btree(bytes, d.btree, symbol).0 as i32
+}
+
+// DTZ tables can map their values through small tables, one for each result
+fn set_dtz_map(bytes : &[u8], table : &mut Table, mut data : usize, files : usize) -> usize {
+    table.map = data;
+    for file in 0..files {
+        let d = &mut table.items[0][file];
+        if d.flags & FLAG_MAPPED == 0 {
+            continue;
+        }
+        if d.flags & FLAG_WIDE != 0 {
+            data += data & 1;
+            for i in 0..4 {
+                d.map_idx[i] = (data - table.map) / 2 + 1;
+                data += 2 * read_u16(bytes, data) as usize + 2;
+            }
+        } else {
+            for i in 0..4 {
+                d.map_idx[i] = data - table.map + 1;
+                data += read_byte(bytes, data) as usize + 1;
+            }
+        }
+    }
+    data + (data & 1)
+}
+
+// Piece counts by file code, from a name like KRPvKR
+pub fn side_pieces(side : &str) -> Option<[usize; 7]> {
+    let mut counts = [0; 7];
+    for letter in side.chars() {
+        let code = match letter { 'P' => 1, 'N' => 2, 'B' => 3, 'R' => 4, 'Q' => 5, 'K' => 6, _ => return None };
+        counts[code] += 1;
+    }
+    Some(counts)
+}
+
+impl Table {
+    // The piece order and index groups of every side to move and file, read
+    // from the start of the file. Returns where the Huffman codes begin.
+    pub fn header(name : &str, bytes : &[u8], dtz : bool) -> Result<(Table, usize), String> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.len() < 5 || bytes[..4] != magic {
+            return Err("not a Syzygy table".to_string());
+        }
+        let sides : Vec<&str> = name.split('v').collect();
+        let (white, black) = match (sides.len(), sides.first().and_then(|side| side_pieces(side)), sides.get(1).and_then(|side| side_pieces(side))) {
+            (2, Some(white), Some(black)) => (white, black),
+            _ => return Err(format!("{}: not a material signature", name)),
+        };
+        // The leading pawns are those of the side with fewer, white on a tie
+        let white_leads = black[1] == 0 || (white[1] > 0 && black[1] >= white[1]);
+        let mut table = Table{
+            bytes: Vec::new(),
+            dtz,
+            symmetric: sides[0] == sides[1],
+            has_pawns: white[1] + black[1] > 0,
+            has_unique_pieces: (1..6).any(|code| white[code] == 1 || black[code] == 1),
+            piece_count: name.len() - 1,
+            pawn_count: if white_leads { [white[1], black[1]] } else { [black[1], white[1]] },
+            items: [Vec::new(), Vec::new()],
+            map: 0,
+        };
+
+        let mut data = 5; // the magic and a flags byte
+        let (sides, files) = (table.sides(), table.files());
+        let both_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        table.items = [vec![PairsData::default(); files], vec![PairsData::default(); files]];
+        for file in 0..files {
+            let first = read_byte(bytes, data);
+            let second = if both_pawns { read_byte(bytes, data + 1) } else { 0xff };
+            let orders = [[first & 0xf, if both_pawns { second & 0xf } else { 0xf }],
+                          [first >> 4, if both_pawns { second >> 4 } else { 0xf }]];
+            data += if both_pawns { 2 } else { 1 };
+            for _ in 0..table.piece_count {
+                let byte = read_byte(bytes, data);
+                table.items[0][file].pieces.push(byte & 0xf);
+                table.items[1][file].pieces.push(byte >> 4);
+                data += 1;
+            }
+            for (side, order) in orders.iter().enumerate().take(sides) {
+                let mut d = table.items[side][file].clone();
+                set_groups(&table, &mut d, *order, file);
+                table.items[side][file] = d;
+            }
+        }
+        Ok((table, data + (data & 1)))
+    }
+
+    pub fn new(name : &str, bytes : Vec<u8>, dtz : bool) -> Result<Table, String> {
+        let (mut table, mut data) = Table::header(name, &bytes, dtz)?;
+        let (sides, files) = (table.sides(), table.files());
+        for file in 0..files {
+            for side in 0..sides {
+                data = set_sizes(&bytes, &mut table.items[side][file], data);
+            }
+        }
+        if dtz {
+            data = set_dtz_map(&bytes, &mut table, data, files);
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                table.items[side][file].sparse_index = data;
+                data += table.items[side][file].sparse_index_size as usize * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                table.items[side][file].block_length = data;
+                data += table.items[side][file].block_length_size as usize * 2;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                data = (data + 0x3f) & !0x3f;
+                table.items[side][file].data = data;
+                data += (table.items[side][file].num_blocks * table.items[side][file].block_size) as usize;
+            }
+        }
+        if data > bytes.len() {
+            return Err(format!("{}: truncated table", name));
+        }
+        table.bytes = bytes;
+        Ok(table)
+    }
+
+    // WDL tables store both sides to move unless the sides have the same pieces
+    pub fn sides(& self) -> usize {
+        if !self.dtz && !self.symmetric { 2 } else { 1 }
+    }
+
+    // Pawn tables by the file of the leading pawn, a to d
+    fn files(& self) -> usize {
+        if self.has_pawns { 4 } else { 1 }
+    }
+
+    // DTZ values are in moves unless flagged as plies, wins and losses
+    // the fifty move rule spoils are always in moves
+    fn map_score(& self, file : usize, mut value : i32, wdl : Wdl) -> i32 {
+        if !self.dtz {
+            return value - 2;
+        }
+        let d = &self.items[0][file];
+        if d.flags & FLAG_MAPPED != 0 {
+            let index = d.map_idx[[1, 3, 0, 2, 0][(wdl.value() + 2) as usize]] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16(&self.bytes, self.map + 2 * index) as i32
+            } else {
+                read_byte(&self.bytes, self.map + index) as i32
+            };
+        }
+        if (wdl == Wdl::Win && d.flags & FLAG_WIN_PLIES == 0) || (wdl == Wdl::Loss && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss {
+            value *= 2;
+        }
+        value + 1
+    }
+
+    // The value for the board, black_stronger when the board's black has the
+    // table's first side
+    fn probe(& self, board : &board::ChessBoard, black_stronger : bool, wdl : Wdl) -> (i32, State) {
+        let (stm, file, index) = self.locate(&piece_codes(board), board.protagonist == board::BLACK, black_stronger);
+        if self.dtz {
+            let flags = self.items[0][file].flags;
+            if (flags & FLAG_STM) as usize != stm && (!self.symmetric || self.has_pawns) {
+                return (0, State::ChangeStm);
+            }
+        }
+        let d = &self.items[if self.dtz { 0 } else { stm }][file];
+        (self.map_score(file, decompress_pairs(&self.bytes, d, index), wdl), State::Ok)
+    }
+
+    // Where a position is stored: the side to move once the colors are
+    // flipped, the leading pawn's file and the index
+    pub fn locate(& self, codes : &[u8; 64], black_to_move : bool, black_stronger : bool) -> (usize, usize, u64) {
+        let indices = indices();
+        let flip = (self.symmetric && black_to_move) || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip as usize) ^ (black_to_move as usize);
+
+        let mut squares : Vec<usize> = Vec::new();
+        let mut pieces : Vec<u8> = Vec::new();
+        let mut lead_pawns = 0u64;
+        let mut file = 0;
+        if self.has_pawns {
+            let lead = self.items[0][0].pieces[0] ^ flip_color;
+            for (square, code) in codes.iter().enumerate() {
+                if *code == lead {
+                    squares.push(square ^ flip_squares);
+                    pieces.push(lead ^ flip_color);
+                    lead_pawns |= 1 << square;
+                }
+            }
+            let mut best = 0;
+            for index in 1..squares.len() {
+                if indices.map_pawns[squares[index]] > indices.map_pawns[squares[best]] {
+                    best = index;
+                }
+            }
+            squares.swap(0, best);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+        let lead_count = squares.len();
+
+        for (square, code) in codes.iter().enumerate() {
+            if *code != 0 && lead_pawns & (1 << square) == 0 {
+                squares.push(square ^ flip_squares);
+                pieces.push(code ^ flip_color);
+            }
+        }
+        let size = squares.len();
+        let d = &self.items[if self.dtz { 0 } else { stm }][file];
+
+        // Same piece order as the table
+        for i in lead_count..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The leading piece goes to the a-d files
+        if file_of(squares[0]) > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut index : u64;
+        if self.has_pawns {
+            index = indices.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|square| indices.map_pawns[*square]);
+            for (i, square) in squares.iter().enumerate().take(lead_count).skip(1) {
+                index += indices.binomial[i][indices.map_pawns[*square] as usize];
+            }
+        } else {
+            // Without pawns also to ranks 1-4, and below the a1-h8 diagonal for
+            // the first leading piece off it
+            if rank_of(squares[0]) > 3 {
+                for square in squares.iter_mut() {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_diagonal(squares[i]) == 0 {
+                    continue;
+                }
+                if off_diagonal(squares[i]) > 0 {
+                    for square in squares[i..].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let (first, second, third) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (second > first) as u64;
+                let adjust2 = (third > first) as u64 + (third > second) as u64;
+                index = if off_diagonal(first) != 0 {
+                    (indices.map_a1d1d4[first] * 63 + second as u64 - adjust1) * 62 + third as u64 - adjust2
+                } else if off_diagonal(second) != 0 {
+                    (6 * 63 + rank_of(first) as u64 * 28 + indices.map_b1h1h7[second]) * 62 + third as u64 - adjust2
+                } else if off_diagonal(third) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank_of(first) as u64 * 7 * 28
+                        + (rank_of(second) as u64 - adjust1) * 28 + indices.map_b1h1h7[third]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(first) as u64 * 7 * 6
+                        + (rank_of(second) as u64 - adjust1) * 6 + rank_of(third) as u64 - adjust2
+                };
+            } else {
+                index = indices.map_kk[indices.map_a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        // The other groups by their squares in increasing order, skipping
+        // squares taken by earlier groups
+        index *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        for group in 1..d.group_len.len() {
+            let length = d.group_len[group];
+            squares[start..start + length].sort();
+            let mut n = 0;
+            for i in 0..length {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|other| square > **other).count();
+                n += indices.binomial[i + 1][square - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            index += n * d.group_idx[group];
+            start += length;
+        }
+        (stm, file, index)
+    }
+}
+
+// File codes by square number
+fn piece_codes(board : &board::ChessBoard) -> [u8; 64] {
+    let mut codes = [0u8; 64];
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            let code = match board::clear_piece_color(piece) {
+                board::PAWN => 1,
+                board::KNIGHT => 2,
+                board::BISHOP => 3,
+                board::ROOK => 4,
+                board::QUEEN => 5,
+                board::KING => 6,
+                _ => 0,
+            };
+            if code != 0 {
+                codes[(7 - row) * 8 + col] = code + if piece & 0b1000 == board::WHITE { 0 } else { 8 };
+            }
+        }
+    }
+    codes
+}
+
+fn side_name(board : &board::ChessBoard, color : u8) -> String {
+    let mut name = String::new();
+    for (letter, piece) in [('K', board::KING), ('Q', board::QUEEN), ('R', board::ROOK), ('B', board::BISHOP), ('N', board::KNIGHT), ('P', board::PAWN)].iter() {
+        for row in 0..8 {
+            for col in 0..8 {
+                if board.board[row][col] == piece | color {
+                    name.push(*letter);
+                }
+            }
+        }
+    }
+    name
+}
+
+// Material as the table files name it, white first, like KRPvKR
+pub fn table_name(board : &board::ChessBoard) -> String {
+    format!("{}v{}", side_name(board, board::WHITE), side_name(board, board::BLACK))
+}
+
+fn piece_count(board : &board::ChessBoard) -> usize {
+    board.board.iter().map(|row| row.iter().filter(|piece| **piece != 0b0000).count()).sum()
+}
+
+// Every directory in a path separated like the system's PATH, returns the
+// number of WDL tables found. An empty path turns probing off.
+pub fn set_path(path : &str) -> usize {
+    let mut files = HashMap::new();
+    let mut max_pieces = 0;
+    let separator = if cfg!(windows) { ';' } else { ':' };
+    for dir in path.split(separator).filter(|dir| !dir.is_empty()) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.ends_with(".rtbw") {
+                max_pieces = max_pieces.max(file_name.len() - ".rtbw".len() - 1);
+            }
+            if file_name.ends_with(".rtbw") || file_name.ends_with(".rtbz") {
+                files.insert(file_name, entry.path());
+            }
+        }
+    }
+    let count = files.keys().filter(|name| name.ends_with(".rtbw")).count();
+    *SYZYGY.write().unwrap() = if count > 0 { Some(Syzygy{ files, tables: HashMap::new(), max_pieces }) } else { None };
+    count
+}
+
+// Largest number of pieces, kings included, the tables cover
+pub fn max_pieces() -> usize {
+    SYZYGY.read().unwrap().as_ref().map_or(0, |syzygy| syzygy.max_pieces)
+}
+
+// Loaded on first use and kept
+fn load(file_name : &str, dtz : bool) -> Option<Arc<Table>> {
+    {
+        let syzygy = SYZYGY.read().unwrap();
+        if let Some(table) = syzygy.as_ref()?.tables.get(file_name) {
+            return table.clone();
+        }
+    }
+    let mut syzygy = SYZYGY.write().unwrap();
+    let syzygy = syzygy.as_mut()?;
+    let table = syzygy.files.get(file_name)
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|bytes| Table::new(&file_name[..file_name.len() - 5], bytes, dtz).ok())
+        .map(Arc::new);
+    syzygy.tables.insert(file_name.to_string(), table.clone());
+    table
+}
+
+// The table for the board, and whether its colors are swapped
+fn find_table(board : &board::ChessBoard, dtz : bool) -> Option<(Arc<Table>, bool)> {
+    let white = side_name(board, board::WHITE);
+    let black = side_name(board, board::BLACK);
+    let extension = if dtz { "rtbz" } else { "rtbw" };
+    if let Some(table) = load(&format!("{}v{}.{}", white, black, extension), dtz) {
+        return Some((table, false));
+    }
+    load(&format!("{}v{}.{}", black, white, extension), dtz).map(|table| (table, true))
+}
+
+fn probe_table(board : &board::ChessBoard, dtz : bool, wdl : Wdl) -> Option<(i32, State)> {
+    if piece_count(board) == 2 {
+        return Some((0, State::Ok));
+    }
+    let (table, black_stronger) = find_table(board, dtz)?;
+    Some(table.probe(board, black_stronger, wdl))
+}
+
+fn is_capture(board : &board::ChessBoard, amove : Move) -> bool {
+    let (source, dest) = amove;
+    board.board[dest.0][dest.1] != 0b0000
+        || (board::clear_piece_color(board.board[source.0][source.1]) == board::PAWN && source.1 != dest.1)
+}
+
+fn is_pawn_move(board : &board::ChessBoard, amove : Move) -> bool {
+    board::clear_piece_color(board.board[amove.0.0][amove.0.1]) == board::PAWN
+}
+
+// Tables leave out positions where a capture (or for DTZ a pawn move) is best,
+// and have no en passant, so those moves are searched first
+fn search(board : &mut board::ChessBoard, check_zeroing_moves : bool) -> Option<(Wdl, State)> {
+    let moves = board.get_legal_moves();
+    let total = moves.len();
+    let mut count = 0;
+    let mut best = Wdl::Loss;
+    for amove in moves {
+        if !is_capture(board, amove) && (!check_zeroing_moves || !is_pawn_move(board, amove)) {
+            continue;
+        }
+        count += 1;
+        let state = board.save_state();
+        let captured_piece = board.make(amove.0, amove.1).unwrap();
+        let value = search(board, false);
+        board.unmake(amove.0, amove.1, captured_piece);
+        board.restore_state(state);
+        let value = value?.0.negate();
+        if value > best {
+            best = value;
+            if value >= Wdl::Win {
+                return Some((value, State::ZeroingBestMove));
+            }
+        }
+    }
+
+    let no_more_moves = count > 0 && count == total;
+    let value = if no_more_moves { best } else { Wdl::from_value(probe_table(board, false, Wdl::Draw)?.0) };
+    if best >= value {
+        let state = if best > Wdl::Draw || no_more_moves { State::ZeroingBestMove } else { State::Ok };
+        return Some((best, state));
+    }
+    Some((value, State::Ok))
+}
+
+fn covered(board : &board::ChessBoard) -> bool {
+    !(board.white_kingside_castle || board.white_queenside_castle || board.black_kingside_castle || board.black_queenside_castle)
+        && piece_count(board) <= max_pieces()
+}
+
+// Win, draw or loss for the side to move, None when not in the tables
+pub fn probe_wdl(board : &mut board::ChessBoard) -> Option<Wdl> {
+    if !covered(board) {
+        return None;
+    }
+    search(board, false).map(|(wdl, _)| wdl)
+}
+
+fn dtz_before_zeroing(wdl : Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+fn dtz(board : &mut board::ChessBoard) -> Option<i32> {
+    let (wdl, state) = search(board, true)?;
+    if wdl == Wdl::Draw {
+        return Some(0);
+    }
+    if state == State::ZeroingBestMove {
+        return Some(dtz_before_zeroing(wdl));
+    }
+    let (value, state) = probe_table(board, true, wdl)?;
+    let sign = wdl.value().signum();
+    if state != State::ChangeStm {
+        let cursed = wdl == Wdl::BlessedLoss || wdl == Wdl::CursedWin;
+        return Some((value + if cursed { 100 } else { 0 }) * sign);
+    }
+
+    // Only the other side to move is stored: the best reply's value, one ply further
+    let mut best = 0xffff;
+    for amove in board.get_legal_moves() {
+        let zeroing = is_capture(board, amove) || is_pawn_move(board, amove);
+        let state = board.save_state();
+        let captured_piece = board.make(amove.0, amove.1).unwrap();
+        let reply = if zeroing {
+            search(board, false).map(|(wdl, _)| -dtz_before_zeroing(wdl))
+        } else {
+            dtz(board).map(|value| -value)
+        };
+        let mates = board.protagonist_in_check() && board.get_legal_moves().is_empty();
+        board.unmake(amove.0, amove.1, captured_piece);
+        board.restore_state(state);
+        let mut value = reply?;
+        if value == 1 && mates {
+            best = 1;
+        }
+        if !zeroing {
+            value += value.signum();
+        }
+        if value < best && value.signum() == sign {
+            best = value;
+        }
+    }
+    Some(if best == 0xffff { -1 } else { best })
+}
+
+// Plies to the next capture or pawn move with best play, positive when the side
+// to move wins, 0 for a draw
+pub fn probe_dtz(board : &mut board::ChessBoard) -> Option<i32> {
+    if !covered(board) {
+        return None;
+    }
+    dtz(board)
+}
+
+// Every legal move ranked by the tables. Wins that finish inside the fifty
+// move rule rank highest, then wins and losses ordered by distance, then draws.
+pub fn root_moves(board : &mut board::ChessBoard) -> Option<Vec<RootMove>> {
+    if !covered(board) {
+        return None;
+    }
+    let clock = board.halfmove_clock as i32;
+    let mut result = Vec::new();
+    for amove in board.get_legal_moves() {
+        let state = board.save_state();
+        let captured_piece = board.make(amove.0, amove.1).unwrap();
+        let value = if board.halfmove_clock == 0 {
+            search(board, false).map(|(wdl, _)| dtz_before_zeroing(wdl.negate()))
+        } else if board.halfmove_clock >= 100 {
+            Some(0)
+        } else {
+            dtz(board).map(|value| if value > 0 { -value - 1 } else if value < 0 { -value + 1 } else { 0 })
+        };
+        let mates = board.protagonist_in_check() && board.get_legal_moves().is_empty();
+        board.unmake(amove.0, amove.1, captured_piece);
+        board.restore_state(state);
+        let mut dtz = value?;
+        if mates && dtz == 2 {
+            dtz = 1;
+        }
+        let rank = if dtz > 0 {
+            if dtz + clock <= 99 { MAX_DTZ } else { MAX_DTZ - (dtz + clock) }
+        } else if dtz < 0 {
+            if -dtz * 2 + clock < 100 { -MAX_DTZ } else { -MAX_DTZ + (-dtz + clock) }
+        } else {
+            0
+        };
+        result.push(RootMove{ amove, dtz, rank });
+    }
+    Some(result)
+}
+
+// The moves that keep the best result. Among winning moves inside the fifty
+// move rule the shortest distance to zeroing is kept, which always makes progress.
+pub fn filter_root_moves(board : &mut board::ChessBoard) -> Option<Vec<Move>> {
+    let moves = root_moves(board)?;
+    let best_rank = moves.iter().map(|root| root.rank).max()?;
+    let best : Vec<&RootMove> = moves.iter().filter(|root| root.rank == best_rank).collect();
+    let shortest = if best_rank > 0 { best.iter().map(|root| root.dtz).min().unwrap() } else { 0 };
+    Some(best.iter().filter(|root| best_rank <= 0 || root.dtz == shortest).map(|root| root.amove).collect())
+}
+
+// Search score for the side to move, for positions right after a capture or pawn move
+pub fn probe_score(board : &mut board::ChessBoard, ply : i32) -> Option<i32> {
+    if board.halfmove_clock != 0 {
+        return None;
+    }
+    Some(match probe_wdl(board)? {
+        Wdl::Win => TB_WIN - ply,
+        Wdl::CursedWin => 1,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -1,
+        Wdl::Loss => -TB_WIN + ply,
+    })
+}
@@ -0,0 +1,389 @@
+use syzygy;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Writing Syzygy tables, for test fixtures made by the retrograde solver. The
+// values are Re-Pair and Huffman coded the way syzygy.rs reads them, without
+// the real generator's tricks for smaller files.
+const MAX_SYMBOLS : usize = 4095;
+const MIN_PAIR_COUNT : u32 = 8;
+const PAIRS_PER_ROUND : usize = 16;
+const MAX_CODE_LENGTH : usize = 32;
+const BLOCK_SIZE_BITS : u8 = 6;
+const SPAN_BITS : u8 = 12;
+const MAX_BLOCK_VALUES : usize = 32768;
+
+// One side to move and file, in the order set_sizes and Table::new read them
+struct Compressed {
+    sizes: Vec<u8>,
+    sparse_index: Vec<u8>,
+    block_lengths: Vec<u8>,
+    data: Vec<u8>,
+}
+
+// Huffman code lengths, 0 for symbols that never occur
+fn code_lengths(frequency : &[u64]) -> Vec<usize> {
+    let mut heap = BinaryHeap::new();
+    let mut parent = vec![usize::MAX; frequency.len()];
+    for (symbol, count) in frequency.iter().enumerate() {
+        if *count > 0 {
+            heap.push(Reverse((*count, symbol)));
+        }
+    }
+    while heap.len() > 1 {
+        let Reverse((first, left)) = heap.pop().unwrap();
+        let Reverse((second, right)) = heap.pop().unwrap();
+        parent.push(usize::MAX);
+        parent[left] = parent.len() - 1;
+        parent[right] = parent.len() - 1;
+        heap.push(Reverse((first + second, parent.len() - 1)));
+    }
+    (0..frequency.len()).map(|symbol| {
+        if frequency[symbol] == 0 {
+            return 0;
+        }
+        let mut length = 0;
+        let mut node = symbol;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            length += 1;
+        }
+        length.max(1)
+    }).collect()
+}
+
+fn compress(values : &[u8], flags : u8) -> Result<Compressed, String> {
+    if values.iter().all(|value| *value == values[0]) {
+        return Ok(Compressed{ sizes: vec![flags | syzygy::FLAG_SINGLE_VALUE, values[0]], sparse_index: Vec::new(), block_lengths: Vec::new(), data: Vec::new() });
+    }
+
+    // A leaf for every value, then the most frequent pairs of adjacent symbols
+    // become new symbols while they pay for their entry
+    let mut symbols : Vec<(usize, usize)> = Vec::new(); // leaves hold their value and 0xfff
+    let mut expansion : Vec<usize> = Vec::new();
+    let mut leaf = [0usize; 256];
+    let mut present = [false; 256];
+    for value in values {
+        present[*value as usize] = true;
+    }
+    for value in 0..256 {
+        if present[value] {
+            leaf[value] = symbols.len();
+            symbols.push((value, 0xfff));
+            expansion.push(1);
+        }
+    }
+    let mut stream : Vec<u16> = values.iter().map(|value| leaf[*value as usize] as u16).collect();
+    while symbols.len() < MAX_SYMBOLS {
+        let count = symbols.len();
+        let mut counts = vec![0u32; count * count];
+        for pair in stream.windows(2) {
+            counts[pair[0] as usize * count + pair[1] as usize] += 1;
+        }
+        let mut candidates : Vec<usize> = (0..count * count).filter(|pair| counts[*pair] >= MIN_PAIR_COUNT).collect();
+        candidates.sort_by_key(|pair| Reverse(counts[*pair]));
+
+        // Pairs without a symbol in common, so one pass replaces them all
+        let mut used = vec![false; count];
+        let mut replacement = vec![u16::MAX; count * count];
+        for pair in candidates {
+            let (left, right) = (pair / count, pair % count);
+            if symbols.len() == MAX_SYMBOLS || symbols.len() == count + PAIRS_PER_ROUND {
+                break;
+            }
+            if used[left] || used[right] || expansion[left] + expansion[right] > 256 {
+                continue;
+            }
+            used[left] = true;
+            used[right] = true;
+            replacement[pair] = symbols.len() as u16;
+            symbols.push((left, right));
+            expansion.push(expansion[left] + expansion[right]);
+        }
+        if symbols.len() == count {
+            break;
+        }
+        let mut replaced = Vec::with_capacity(stream.len());
+        let mut i = 0;
+        while i < stream.len() {
+            if i + 1 < stream.len() {
+                let symbol = replacement[stream[i] as usize * count + stream[i + 1] as usize];
+                if symbol != u16::MAX {
+                    replaced.push(symbol);
+                    i += 2;
+                    continue;
+                }
+            }
+            replaced.push(stream[i]);
+            i += 1;
+        }
+        stream = replaced;
+    }
+
+    // Canonical Huffman code with the longer codes on the lower symbol
+    // numbers, symbols only found inside pairs numbered last
+    let mut frequency = vec![0u64; symbols.len()];
+    for symbol in &stream {
+        frequency[*symbol as usize] += 1;
+    }
+    let length = code_lengths(&frequency);
+    let max_length = *length.iter().max().unwrap();
+    let min_length = *length.iter().filter(|length| **length > 0).min().unwrap();
+    if max_length > MAX_CODE_LENGTH {
+        return Err(format!("Huffman code of {} bits", max_length));
+    }
+    let mut order : Vec<usize> = (0..symbols.len()).collect();
+    order.sort_by_key(|symbol| (length[*symbol] == 0, Reverse(length[*symbol])));
+    let mut number = vec![0; symbols.len()];
+    for (new, old) in order.iter().enumerate() {
+        number[*old] = new;
+    }
+    let mut with_length = vec![0u64; max_length + 2];
+    for symbol in 0..symbols.len() {
+        with_length[length[symbol]] += 1;
+    }
+    let mut lowest = vec![0u64; max_length + 1];
+    let mut base = vec![0u64; max_length + 1];
+    for bits in (min_length..max_length).rev() {
+        lowest[bits] = lowest[bits + 1] + with_length[bits + 1];
+        base[bits] = (base[bits + 1] + with_length[bits + 1]) / 2;
+    }
+    let code = |symbol : usize| base[length[symbol]] + number[symbol] as u64 - lowest[length[symbol]];
+
+    // Whole symbols to a block, big endian bit order
+    let block_size = 1usize << BLOCK_SIZE_BITS;
+    let mut data = Vec::new();
+    let mut block_values : Vec<usize> = Vec::new();
+    let mut block = vec![0u8; block_size];
+    let (mut bits, mut covered) = (0, 0);
+    for symbol in stream.iter().map(|symbol| *symbol as usize) {
+        if bits + length[symbol] > 8 * block_size || covered + expansion[symbol] > MAX_BLOCK_VALUES {
+            data.extend_from_slice(&block);
+            block_values.push(covered);
+            block = vec![0u8; block_size];
+            bits = 0;
+            covered = 0;
+        }
+        let value = code(symbol);
+        for bit in (0..length[symbol]).rev() {
+            if (value >> bit) & 1 != 0 {
+                block[bits / 8] |= 0x80 >> (bits % 8);
+            }
+            bits += 1;
+        }
+        covered += expansion[symbol];
+    }
+    data.extend_from_slice(&block);
+    block_values.push(covered);
+
+    // Every span values the block and offset of the value in the middle
+    let span = 1usize << SPAN_BITS;
+    let mut starts = vec![0];
+    for values in &block_values {
+        starts.push(starts.last().unwrap() + values);
+    }
+    let mut sparse_index = Vec::new();
+    for k in 0..values.len().div_ceil(span) {
+        let middle = k * span + span / 2;
+        let block = (starts.partition_point(|start| *start <= middle) - 1).min(block_values.len() - 1);
+        let offset = middle - starts[block];
+        if offset > u16::MAX as usize {
+            return Err("sparse index offset too large".to_string());
+        }
+        sparse_index.extend_from_slice(&(block as u32).to_le_bytes());
+        sparse_index.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+    let mut block_lengths = Vec::new();
+    for values in &block_values {
+        block_lengths.extend_from_slice(&(*values as u16 - 1).to_le_bytes());
+    }
+
+    let mut sizes = vec![flags, BLOCK_SIZE_BITS, SPAN_BITS, 0];
+    sizes.extend_from_slice(&(block_values.len() as u32).to_le_bytes());
+    sizes.push(max_length as u8);
+    sizes.push(min_length as u8);
+    for lowest_symbol in &lowest[min_length..=max_length] {
+        sizes.extend_from_slice(&(*lowest_symbol as u16).to_le_bytes());
+    }
+    sizes.extend_from_slice(&(symbols.len() as u16).to_le_bytes());
+    for old in order {
+        let (left, right) = symbols[old];
+        let (left, right) = if right == 0xfff { (left, right) } else { (number[left], number[right]) };
+        sizes.extend_from_slice(&[left as u8, ((left >> 8) | ((right & 0xf) << 4)) as u8, (right >> 4) as u8]);
+    }
+    if symbols.len() & 1 != 0 {
+        sizes.push(0);
+    }
+    Ok(Compressed{ sizes, sparse_index, block_lengths, data })
+}
+
+// The DTZ map a value goes through, wins, losses, cursed wins then blessed
+// losses, and its entry there: plies less one, or for the results the fifty
+// move rule spoils, moves past the hundredth ply as the real tables keep them
+fn dtz_entry(value : i32) -> Result<(usize, u8), String> {
+    let cursed = value.abs() > 100;
+    let entry = if cursed { (value.abs() - 101) / 2 } else { value.abs() - 1 };
+    if entry > u8::MAX as i32 {
+        return Err(format!("DTZ {} does not fit a map", value));
+    }
+    Ok(((value < 0) as usize + if cursed { 2 } else { 0 }, entry as u8))
+}
+
+// A table for the name from the value of every position: win/draw/loss from -2
+// to 2, or for DTZ the plies to zeroing, positive for a win and past 100 when
+// the win is cursed. The squares passed are those of the name's pieces in
+// order, white's first. None marks positions that cannot occur. DTZ tables
+// store white to move. The written table is read back and checked against
+// every value.
+pub fn write_table(name : &str, dtz : bool, value : &dyn Fn(&[usize], bool) -> Option<i32>) -> Result<Vec<u8>, String> {
+    let sides : Vec<&str> = name.split('v').collect();
+    let (white, black) = match (sides.len(), sides.first().and_then(|side| syzygy::side_pieces(side)), sides.get(1).and_then(|side| syzygy::side_pieces(side))) {
+        (2, Some(white), Some(black)) => (white, black),
+        _ => return Err(format!("{}: not a material signature", name)),
+    };
+    let letter_code = |letter : char| " PNBRQK".find(letter).unwrap() as u8;
+    let codes : Vec<u8> = sides[0].chars().map(letter_code).chain(sides[1].chars().map(|letter| letter_code(letter) | 8)).collect();
+
+    // Leading pawns, the other side's pawns, then unique pieces and the rest
+    // with identical pieces together
+    let white_leads = black[1] == 0 || (white[1] > 0 && black[1] >= white[1]);
+    let lead_color = if white_leads { 0 } else { 8 };
+    let count = |code : u8| if code & 8 == 0 { white[(code & 7) as usize] } else { black[(code & 7) as usize] };
+    let mut pieces = codes.clone();
+    pieces.sort_by_key(|code| {
+        let group = if code & 7 == 1 { (code & 8 != lead_color) as u8 } else if count(*code) == 1 { 2 } else { 3 };
+        (group, *code)
+    });
+    let symmetric = sides[0] == sides[1];
+    let has_pawns = white[1] + black[1] > 0;
+    let both_pawns = white[1] > 0 && black[1] > 0;
+    let files = if has_pawns { 4 } else { 1 };
+    let mut bytes = if dtz { syzygy::DTZ_MAGIC.to_vec() } else { syzygy::WDL_MAGIC.to_vec() };
+    bytes.push(!symmetric as u8 | (has_pawns as u8) << 1);
+    for _ in 0..files {
+        bytes.push(0x00);
+        if both_pawns {
+            bytes.push(0x11);
+        }
+        bytes.extend(pieces.iter().map(|code| code | code << 4));
+    }
+    if bytes.len() & 1 != 0 {
+        bytes.push(0);
+    }
+    let (table, _) = syzygy::Table::header(name, &bytes, dtz)?;
+    let sides = table.sides();
+
+    let mut stored : Vec<Vec<Vec<Option<i32>>>> = (0..sides).map(|side| {
+        (0..files).map(|file| vec![None; *table.items[side][file].group_idx.last().unwrap() as usize]).collect()
+    }).collect();
+    let mut squares = vec![0; codes.len()];
+    let mut board = [0u8; 64];
+    for placement in 0..1usize << (6 * codes.len()) {
+        let mut occupied = 0u64;
+        for (i, square) in squares.iter_mut().enumerate() {
+            *square = (placement >> (6 * i)) & 63;
+            occupied |= 1 << *square;
+        }
+        let on_last_ranks = codes.iter().zip(squares.iter()).any(|(code, square)| code & 7 == 1 && (syzygy::rank_of(*square) == 0 || syzygy::rank_of(*square) == 7));
+        if occupied.count_ones() as usize != codes.len() || on_last_ranks {
+            continue;
+        }
+        for (code, square) in codes.iter().zip(squares.iter()) {
+            board[*square] = *code;
+        }
+        for black_to_move in [false, true].iter() {
+            let (stm, file, index) = table.locate(&board, *black_to_move, false);
+            if dtz && stm != 0 {
+                continue;
+            }
+            let value = match value(&squares, *black_to_move) {
+                Some(value) => value,
+                None => continue,
+            };
+            let entry = &mut stored[stm][file][index as usize];
+            if entry.is_some_and(|other| other != value) {
+                return Err(format!("{}: positions with different values share index {}", name, index));
+            }
+            *entry = Some(value);
+        }
+        for square in squares.iter() {
+            board[*square] = 0;
+        }
+    }
+
+    // Positions that cannot occur and DTZ draws repeat the value before them
+    let mut maps = vec![[Vec::new(), Vec::new(), Vec::new(), Vec::new()]; files];
+    let mut compressed = Vec::new();
+    let mut symbols = vec![vec![Vec::new(); files]; sides];
+    for file in 0..files {
+        if dtz {
+            for value in stored[0][file].iter().flatten() {
+                if *value != 0 {
+                    let (map, entry) = dtz_entry(*value)?;
+                    maps[file][map].push(entry);
+                }
+            }
+            for map in maps[file].iter_mut() {
+                map.sort();
+                map.dedup();
+            }
+        }
+        for side in 0..sides {
+            let mut previous = 0;
+            for value in stored[side][file].iter() {
+                let symbol = match *value {
+                    None => previous,
+                    Some(0) if dtz => previous,
+                    Some(value) if dtz => {
+                        let (map, entry) = dtz_entry(value)?;
+                        maps[file][map].binary_search(&entry).unwrap() as u8
+                    },
+                    Some(value) => (value + 2) as u8,
+                };
+                symbols[side][file].push(symbol);
+                previous = symbol;
+            }
+            let flags = if dtz { syzygy::FLAG_MAPPED | syzygy::FLAG_WIN_PLIES | syzygy::FLAG_LOSS_PLIES } else { 0 };
+            compressed.push(compress(&symbols[side][file], flags)?);
+        }
+    }
+
+    for item in compressed.iter() {
+        bytes.extend_from_slice(&item.sizes);
+    }
+    if dtz {
+        for map in maps.iter() {
+            for list in map.iter() {
+                bytes.push(list.len() as u8);
+                bytes.extend_from_slice(list);
+            }
+        }
+        if bytes.len() & 1 != 0 {
+            bytes.push(0);
+        }
+    }
+    for item in compressed.iter() {
+        bytes.extend_from_slice(&item.sparse_index);
+    }
+    for item in compressed.iter() {
+        bytes.extend_from_slice(&item.block_lengths);
+    }
+    for item in compressed.iter() {
+        bytes.resize((bytes.len() + 0x3f) & !0x3f, 0);
+        bytes.extend_from_slice(&item.data);
+    }
+
+    let table = syzygy::Table::new(name, bytes, dtz)?;
+    for file in 0..files {
+        for side in 0..sides {
+            for (index, value) in stored[side][file].iter().enumerate() {
+                if value.is_some_and(|value| !dtz || value != 0)
+                    && syzygy::decompress_pairs(&table.bytes, &table.items[side][file], index as u64) != symbols[side][file][index] as i32 {
+                    return Err(format!("{}: wrong value read back at index {}", name, index));
+                }
+            }
+        }
+    }
+    Ok(table.bytes)
+}
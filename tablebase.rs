@@ -0,0 +1,606 @@
+use board;
+use eval;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+// Pawnless endings of up to four pieces, kings included, with exact distance to
+// mate. The table's first side is white; the same table answers for the colors
+// swapped. Castling and en passant never come up without pawns and rooks at home.
+pub const MAX_PIECES : usize = 4;
+pub const EXTENSION : &str = "rctb";
+
+const MAGIC : &[u8; 4] = b"RCTB";
+const VERSION : u32 = 1;
+
+// The white king is kept in the a1-d1-d4 triangle by mirroring and rotating the
+// board, every other piece takes any of the 64 squares
+const KING_SQUARES : usize = 10;
+
+// One byte per position for the side to move: DRAW, or plies to mate plus one
+// with odd plies a win and even plies a loss (1 is checkmated)
+const DRAW : u8 = 0;
+const INVALID : u8 = 255;
+const UNRESOLVED : u8 = 254;
+const MAX_PLIES : usize = 252;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+// Letters in the order they are written in a signature
+const PIECE_LETTERS : [(char, u8); 4] = [('Q', board::QUEEN), ('R', board::ROOK), ('B', board::BISHOP), ('N', board::KNIGHT)];
+
+// Plies to mate from the side to move's point of view
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Probe {
+    Draw,
+    Win(u32),
+    Loss(u32),
+}
+
+pub struct Tablebase {
+    pub signature: String,
+    // White king, white pieces, black king, black pieces
+    pieces: Vec<u8>,
+    values: Vec<u8>,
+}
+
+pub struct Tablebases {
+    tables: HashMap<String, Tablebase>,
+}
+
+static TABLEBASES : RwLock<Option<Tablebases>> = RwLock::new(None);
+
+fn triangle_index(square : (usize, usize)) -> Option<usize> {
+    let rank = 7 - square.0;
+    let file = square.1;
+    if file <= 3 && rank <= file { Some(file * (file + 1) / 2 + rank) } else { None }
+}
+
+fn triangle_square(index : usize) -> (usize, usize) {
+    let mut file = 0;
+    while (file + 1) * (file + 2) / 2 <= index {
+        file += 1;
+    }
+    (7 - (index - file * (file + 1) / 2), file)
+}
+
+// The eight symmetries of the board: bit 2 reflects in the a1-h8 diagonal,
+// bit 1 flips the ranks and bit 0 the files
+fn transform(square : (usize, usize), symmetry : usize) -> (usize, usize) {
+    let (mut row, mut col) = square;
+    if symmetry & 4 != 0 {
+        let swapped = (7 - col, 7 - row);
+        row = swapped.0;
+        col = swapped.1;
+    }
+    if symmetry & 2 != 0 {
+        row = 7 - row;
+    }
+    if symmetry & 1 != 0 {
+        col = 7 - col;
+    }
+    (row, col)
+}
+
+// The first symmetry that brings the white king into the triangle
+fn canonical_symmetry(white_king : (usize, usize)) -> usize {
+    (0..8).find(|symmetry| triangle_index(transform(white_king, *symmetry)).is_some()).unwrap()
+}
+
+fn table_size(pieces : usize) -> usize {
+    2 * KING_SQUARES * 64usize.pow(pieces as u32 - 1)
+}
+
+// Squares in table order, the white king already in the triangle
+fn index(white_to_move : bool, squares : &[(usize, usize)]) -> usize {
+    let side = if white_to_move { 0 } else { 1 };
+    let mut index = side * KING_SQUARES + triangle_index(squares[0]).unwrap();
+    for square in &squares[1..] {
+        index = index * 64 + square.0 * 8 + square.1;
+    }
+    index
+}
+
+fn canonical_index(white_to_move : bool, squares : &[(usize, usize)]) -> usize {
+    let symmetry = canonical_symmetry(squares[0]);
+    let squares : Vec<(usize, usize)> = squares.iter().map(|square| transform(*square, symmetry)).collect();
+    index(white_to_move, &squares)
+}
+
+fn decode(mut index : usize, pieces : usize) -> (bool, Vec<(usize, usize)>) {
+    let mut squares = vec![(0, 0); pieces];
+    for piece in (1..pieces).rev() {
+        squares[piece] = ((index % 64) / 8, index % 8);
+        index /= 64;
+    }
+    squares[0] = triangle_square(index % KING_SQUARES);
+    (index / KING_SQUARES == 0, squares)
+}
+
+fn decode_value(value : u8) -> Option<Probe> {
+    match value {
+        INVALID | UNRESOLVED => None,
+        DRAW => Some(Probe::Draw),
+        _ if value.is_multiple_of(2) => Some(Probe::Win(value as u32 - 1)),
+        _ => Some(Probe::Loss(value as u32 - 1)),
+    }
+}
+
+fn letters(pieces : &[u8]) -> String {
+    let mut text = String::new();
+    for (letter, piece) in PIECE_LETTERS.iter() {
+        for _ in pieces.iter().filter(|other| board::clear_piece_color(**other) == *piece) {
+            text.push(*letter);
+        }
+    }
+    text
+}
+
+fn units(letters : &str) -> u32 {
+    letters.chars().map(|letter| match letter { 'Q' => 9, 'R' => 5, _ => 3 }).sum()
+}
+
+// The stronger side first, so KRKN and KNKR share a table
+fn signature_name(white : &str, black : &str) -> String {
+    if units(white) > units(black) || (units(white) == units(black) && white <= black) {
+        format!("K{}K{}", white, black)
+    } else {
+        format!("K{}K{}", black, white)
+    }
+}
+
+// Signature as typed, for example KRKN or krkn, to its table name and pieces
+pub fn parse_signature(text : &str) -> Result<(String, Vec<u8>), String> {
+    let text = text.to_uppercase();
+    if !text.starts_with('K') || text.matches('K').count() != 2 {
+        return Err(format!("{}: a signature is two kings with their pieces, like KRKN", text));
+    }
+    let split = text[1..].find('K').unwrap() + 1;
+    let mut sides = Vec::new();
+    for side in [&text[1..split], &text[split + 1..]].iter() {
+        let mut pieces = Vec::new();
+        for letter in side.chars() {
+            match PIECE_LETTERS.iter().find(|(other, _)| *other == letter) {
+                Some((_, piece)) => pieces.push(*piece),
+                None if letter == 'P' => return Err(format!("{}: pawns are not supported", text)),
+                None => return Err(format!("{}: unknown piece {}", text, letter)),
+            }
+        }
+        sides.push(letters(&pieces));
+    }
+    let name = signature_name(&sides[0], &sides[1]);
+    if name.len() > MAX_PIECES {
+        return Err(format!("{}: at most {} pieces", text, MAX_PIECES));
+    }
+    let split = name[1..].find('K').unwrap() + 1;
+    let mut pieces = Vec::new();
+    for (side, color) in [(&name[..split], board::WHITE), (&name[split..], board::BLACK)].iter() {
+        for letter in side.chars() {
+            let piece = if letter == 'K' { board::KING } else { PIECE_LETTERS.iter().find(|(other, _)| *other == letter).unwrap().1 };
+            pieces.push(piece | color);
+        }
+    }
+    Ok((name, pieces))
+}
+
+// Tables reached by a capture, leaving out the always drawn KK
+pub fn dependencies(signature : &str) -> Result<Vec<String>, String> {
+    let (name, _) = parse_signature(signature)?;
+    let split = name[1..].find('K').unwrap() + 1;
+    let (white, black) = (&name[1..split], &name[split + 1..]);
+    let mut result : Vec<String> = Vec::new();
+    for (side, other, first) in [(white, black, true), (black, white, false)].iter() {
+        for skip in 0..side.len() {
+            let rest : String = side.chars().enumerate().filter(|(index, _)| *index != skip).map(|(_, letter)| letter).collect();
+            let table = if *first { signature_name(&rest, other) } else { signature_name(other, &rest) };
+            if table != "KK" && !result.contains(&table) {
+                result.push(table);
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Non-king pieces of each side, None with pawns on the board
+fn material(board : &board::ChessBoard) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut white = Vec::new();
+    let mut black = Vec::new();
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            match board::clear_piece_color(piece) {
+                0b0000 | board::KING => {},
+                board::PAWN => return None,
+                _ => if piece & 0b1000 == board::WHITE { white.push(piece) } else { black.push(piece) },
+            }
+        }
+    }
+    Some((white, black))
+}
+
+fn set_king(board : &mut board::ChessBoard, piece : u8, square : (usize, usize)) {
+    if piece == board::KING | board::WHITE {
+        board.white_king_pos = square;
+    } else if piece == board::KING | board::BLACK {
+        board.black_king_pos = square;
+    }
+}
+
+fn place(board : &mut board::ChessBoard, pieces : &[u8], squares : &[(usize, usize)], white_to_move : bool) {
+    board.board = [[0u8; 8]; 8];
+    for (piece, square) in pieces.iter().zip(squares.iter()) {
+        board.board[square.0][square.1] = *piece;
+        set_king(board, *piece, *square);
+    }
+    board.white_kingside_castle = false;
+    board.white_queenside_castle = false;
+    board.black_kingside_castle = false;
+    board.black_queenside_castle = false;
+    board.ep = None;
+    board.set_protagonist(if white_to_move { board::WHITE } else { board::BLACK });
+}
+
+// Positions in the table with a move to the one at index, without captures.
+// Moving a piece back is the same as moving it forward for everything but
+// pawns. Every symmetric image that maps onto the index is unmoved from, and
+// only predecessors already in table form are kept, so each move of a stored
+// position is found exactly once.
+fn predecessors(board : &mut board::ChessBoard, pieces : &[u8], position : usize, result : &mut Vec<usize>) {
+    result.clear();
+    let (white_to_move, squares) = decode(position, pieces.len());
+    let mut images : Vec<Vec<(usize, usize)>> = Vec::new();
+    for symmetry in 0..8 {
+        let image : Vec<(usize, usize)> = squares.iter().map(|square| transform(*square, symmetry)).collect();
+        if images.contains(&image) || canonical_index(white_to_move, &image) != position {
+            continue;
+        }
+        place(board, pieces, &image, !white_to_move);
+        for amove in board.get_all_moves() {
+            let (source, dest) = (amove.0, (amove.1.0, amove.1.1));
+            if board.board[dest.0][dest.1] != 0b0000 {
+                continue;
+            }
+            let mut before = image.clone();
+            let moved = before.iter().position(|square| *square == source).unwrap();
+            before[moved] = dest;
+            if triangle_index(before[0]).is_none() {
+                continue;
+            }
+            // The side waiting for the move cannot have been in check
+            let piece = pieces[moved];
+            board.board[source.0][source.1] = 0b0000;
+            board.board[dest.0][dest.1] = piece;
+            set_king(board, piece, dest);
+            board.set_protagonist(if white_to_move { board::WHITE } else { board::BLACK });
+            if !board.protagonist_in_check() {
+                result.push(index(!white_to_move, &before));
+            }
+            board.board[dest.0][dest.1] = 0b0000;
+            board.board[source.0][source.1] = piece;
+            set_king(board, piece, source);
+            board.set_protagonist(if white_to_move { board::BLACK } else { board::WHITE });
+        }
+        images.push(image);
+    }
+}
+
+// Captures lead into smaller tables, which have to be in tables already
+const FLAG_ESCAPE : u8 = 1; // a capture reaches a draw
+const FLAG_CAPTURE_WIN : u8 = 2; // a capture wins
+
+// Retrograde analysis by number of plies to mate. Every position first counts its
+// quiet moves and looks its captures up. Positions are then settled one ply
+// level at a time: the predecessors of a loss are wins one ply later, and a
+// position whose quiet moves all turn out to be the opponent's wins is lost once
+// the last of them is settled. Whatever is never settled is a draw.
+pub fn generate(signature : &str, tables : &Tablebases) -> Result<Tablebase, String> {
+    let (name, pieces) = parse_signature(signature)?;
+    for dependency in dependencies(&name)? {
+        if !tables.tables.contains_key(&dependency) {
+            return Err(format!("{} needs the {} table", name, dependency));
+        }
+    }
+    let size = table_size(pieces.len());
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    let mut values = vec![UNRESOLVED; size];
+    let mut remaining = vec![0u8; size];
+    let mut flags = vec![0u8; size];
+    let mut capture_loss = vec![0u8; size];
+    let mut pending : Vec<Vec<u32>> = vec![Vec::new(); MAX_PLIES + 1];
+
+    for position in 0..size {
+        let (white_to_move, squares) = decode(position, pieces.len());
+        let overlapping = (1..squares.len()).any(|piece| squares[..piece].contains(&squares[piece]));
+        // The side that just moved cannot be in check
+        place(&mut board, &pieces, &squares, !white_to_move);
+        if overlapping || board.protagonist_in_check() {
+            values[position] = INVALID;
+            continue;
+        }
+        board.set_protagonist(if white_to_move { board::WHITE } else { board::BLACK });
+        let moves = board.get_legal_moves();
+        if moves.is_empty() {
+            if board.protagonist_in_check() {
+                pending[0].push(position as u32);
+            } else {
+                values[position] = DRAW;
+            }
+            continue;
+        }
+        for amove in moves {
+            if board.board[amove.1.0][amove.1.1] == 0b0000 {
+                remaining[position] += 1;
+                continue;
+            }
+            let state = board.save_state();
+            let captured_piece = board.make(amove.0, amove.1).unwrap();
+            let result = tables.probe(&board);
+            board.unmake(amove.0, amove.1, captured_piece);
+            board.restore_state(state);
+            match result {
+                Some(Probe::Draw) => flags[position] |= FLAG_ESCAPE,
+                Some(Probe::Loss(plies)) if (plies as usize) < MAX_PLIES => {
+                    flags[position] |= FLAG_CAPTURE_WIN;
+                    pending[plies as usize + 1].push(position as u32);
+                },
+                Some(Probe::Loss(_)) => return Err(format!("{}: mates longer than {} plies do not fit", name, MAX_PLIES)),
+                Some(Probe::Win(plies)) => capture_loss[position] = capture_loss[position].max(plies as u8 + 1),
+                None => return Err(format!("{}: a capture is missing from the smaller tables", name)),
+            }
+        }
+        if remaining[position] == 0 && flags[position] == 0 {
+            pending[capture_loss[position] as usize].push(position as u32);
+        }
+    }
+
+    let mut before = Vec::new();
+    for level in 0..=MAX_PLIES {
+        for position in std::mem::take(&mut pending[level]) {
+            let position = position as usize;
+            if values[position] != UNRESOLVED {
+                continue;
+            }
+            values[position] = level as u8 + 1;
+            predecessors(&mut board, &pieces, position, &mut before);
+            for predecessor in &before {
+                let predecessor = *predecessor;
+                if values[predecessor] != UNRESOLVED {
+                    continue;
+                }
+                let settled = if level % 2 == 0 {
+                    Some(level + 1)
+                } else {
+                    remaining[predecessor] -= 1;
+                    if remaining[predecessor] == 0 && flags[predecessor] == 0 {
+                        Some((level + 1).max(capture_loss[predecessor] as usize))
+                    } else {
+                        None
+                    }
+                };
+                if let Some(plies) = settled {
+                    if plies > MAX_PLIES {
+                        return Err(format!("{}: mates longer than {} plies do not fit", name, MAX_PLIES));
+                    }
+                    pending[plies].push(predecessor as u32);
+                }
+            }
+        }
+    }
+    for value in values.iter_mut() {
+        if *value == UNRESOLVED {
+            *value = DRAW;
+        }
+    }
+    Ok(Tablebase{ signature: name, pieces, values })
+}
+
+fn read_u32(bytes : &[u8], offset : &mut usize) -> Option<u32> {
+    let value = bytes.get(*offset..*offset + 4)?;
+    *offset += 4;
+    Some(u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+impl Tablebase {
+    // Result for the given squares in table order, the colors as in the table
+    fn value(& self, white_to_move : bool, squares : &[(usize, usize)]) -> Option<Probe> {
+        decode_value(self.values[canonical_index(white_to_move, squares)])
+    }
+
+    pub fn positions(& self) -> usize {
+        self.values.iter().filter(|value| **value != INVALID).count()
+    }
+
+    // Wins, draws and losses for the given side to move
+    pub fn counts(& self, white_to_move : bool) -> (usize, usize, usize) {
+        let half = if white_to_move { &self.values[..self.values.len() / 2] } else { &self.values[self.values.len() / 2..] };
+        let mut counts = (0, 0, 0);
+        for value in half {
+            match decode_value(*value) {
+                Some(Probe::Win(_)) => counts.0 += 1,
+                Some(Probe::Draw) => counts.1 += 1,
+                Some(Probe::Loss(_)) => counts.2 += 1,
+                None => {},
+            }
+        }
+        counts
+    }
+
+    // The longest win in plies and a position it is reached from
+    pub fn longest_mate(& self) -> Option<(u32, String)> {
+        let mut longest : Option<(u32, usize)> = None;
+        for (position, value) in self.values.iter().enumerate() {
+            if let Some(Probe::Win(plies)) = decode_value(*value) {
+                if longest.is_none_or(|(best, _)| plies > best) {
+                    longest = Some((plies, position));
+                }
+            }
+        }
+        let (plies, position) = longest?;
+        let (white_to_move, squares) = decode(position, self.pieces.len());
+        let stuff = [[0u8; 8]; 8];
+        let mut board = board::build_board(stuff);
+        place(&mut board, &self.pieces, &squares, white_to_move);
+        Some((plies, board.to_fen()))
+    }
+
+    pub fn to_bytes(& self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.push(self.signature.len() as u8);
+        bytes.extend_from_slice(self.signature.as_bytes());
+        bytes.extend_from_slice(&(self.values.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.values);
+        bytes
+    }
+
+    pub fn from_bytes(bytes : &[u8]) -> Result<Tablebase, String> {
+        if bytes.len() < 4 || &bytes[..4] != MAGIC {
+            return Err("not a tablebase file".to_string());
+        }
+        let mut offset = 4;
+        if read_u32(bytes, &mut offset) != Some(VERSION) {
+            return Err(format!("expected version {}", VERSION));
+        }
+        let length = *bytes.get(offset).ok_or("truncated file")? as usize;
+        let signature = bytes.get(offset + 1..offset + 1 + length).ok_or("truncated file")?;
+        offset += 1 + length;
+        let (name, pieces) = parse_signature(&String::from_utf8_lossy(signature))?;
+        let size = read_u32(bytes, &mut offset).ok_or("truncated file")? as usize;
+        if size != table_size(pieces.len()) || bytes.len() != offset + size {
+            return Err("wrong file size".to_string());
+        }
+        Ok(Tablebase{ signature: name, pieces, values: bytes[offset..].to_vec() })
+    }
+}
+
+pub fn load_table(path : &str) -> Result<Tablebase, String> {
+    match fs::read(path) {
+        Ok(bytes) => Tablebase::from_bytes(&bytes).map_err(|error| format!("{}: {}", path, error)),
+        Err(error) => Err(format!("{}: {}", path, error)),
+    }
+}
+
+// Written as <signature>.rctb
+pub fn save_table(dir : &str, table : &Tablebase) -> Result<String, String> {
+    let path = Path::new(dir).join(format!("{}.{}", table.signature, EXTENSION));
+    let path = path.to_string_lossy().to_string();
+    fs::write(&path, table.to_bytes()).map_err(|error| format!("{}: {}", path, error))?;
+    Ok(path)
+}
+
+impl Tablebases {
+    pub fn new() -> Tablebases {
+        Tablebases{ tables: HashMap::new() }
+    }
+
+    // Every .rctb file in a directory
+    pub fn load_dir(dir : &str) -> Result<Tablebases, String> {
+        let mut tables = Tablebases::new();
+        let entries = fs::read_dir(dir).map_err(|error| format!("{}: {}", dir, error))?;
+        for entry in entries {
+            let path = entry.map_err(|error| format!("{}: {}", dir, error))?.path();
+            if path.extension().is_some_and(|extension| extension == EXTENSION) {
+                tables.insert(load_table(&path.to_string_lossy())?);
+            }
+        }
+        Ok(tables)
+    }
+
+    pub fn insert(&mut self, table : Tablebase) {
+        self.tables.insert(table.signature.clone(), table);
+    }
+
+    pub fn contains(& self, signature : &str) -> bool {
+        self.tables.contains_key(signature)
+    }
+
+    pub fn len(& self) -> usize {
+        self.tables.len()
+    }
+
+    // None unless the board is pawnless and its table is loaded
+    pub fn probe(& self, board : &board::ChessBoard) -> Option<Probe> {
+        if board.white_kingside_castle || board.white_queenside_castle || board.black_kingside_castle || board.black_queenside_castle {
+            return None;
+        }
+        let (white, black) = material(board)?;
+        if white.is_empty() && black.is_empty() {
+            return Some(Probe::Draw);
+        }
+        if 2 + white.len() + black.len() > MAX_PIECES {
+            return None;
+        }
+        let name = signature_name(&letters(&white), &letters(&black));
+        let table = self.tables.get(&name)?;
+        // Colors are swapped, and the board flipped, when black has the table's white pieces
+        let swapped = name != format!("K{}K{}", letters(&white), letters(&black));
+        let mut squares = Vec::new();
+        let mut used = [[false; 8]; 8];
+        for piece in &table.pieces {
+            let wanted = if swapped { piece ^ 0b1000 } else { *piece };
+            let square = (0..64).map(|square| (square / 8, square % 8))
+                .find(|square| board.board[square.0][square.1] == wanted && !used[square.0][square.1])?;
+            used[square.0][square.1] = true;
+            squares.push(if swapped { (7 - square.0, square.1) } else { square });
+        }
+        let white_to_move = (board.protagonist == board::WHITE) != swapped;
+        table.value(white_to_move, &squares)
+    }
+
+    // The best move by the tables: the fastest win, else a draw, else the
+    // slowest loss. None when the position is not covered.
+    pub fn best_move(& self, board : &mut board::ChessBoard) -> Option<(Move, Probe)> {
+        self.probe(board)?;
+        let mut best : Option<(Move, Probe)> = None;
+        for amove in board.get_legal_moves() {
+            let state = board.save_state();
+            let captured_piece = board.make(amove.0, amove.1).unwrap();
+            let reply = self.probe(board);
+            board.unmake(amove.0, amove.1, captured_piece);
+            board.restore_state(state);
+            let result = match reply? {
+                Probe::Draw => Probe::Draw,
+                Probe::Win(plies) => Probe::Loss(plies + 1),
+                Probe::Loss(plies) => Probe::Win(plies + 1),
+            };
+            if best.is_none_or(|(_, other)| rank(result) > rank(other)) {
+                best = Some((amove, result));
+            }
+        }
+        best
+    }
+}
+
+// Orders results from the side to move's point of view
+fn rank(result : Probe) -> i64 {
+    match result {
+        Probe::Win(plies) => 1000 - plies as i64,
+        Probe::Draw => 0,
+        Probe::Loss(plies) => plies as i64 - 1000,
+    }
+}
+
+// Tables used by the search
+pub fn set_tablebases(tables : Tablebases) {
+    *TABLEBASES.write().unwrap() = Some(tables);
+}
+
+// Search score for the side to move, ply from the root, when the tables cover the board
+pub fn probe_score(board : &board::ChessBoard, ply : i32) -> Option<i32> {
+    let tables = TABLEBASES.read().unwrap();
+    match tables.as_ref()?.probe(board)? {
+        Probe::Draw => Some(0),
+        Probe::Win(plies) => Some(eval::mate_in(ply + plies as i32)),
+        Probe::Loss(plies) => Some(eval::mated_in(ply + plies as i32)),
+    }
+}
+
+// Move to play at the root, with its result
+pub fn probe_root(board : &mut board::ChessBoard) -> Option<(Move, Probe)> {
+    let tables = TABLEBASES.read().unwrap();
+    tables.as_ref()?.best_move(board)
+}
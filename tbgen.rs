@@ -0,0 +1,114 @@
+mod board;
+mod eval;
+mod endgame;
+mod kpk;
+mod nnue;
+mod retrograde;
+mod syzygy;
+mod syzygy_writer;
+mod tablebase;
+mod zobrist;
+
+use std::env;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+fn usage() -> ! {
+    println!("usage: tbgen <signature>... [options]");
+    println!("signature: two kings and up to {} pieces in all, without pawns, like KQK, KRKN or KQKR", tablebase::MAX_PIECES);
+    println!("           with --syzygy up to {} pieces with pawns, named like the table files: KPvKP", retrograde::MAX_PIECES);
+    println!("options: --dir <dir>  where tables are read from and written to (default .)");
+    println!("         --syzygy     write Syzygy WDL and DTZ tables instead, for test fixtures");
+    println!("         --wdl        with --syzygy, leave out the DTZ tables");
+    process::exit(2);
+}
+
+// Generates a table after the smaller ones its captures lead to, loading
+// those already in the directory
+fn build(signature : &str, dir : &str, tables : &mut tablebase::Tablebases) -> Result<(), String> {
+    let (name, _) = tablebase::parse_signature(signature)?;
+    if tables.contains(&name) {
+        return Ok(());
+    }
+    for dependency in tablebase::dependencies(&name)? {
+        if tables.contains(&dependency) {
+            continue;
+        }
+        let path = Path::new(dir).join(format!("{}.{}", dependency, tablebase::EXTENSION));
+        if path.exists() {
+            tables.insert(tablebase::load_table(&path.to_string_lossy())?);
+            println!("Loaded {}", path.to_string_lossy());
+        } else {
+            build(&dependency, dir, tables)?;
+        }
+    }
+
+    let started = Instant::now();
+    let table = tablebase::generate(&name, tables)?;
+    let path = tablebase::save_table(dir, &table)?;
+    println!("{}: {} positions in {:.1}s, written to {}", name, table.positions(), started.elapsed().as_secs_f64(), path);
+    for (side, white_to_move) in [("white", true), ("black", false)].iter() {
+        let (wins, draws, losses) = table.counts(*white_to_move);
+        println!("  {} to move: {} wins, {} draws, {} losses", side, wins, draws, losses);
+    }
+    if let Some((plies, fen)) = table.longest_mate() {
+        println!("  longest mate: {} plies, {}", plies, fen);
+    }
+    tables.insert(table);
+    Ok(())
+}
+
+// Solved here rather than loaded, the endings they lead to are only kept in memory
+fn build_syzygy(signature : &str, dir : &str, dtz : bool, solver : &mut retrograde::Solver) -> Result<(), String> {
+    let started = Instant::now();
+    solver.solve(signature)?;
+    for path in solver.write(signature, dir, dtz)? {
+        println!("{}: written to {}", signature, path);
+    }
+    println!("  {:.1}s", started.elapsed().as_secs_f64());
+    Ok(())
+}
+
+fn main() {
+    let mut signatures : Vec<String> = Vec::new();
+    let mut dir = String::from(".");
+    let mut syzygy = false;
+    let mut dtz = true;
+    let mut arg_iter = env::args().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--dir" {
+            dir = arg_iter.next().unwrap_or_else(|| usage());
+        } else if arg == "--syzygy" {
+            syzygy = true;
+        } else if arg == "--wdl" {
+            dtz = false;
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            signatures.push(arg);
+        }
+    }
+    if signatures.is_empty() || (!dtz && !syzygy) {
+        usage();
+    }
+
+    if syzygy {
+        let mut solver = retrograde::Solver::new();
+        for signature in &signatures {
+            if let Err(error) = build_syzygy(signature, &dir, dtz, &mut solver) {
+                println!("{}", error);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut tables = tablebase::Tablebases::new();
+    for signature in &signatures {
+        if let Err(error) = build(signature, &dir, &mut tables) {
+            println!("{}", error);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+mod board;
+mod endgame;
+mod eval;
+mod kpk;
+mod nnue;
+mod trainer;
+mod tuner;
+mod zobrist;
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+
+fn usage() -> ! {
+    println!("usage: train <positions> [options]");
+    println!("positions: one \"<fen> <result>\" per line, result 1-0, 1/2-1/2 or 0-1");
+    println!("options: --out <file>       where to write the network (default network.nnue)");
+    println!("         --network <file>   carry on training this network instead of a random one");
+    println!("         --epochs <n>       passes over the positions (default 10)");
+    println!("         --batch <n>        positions per weight update (default 256)");
+    println!("         --rate <value>     learning rate (default 0.001)");
+    println!("         --seed <n>         for the random start and the shuffling (default 1)");
+    process::exit(2);
+}
+
+fn main() {
+    let mut args : Vec<String> = Vec::new();
+    let mut out = String::from("network.nnue");
+    let mut start : Option<String> = None;
+    let mut options = trainer::TrainOptions{ epochs: 10, batch_size: 256, learning_rate: 0.001, seed: 1 };
+    let mut arg_iter = env::args().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--out" {
+            out = arg_iter.next().unwrap_or_else(|| usage());
+        } else if arg == "--network" {
+            start = Some(arg_iter.next().unwrap_or_else(|| usage()));
+        } else if arg == "--epochs" {
+            options.epochs = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--batch" {
+            options.batch_size = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--rate" {
+            options.learning_rate = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--seed" {
+            options.seed = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            args.push(arg);
+        }
+    }
+    if args.len() != 1 || options.batch_size == 0 || options.epochs == 0 {
+        usage();
+    }
+
+    let text = fs::read_to_string(&args[0]).unwrap_or_else(|error| {
+        println!("Could not read {}: {}", args[0], error);
+        process::exit(1);
+    });
+    let (positions, skipped) = tuner::parse_labelled_positions(&text);
+    println!("{} positions, {} lines skipped", positions.len(), skipped);
+    if positions.is_empty() {
+        process::exit(1);
+    }
+    let samples = trainer::samples(&positions);
+
+    let mut network = match start {
+        Some(path) => match nnue::load_network(&path) {
+            Ok(network) => trainer::from_network(&network),
+            Err(error) => {
+                println!("Could not load network: {}", error);
+                process::exit(1);
+            },
+        },
+        None => trainer::random_network(options.seed),
+    };
+    println!("starting error {:.6}", network.mean_error(&samples));
+
+    // Saved after every epoch so an interrupted run keeps its progress
+    let started = Instant::now();
+    trainer::train(&mut network, &samples, &options, |epoch, error, network| {
+        println!("epoch {}: error {:.6} ({:.1}s)", epoch, error, started.elapsed().as_secs_f64());
+        if let Err(error) = nnue::save_network(&out, &network.quantize()) {
+            println!("Could not save network: {}", error);
+            process::exit(1);
+        }
+    });
+    println!("Wrote {}", out);
+}
@@ -0,0 +1,221 @@
+use board;
+use nnue;
+use tuner;
+
+// Full precision copy of the network for training, quantized when saved
+#[derive(Clone)]
+pub struct FloatNetwork {
+    pub feature_weights: Vec<f32>,
+    pub feature_biases: Vec<f32>,
+    pub output_weights: Vec<f32>,
+    pub output_bias: f32,
+}
+
+// Inputs of both sides, side to move first, and the result from the side to move's point of view
+pub struct Sample {
+    features: [Vec<usize>; 2],
+    target: f32,
+}
+
+pub struct TrainOptions {
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    pub seed: u64,
+}
+
+// Keeps quantized weights, and accumulators summing a bias and 33 weights, inside an i16
+const MAX_WEIGHT : f32 = 1.98;
+const LN_10 : f32 = std::f32::consts::LN_10;
+
+fn next_random(state : &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// Uniform in -1..1
+fn random_unit(state : &mut u64) -> f32 {
+    (next_random(state) >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+pub fn random_network(seed : u64) -> FloatNetwork {
+    let mut state = seed.max(1);
+    let feature_scale = 0.1;
+    let output_scale = 1.0 / (2 * nnue::HIDDEN) as f32;
+    FloatNetwork{
+        feature_weights: (0..nnue::INPUTS * nnue::HIDDEN).map(|_| random_unit(&mut state) * feature_scale).collect(),
+        feature_biases: vec![0.1; nnue::HIDDEN],
+        output_weights: (0..2 * nnue::HIDDEN).map(|_| random_unit(&mut state) * output_scale).collect(),
+        output_bias: 0.0,
+    }
+}
+
+// To carry on training from a saved network
+pub fn from_network(network : &nnue::Network) -> FloatNetwork {
+    let qa = nnue::QA as f32;
+    let qb = nnue::QB as f32;
+    FloatNetwork{
+        feature_weights: network.feature_weights.iter().map(|weight| *weight as f32 / qa).collect(),
+        feature_biases: network.feature_biases.iter().map(|weight| *weight as f32 / qa).collect(),
+        output_weights: network.output_weights.iter().map(|weight| *weight as f32 / qb).collect(),
+        output_bias: network.output_bias as f32 / (qa * qb),
+    }
+}
+
+fn quantize(values : &[f32], scale : i32) -> Vec<i16> {
+    values.iter().map(|value| (value * scale as f32).round().max(i16::MIN as f32).min(i16::MAX as f32) as i16).collect()
+}
+
+impl FloatNetwork {
+    pub fn quantize(& self) -> nnue::Network {
+        nnue::Network{
+            feature_weights: quantize(&self.feature_weights, nnue::QA),
+            feature_biases: quantize(&self.feature_biases, nnue::QA),
+            output_weights: quantize(&self.output_weights, nnue::QB),
+            output_bias: (self.output_bias * (nnue::QA * nnue::QB) as f32).round() as i32,
+        }
+    }
+
+    // Hidden layer of both sides before activation, and the output in units of SCALE centipawns
+    fn forward(& self, sample : &Sample) -> ([Vec<f32>; 2], f32) {
+        let mut hidden = [self.feature_biases.clone(), self.feature_biases.clone()];
+        let mut output = self.output_bias;
+        for (side, values) in hidden.iter_mut().enumerate() {
+            for feature in &sample.features[side] {
+                let row = &self.feature_weights[feature * nnue::HIDDEN..(feature + 1) * nnue::HIDDEN];
+                for (value, weight) in values.iter_mut().zip(row) {
+                    *value += *weight;
+                }
+            }
+            let weights = &self.output_weights[side * nnue::HIDDEN..(side + 1) * nnue::HIDDEN];
+            for (value, weight) in values.iter().zip(weights) {
+                output += value.clamp(0.0, 1.0) * weight;
+            }
+        }
+        (hidden, output)
+    }
+
+    // Same mapping from centipawns to an expected result as the tuner with K = 1
+    fn predict(output : f32) -> f32 {
+        1.0 / (1.0 + (-output * LN_10 * nnue::SCALE as f32 / 400.0).exp())
+    }
+
+    pub fn mean_error(& self, samples : &[Sample]) -> f32 {
+        let mut total = 0.0;
+        for sample in samples {
+            let difference = FloatNetwork::predict(self.forward(sample).1) - sample.target;
+            total += difference * difference;
+        }
+        total / samples.len().max(1) as f32
+    }
+}
+
+pub fn samples(positions : &[tuner::LabelledPosition]) -> Vec<Sample> {
+    positions.iter().map(|position| {
+        let us = position.board.protagonist;
+        let them = if us == board::WHITE { board::BLACK } else { board::WHITE };
+        let target = if us == board::WHITE { position.result } else { 1.0 - position.result };
+        Sample{
+            features: [nnue::active_features(&position.board, us), nnue::active_features(&position.board, them)],
+            target: target as f32,
+        }
+    }).collect()
+}
+
+// Adam moments for one group of weights
+struct Moments {
+    first: Vec<f32>,
+    second: Vec<f32>,
+}
+
+fn moments(size : usize) -> Moments {
+    Moments{ first: vec![0.0; size], second: vec![0.0; size] }
+}
+
+fn adam_step(weight : &mut f32, gradient : f32, moments : &mut Moments, index : usize, learning_rate : f32) {
+    moments.first[index] = 0.9 * moments.first[index] + 0.1 * gradient;
+    moments.second[index] = 0.999 * moments.second[index] + 0.001 * gradient * gradient;
+    *weight -= learning_rate * moments.first[index] / (moments.second[index].sqrt() + 1e-8);
+}
+
+// Mini-batch Adam on the squared error between prediction and result. Only the
+// feature rows used in a batch are stepped, which keeps an epoch proportional to
+// the number of positions. on_epoch gets the epoch number, the mean error and the network.
+pub fn train<F : FnMut(usize, f32, &FloatNetwork)>(network : &mut FloatNetwork, samples : &[Sample], options : &TrainOptions, mut on_epoch : F) {
+    let hidden = nnue::HIDDEN;
+    let mut feature_moments = moments(network.feature_weights.len());
+    let mut bias_moments = moments(hidden);
+    let mut output_moments = moments(2 * hidden);
+    let mut output_bias_moments = moments(1);
+
+    let mut order : Vec<usize> = (0..samples.len()).collect();
+    let mut state = options.seed.max(1);
+    // Where a feature's gradients are in this batch, usize::MAX when unused
+    let mut row_of = vec![usize::MAX; nnue::INPUTS];
+
+    for epoch in 1..=options.epochs {
+        for index in (1..order.len()).rev() {
+            order.swap(index, next_random(&mut state) as usize % (index + 1));
+        }
+        for batch in order.chunks(options.batch_size.max(1)) {
+            let mut feature_gradients : Vec<(usize, Vec<f32>)> = Vec::new();
+            let mut bias_gradients = vec![0.0; hidden];
+            let mut output_gradients = vec![0.0; 2 * hidden];
+            let mut output_bias_gradient = 0.0;
+
+            for sample_index in batch {
+                let sample = &samples[*sample_index];
+                let (layer, output) = network.forward(sample);
+                let prediction = FloatNetwork::predict(output);
+                let output_gradient = 2.0 * (prediction - sample.target) * prediction * (1.0 - prediction)
+                    * LN_10 * nnue::SCALE as f32 / 400.0 / batch.len() as f32;
+                output_bias_gradient += output_gradient;
+                for side in 0..2 {
+                    let mut hidden_gradients = vec![0.0; hidden];
+                    for index in 0..hidden {
+                        let value = layer[side][index];
+                        output_gradients[side * hidden + index] += output_gradient * value.clamp(0.0, 1.0);
+                        if value > 0.0 && value < 1.0 {
+                            hidden_gradients[index] = output_gradient * network.output_weights[side * hidden + index];
+                        }
+                    }
+                    for (bias, gradient) in bias_gradients.iter_mut().zip(&hidden_gradients) {
+                        *bias += *gradient;
+                    }
+                    for feature in &sample.features[side] {
+                        if row_of[*feature] == usize::MAX {
+                            row_of[*feature] = feature_gradients.len();
+                            feature_gradients.push((*feature, vec![0.0; hidden]));
+                        }
+                        let row = row_of[*feature];
+                        for (sum, gradient) in feature_gradients[row].1.iter_mut().zip(&hidden_gradients) {
+                            *sum += *gradient;
+                        }
+                    }
+                }
+            }
+
+            for (feature, gradients) in &feature_gradients {
+                row_of[*feature] = usize::MAX;
+                for (index, gradient) in gradients.iter().enumerate() {
+                    let weight = feature * hidden + index;
+                    adam_step(&mut network.feature_weights[weight], *gradient, &mut feature_moments, weight, options.learning_rate);
+                    network.feature_weights[weight] = network.feature_weights[weight].clamp(-MAX_WEIGHT, MAX_WEIGHT);
+                }
+            }
+            for (index, gradient) in bias_gradients.iter().enumerate() {
+                adam_step(&mut network.feature_biases[index], *gradient, &mut bias_moments, index, options.learning_rate);
+                network.feature_biases[index] = network.feature_biases[index].clamp(-MAX_WEIGHT, MAX_WEIGHT);
+            }
+            for (index, gradient) in output_gradients.iter().enumerate() {
+                adam_step(&mut network.output_weights[index], *gradient, &mut output_moments, index, options.learning_rate);
+                network.output_weights[index] = network.output_weights[index].clamp(-MAX_WEIGHT, MAX_WEIGHT);
+            }
+            adam_step(&mut network.output_bias, output_bias_gradient, &mut output_bias_moments, 0, options.learning_rate);
+            network.output_bias = network.output_bias.clamp(-MAX_WEIGHT, MAX_WEIGHT);
+        }
+        on_epoch(epoch, network.mean_error(samples), network);
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+// A transposition table shared by every search thread without locks. Each
+// slot holds the entry's data and its key xored with that data, so a slot
+// torn by two threads writing at once no longer matches its key and is
+// ignored, rather than read as another position's entry.
+
+pub const DEFAULT_MEGABYTES : usize = 16;
+pub const MAX_MEGABYTES : usize = 4096;
+
+const SLOT_BYTES : usize = 16;
+// Generations wrap around in the six bits they are stored in
+const GENERATIONS : u8 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Exact,
+    // The score is at least this, the search failed high
+    Lower,
+    // The score is at most this, every move failed low
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub amove: Option<Move>,
+    pub score: i32,
+    pub depth: u16,
+    pub bound: Bound,
+    generation: u8,
+}
+
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+pub struct Table {
+    slots: Vec<Slot>,
+    generation: AtomicU8,
+}
+
+// Six bits for each square and three for the colorless promotion piece,
+// 0 standing for no move
+fn encode_move(amove : Option<Move>) -> u64 {
+    match amove {
+        Some(((row, col), (to_row, to_col, promotion))) => {
+            1 << 15 | ((row * 8 + col) as u64) << 9 | ((to_row * 8 + to_col) as u64) << 3 | promotion as u64 & 0b111
+        },
+        None => 0,
+    }
+}
+
+fn decode_move(raw : u64) -> Option<Move> {
+    if raw & 1 << 15 == 0 {
+        return None;
+    }
+    let from = (raw >> 9 & 63) as usize;
+    let to = (raw >> 3 & 63) as usize;
+    Some(((from / 8, from % 8), (to / 8, to % 8, (raw & 0b111) as u8)))
+}
+
+// Score in the low 32 bits, then the move, the depth, the bound and the generation
+fn pack(entry : &Entry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    };
+    entry.score as u32 as u64 | encode_move(entry.amove) << 32 | (entry.depth.min(255) as u64) << 48
+        | bound << 56 | ((entry.generation % GENERATIONS) as u64) << 58
+}
+
+fn unpack(data : u64) -> Entry {
+    let bound = match data >> 56 & 0b11 {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        _ => Bound::Upper,
+    };
+    Entry{ amove: decode_move(data >> 32 & 0xFFFF), score: data as u32 as i32, depth: (data >> 48 & 0xFF) as u16,
+           bound, generation: (data >> 58) as u8 }
+}
+
+impl Table {
+    // The largest power of two number of slots that fits
+    pub fn new(megabytes : usize) -> Table {
+        let wanted = (megabytes.clamp(1, MAX_MEGABYTES) << 20) / SLOT_BYTES;
+        let count = 1 << (usize::BITS - 1 - wanted.leading_zeros());
+        let slots = (0..count).map(|_| Slot{ check: AtomicU64::new(0), data: AtomicU64::new(0) }).collect();
+        Table{ slots, generation: AtomicU8::new(0) }
+    }
+
+    pub fn len(& self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn clear(& self) {
+        for slot in &self.slots {
+            slot.check.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    // Called before each search, so entries left by older ones give way first
+    pub fn new_search(& self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        self.generation.store((generation + 1) % GENERATIONS, Ordering::Relaxed);
+    }
+
+    fn slot(& self, key : u64) -> &Slot {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn probe(& self, key : u64) -> Option<Entry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        if data == 0 || slot.check.load(Ordering::Relaxed) ^ data != key {
+            return None;
+        }
+        Some(unpack(data))
+    }
+
+    // Keeps a deeper result for the same position from this search, and
+    // replaces anything else
+    pub fn store(& self, key : u64, amove : Option<Move>, score : i32, depth : u16, bound : Bound) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let slot = self.slot(key);
+        if let Some(old) = self.probe(key) {
+            if old.generation == generation && old.depth > depth && bound != Bound::Exact {
+                return;
+            }
+        }
+        // A search that failed low has no best move, keep the one known
+        let amove = amove.or_else(|| self.probe(key).and_then(|old| old.amove));
+        let data = pack(&Entry{ amove, score, depth, bound, generation });
+        slot.data.store(data, Ordering::Relaxed);
+        slot.check.store(key ^ data, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,98 @@
+mod board;
+mod endgame;
+mod eval;
+mod kpk;
+mod nnue;
+mod tuner;
+mod zobrist;
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+
+fn usage() -> ! {
+    println!("usage: tune <positions> [options]");
+    println!("positions: one \"<fen> <result>\" per line, result 1-0, 1/2-1/2 or 0-1");
+    println!("options: --out <file>       where to write the tuned parameters (default tuned.txt)");
+    println!("         --params <file>    start from these parameters instead of the defaults");
+    println!("         --k <value>        sigmoid scaling, fitted to the positions by default");
+    println!("         --step <n>         centipawns to nudge every weight by (default 1)");
+    println!("         --passes <n>       stop after this many passes (default 100)");
+    println!("         --only <prefixes>  comma separated weight name prefixes to tune");
+    process::exit(2);
+}
+
+fn main() {
+    let mut args : Vec<String> = Vec::new();
+    let mut out = String::from("tuned.txt");
+    let mut start = eval::DEFAULT_PARAMS;
+    let mut k : Option<f64> = None;
+    let mut options = tuner::TuneOptions{ k: 1.0, step: 1, max_passes: 100, only: Vec::new() };
+    let mut arg_iter = env::args().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--out" {
+            out = arg_iter.next().unwrap_or_else(|| usage());
+        } else if arg == "--params" {
+            let path = arg_iter.next().unwrap_or_else(|| usage());
+            start = eval::load_params(&path).unwrap_or_else(|error| {
+                println!("Could not load parameters: {}", error);
+                process::exit(1);
+            });
+        } else if arg == "--k" {
+            k = Some(arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage()));
+        } else if arg == "--step" {
+            options.step = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--passes" {
+            options.max_passes = arg_iter.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--only" {
+            let prefixes = arg_iter.next().unwrap_or_else(|| usage());
+            options.only = prefixes.split(',').map(|prefix| prefix.to_string()).collect();
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            args.push(arg);
+        }
+    }
+    if args.len() != 1 || options.step <= 0 {
+        usage();
+    }
+
+    let text = fs::read_to_string(&args[0]).unwrap_or_else(|error| {
+        println!("Could not read {}: {}", args[0], error);
+        process::exit(1);
+    });
+    let (positions, skipped) = tuner::parse_labelled_positions(&text);
+    println!("{} positions, {} lines skipped", positions.len(), skipped);
+    if positions.is_empty() {
+        process::exit(1);
+    }
+
+    options.k = match k {
+        Some(k) => k,
+        None => tuner::fit_k(&start, &positions),
+    };
+    println!("K = {:.4}, starting error {:.6}", options.k, tuner::mean_error(&start, &positions, options.k));
+
+    // The file is rewritten after every pass so an interrupted run keeps its progress
+    let started = Instant::now();
+    let tuned = tuner::local_search(&start, &positions, &options, |pass, error, params| {
+        println!("pass {}: error {:.6} ({:.1}s)", pass, error, started.elapsed().as_secs_f64());
+        if let Err(error) = eval::save_params(&out, params) {
+            println!("Could not save parameters: {}", error);
+            process::exit(1);
+        }
+    });
+
+    let names = tuner::weight_names(&tuned);
+    let mut before : Vec<i32> = Vec::new();
+    start.clone().for_each_weight(|_, value| before.push(*value));
+    let mut index = 0;
+    tuned.clone().for_each_weight(|_, value| {
+        if *value != before[index] {
+            println!("{} {} -> {}", names[index], before[index], value);
+        }
+        index += 1;
+    });
+    println!("Wrote {}", out);
+}
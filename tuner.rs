@@ -0,0 +1,169 @@
+use board;
+use eval;
+
+// A quiet position with the result of the game it came from,
+// 1.0 for a white win, 0.5 for a draw and 0.0 for a black win
+pub struct LabelledPosition {
+    pub board: board::ChessBoard,
+    pub result: f64,
+}
+
+fn parse_result(token : &str) -> Option<f64> {
+    let token = token.trim_matches(|c : char| c == '"' || c == ';' || c == '[' || c == ']' || c == '(' || c == ')');
+    match token {
+        "1-0" | "1.0" => Some(1.0),
+        "0-1" | "0.0" => Some(0.0),
+        "1/2-1/2" | "½-½" | "0.5" => Some(0.5),
+        _ => None,
+    }
+}
+
+// "<fen> <result>", where the result may be quoted, bracketed or given as an EPD
+// c9 operation, and the FEN clocks may be left out
+pub fn parse_labelled_position(line : &str) -> Option<LabelledPosition> {
+    let fields : Vec<&str> = line.split_whitespace().filter(|field| *field != "c9" && *field != ";").collect();
+    if fields.len() < 5 {
+        return None;
+    }
+    let result = parse_result(fields[fields.len() - 1])?;
+    let fen = fields[..fields.len() - 1].iter().take(6).cloned().collect::<Vec<&str>>().join(" ");
+    if fen.matches('/').count() != 7 {
+        return None;
+    }
+
+    let stuff = [[0u8; 8]; 8];
+    let mut board = board::build_board(stuff);
+    board.from_fen(&fen);
+    Some(LabelledPosition{ board, result })
+}
+
+// Skips lines that don't parse and positions with the side to move in check,
+// which are not quiet. Returns the positions and the number of skipped lines.
+pub fn parse_labelled_positions(text : &str) -> (Vec<LabelledPosition>, usize) {
+    let mut positions = Vec::new();
+    let mut skipped = 0;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_labelled_position(line) {
+            Some(position) if !position.board.protagonist_in_check() => positions.push(position),
+            _ => skipped += 1,
+        }
+    }
+    (positions, skipped)
+}
+
+// Expected score for white from an evaluation in centipawns
+pub fn sigmoid(k : f64, centipawns : i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * centipawns as f64 / 400.0))
+}
+
+fn white_eval(params : &eval::EvalParams, board : &board::ChessBoard) -> i32 {
+    let score = eval::eval_with(params, board);
+    if board.protagonist == board::WHITE { score } else { -score }
+}
+
+fn error_of(evals : &[i32], positions : &[LabelledPosition], k : f64) -> f64 {
+    let mut total = 0.0;
+    for (position, score) in positions.iter().zip(evals.iter()) {
+        let difference = position.result - sigmoid(k, *score);
+        total += difference * difference;
+    }
+    total / positions.len().max(1) as f64
+}
+
+// Mean squared difference between the game results and the predicted results
+pub fn mean_error(params : &eval::EvalParams, positions : &[LabelledPosition], k : f64) -> f64 {
+    let evals : Vec<i32> = positions.iter().map(|position| white_eval(params, &position.board)).collect();
+    error_of(&evals, positions, k)
+}
+
+// The scaling constant that best maps evaluations to results before any
+// weight changes, found by narrowing down on the minimum
+pub fn fit_k(params : &eval::EvalParams, positions : &[LabelledPosition]) -> f64 {
+    let evals : Vec<i32> = positions.iter().map(|position| white_eval(params, &position.board)).collect();
+    let mut low = 0.0;
+    let mut high = 3.0;
+    for _ in 0..60 {
+        let left = low + (high - low) / 3.0;
+        let right = high - (high - low) / 3.0;
+        if error_of(&evals, positions, left) < error_of(&evals, positions, right) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+    (low + high) / 2.0
+}
+
+pub fn weight_names(params : &eval::EvalParams) -> Vec<String> {
+    let mut names = Vec::new();
+    params.clone().for_each_weight(|name, _| names.push(name.to_string()));
+    names
+}
+
+fn weight(params : &mut eval::EvalParams, index : usize) -> i32 {
+    let mut count = 0;
+    let mut found = 0;
+    params.for_each_weight(|_, value| {
+        if count == index {
+            found = *value;
+        }
+        count += 1;
+    });
+    found
+}
+
+fn set_weight(params : &mut eval::EvalParams, index : usize, new_value : i32) {
+    let mut count = 0;
+    params.for_each_weight(|_, value| {
+        if count == index {
+            *value = new_value;
+        }
+        count += 1;
+    });
+}
+
+pub struct TuneOptions {
+    pub k: f64,
+    pub step: i32,
+    pub max_passes: usize,
+    pub only: Vec<String>, // name prefixes to tune, everything when empty
+}
+
+// Texel's local search: nudge every weight up or down by a step and keep the
+// change whenever the error drops, until a full pass improves nothing.
+// on_pass gets the pass number, the error and the parameters after every pass.
+pub fn local_search<F : FnMut(usize, f64, &eval::EvalParams)>(start : &eval::EvalParams, positions : &[LabelledPosition],
+                                                                options : &TuneOptions, mut on_pass : F) -> eval::EvalParams {
+    let mut params = start.clone();
+    let names = weight_names(&params);
+    let tuned : Vec<usize> = (0..names.len()).filter(|index| {
+        options.only.is_empty() || options.only.iter().any(|prefix| names[*index].starts_with(prefix.as_str()))
+    }).collect();
+
+    let mut best_error = mean_error(&params, positions, options.k);
+    for pass in 1..=options.max_passes {
+        let mut improved = false;
+        for index in &tuned {
+            let original = weight(&mut params, *index);
+            for delta in [options.step, -options.step].iter() {
+                set_weight(&mut params, *index, original + delta);
+                let error = mean_error(&params, positions, options.k);
+                if error < best_error {
+                    best_error = error;
+                    improved = true;
+                    break;
+                }
+                set_weight(&mut params, *index, original);
+            }
+        }
+        on_pass(pass, best_error, &params);
+        if !improved {
+            break;
+        }
+    }
+    params
+}
@@ -0,0 +1,323 @@
+use board;
+use clock;
+use eval;
+use kpk;
+use polyglot;
+use search;
+use syzygy;
+use transposition;
+use zobrist;
+
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// Source square, then destination square and promotion piece
+type Move = ((usize, usize),(usize, usize, u8));
+
+const NAME : &str = "RustChess";
+const MAX_MULTIPV : usize = 256;
+const MAX_THREADS : usize = 256;
+const MAX_CONTEMPT : i32 = 100;
+
+struct Options {
+    multipv: usize,
+    threads: usize,
+    search: search::SearchOptions,
+    // go mate tries only checking moves for the attacker
+    mate_checks_only: bool,
+    own_book: bool,
+    book_selection: polyglot::Selection,
+    move_overhead: Duration,
+}
+
+// The search running in the background, what stops it and what turns its
+// pondering into a timed search
+struct Running {
+    stop: Arc<AtomicBool>,
+    ponder: Option<Arc<AtomicBool>>,
+    handle: thread::JoinHandle<()>,
+}
+
+// Coordinate notation for a legal move, None if it is not one
+pub fn parse_move(board : &mut board::ChessBoard, text : &str) -> Option<Move> {
+    board.get_legal_moves().into_iter().find(|amove| board::move_to_string(*amove) == text)
+}
+
+// "startpos" or "fen <fen>", then optionally "moves" and the moves played.
+// Gives the hashes of the positions before the last, for repetitions.
+pub fn set_position(board : &mut board::ChessBoard, tokens : &[&str]) -> Result<Vec<u64>, String> {
+    let moves_at = tokens.iter().position(|token| *token == "moves").unwrap_or(tokens.len());
+    match tokens.first() {
+        Some(&"startpos") => board.from_fen(board::START_FEN),
+        Some(&"fen") if moves_at > 1 => board.from_fen(&tokens[1..moves_at].join(" ")),
+        _ => return Err(format!("bad position: {}", tokens.join(" "))),
+    }
+    let mut history = Vec::new();
+    for text in tokens.iter().skip(moves_at + 1) {
+        let amove = parse_move(board, text).ok_or(format!("illegal move {}", text))?;
+        history.push(zobrist::hash(board));
+        board.make(amove.0, amove.1);
+    }
+    Ok(history)
+}
+
+pub fn info_line(info : &search::SearchInfo) -> String {
+    let millis = info.elapsed.as_millis() as u64;
+    let nps = info.nodes * 1000 / millis.max(1);
+    let pv : Vec<String> = info.pv.iter().map(|amove| board::move_to_string(*amove)).collect();
+    format!("info depth {} multipv {} score {} nodes {} nps {} time {} pv {}",
+            info.depth, info.multipv, eval::score_to_uci(info.score), info.nodes, nps, millis, pv.join(" "))
+}
+
+// The limits of a go command. On a clock the time manager gets a budget,
+// keeping the overhead given back for every move.
+pub fn parse_go(tokens : &[&str], white_to_move : bool, overhead : Duration) -> search::SearchLimits {
+    let mut limits = search::default_limits();
+    // White's then black's
+    let mut time_left : [Option<u64>; 2] = [None, None];
+    let mut increment : [u64; 2] = [0, 0];
+    let mut moves_to_go = None;
+    let mut index = 0;
+    while index < tokens.len() {
+        let value = tokens.get(index + 1).and_then(|text| text.parse::<u64>().ok());
+        match (tokens[index], value) {
+            ("depth", Some(depth)) => limits.depth = depth.min(search::MAX_DEPTH as u64) as u16,
+            ("nodes", Some(nodes)) => limits.nodes = Some(nodes),
+            ("movetime", Some(millis)) => limits.movetime = Some(Duration::from_millis(millis)),
+            ("mate", Some(moves)) => limits.mate = Some(moves.min(search::MAX_DEPTH as u64 / 2) as u16),
+            ("wtime", Some(millis)) => time_left[0] = Some(millis),
+            ("btime", Some(millis)) => time_left[1] = Some(millis),
+            ("winc", Some(millis)) => increment[0] = millis,
+            ("binc", Some(millis)) => increment[1] = millis,
+            ("movestogo", Some(moves)) => moves_to_go = Some(moves.min(u32::MAX as u64) as u32),
+            ("ponder", _) => {
+                limits.ponder = Some(Arc::new(AtomicBool::new(true)));
+                index += 1;
+                continue;
+            },
+            _ => {
+                index += 1;
+                continue;
+            },
+        }
+        index += 2;
+    }
+    // Without a mate, a normal search no deeper than the mate would be
+    if let Some(moves) = limits.mate {
+        limits.depth = limits.depth.min(2 * moves);
+    }
+    let side = if white_to_move { 0 } else { 1 };
+    if let (Some(left), None) = (time_left[side], limits.movetime) {
+        let control = clock::TimeControl{ time_left: Duration::from_millis(left), increment: Duration::from_millis(increment[side]),
+                                          moves_to_go, overhead };
+        limits.clock = Some(clock::budget(&control));
+    }
+    limits
+}
+
+fn stop_search(running : &mut Option<Running>) {
+    if let Some(search) = running.take() {
+        search.stop.store(true, Ordering::Relaxed);
+        search.handle.join().unwrap();
+    }
+}
+
+// The best move and the reply expected to it, from the line or, when the
+// line was cut short, from the table
+pub fn expected_moves(board : &mut board::ChessBoard, result : &search::SearchResult, table : &transposition::Table) -> Vec<Move> {
+    let mut moves : Vec<Move> = result.lines.first().map_or(Vec::new(), |line| line.moves.iter().take(2).copied().collect());
+    if moves.len() == 1 {
+        let state = board.save_state();
+        let captured_piece = board.make(moves[0].0, moves[0].1).unwrap();
+        let reply = table.probe(zobrist::hash(board)).and_then(|entry| entry.amove);
+        if let Some(reply) = reply.filter(|reply| board.get_legal_moves().contains(reply)) {
+            moves.push(reply);
+        }
+        board.unmake(moves[0].0, moves[0].1, captured_piece);
+        board.restore_state(state);
+    }
+    moves
+}
+
+fn bestmove_line(moves : &[Move]) -> String {
+    match moves {
+        [] => "bestmove 0000".to_string(),
+        [amove] => format!("bestmove {}", board::move_to_string(*amove)),
+        [amove, reply, ..] => format!("bestmove {} ponder {}", board::move_to_string(*amove), board::move_to_string(*reply)),
+    }
+}
+
+// An infinite or pondering search only answers once told to stop, or on a
+// ponderhit, even when it finished before
+fn start_search(board : &board::ChessBoard, limits : search::SearchLimits, options : search::SearchOptions, threads : usize,
+                table : Arc<transposition::Table>, checks_only : bool, infinite : bool) -> Running {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let ponder = limits.ponder.clone();
+    let mut board = board.clone();
+    let handle = thread::spawn(move || {
+        let mut moves = Vec::new();
+        if let Some(mate) = limits.mate {
+            match search::mate_search(&mut board, mate, checks_only, &thread_stop) {
+                search::MateResult::Mate(line) => {
+                    let pv : Vec<String> = line.iter().map(|amove| board::move_to_string(*amove)).collect();
+                    println!("info depth {} score mate {} pv {}", line.len(), line.len().div_ceil(2), pv.join(" "));
+                    moves = line;
+                },
+                search::MateResult::NoMate => println!("info string No mate in {}", mate),
+                search::MateResult::Stopped => {},
+            }
+        }
+        if moves.is_empty() {
+            let result = search::parallel_search(&mut board, &limits, &options, threads, &table, &thread_stop, |info| println!("{}", info_line(info)));
+            moves = expected_moves(&mut board, &result, &table);
+        }
+        let pondering = || limits.ponder.as_ref().is_some_and(|ponder| ponder.load(Ordering::Relaxed));
+        while (infinite || pondering()) && !thread_stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        println!("{}", bestmove_line(&moves));
+    });
+    Running{ stop, ponder, handle }
+}
+
+fn set_option(options : &mut Options, table : &mut Arc<transposition::Table>, tokens : &[&str]) {
+    let value_at = tokens.iter().position(|token| *token == "value").unwrap_or(tokens.len());
+    if tokens.first() != Some(&"name") {
+        return;
+    }
+    let name = tokens[1..value_at].join(" ").to_lowercase();
+    let value = tokens.get(value_at + 1..).map_or(String::new(), |value| value.join(" "));
+    match name.as_str() {
+        "multipv" => options.multipv = value.parse().unwrap_or(1).clamp(1, MAX_MULTIPV),
+        "threads" => options.threads = value.parse().unwrap_or(1).clamp(1, MAX_THREADS),
+        "hash" => {
+            let megabytes = value.parse().unwrap_or(transposition::DEFAULT_MEGABYTES);
+            *table = Arc::new(transposition::Table::new(megabytes));
+        },
+        "ownbook" => options.own_book = value == "true",
+        "nullmove" => options.search.null_move = value == "true",
+        "latemovereductions" => options.search.late_move_reductions = value == "true",
+        "reversefutility" => options.search.reverse_futility = value == "true",
+        "futility" => options.search.futility = value == "true",
+        "aspirationwindows" => options.search.aspiration = value == "true",
+        "checkextensions" => options.search.check_extensions = value == "true",
+        "singularextensions" => options.search.singular_extensions = value == "true",
+        "onereplyextensions" => options.search.one_reply_extensions = value == "true",
+        "matechecksonly" => options.mate_checks_only = value == "true",
+        // Only says the GUI may send go ponder
+        "ponder" => {},
+        "contempt" => options.search.contempt = value.parse().unwrap_or(0).clamp(-MAX_CONTEMPT, MAX_CONTEMPT),
+        "move overhead" => {
+            let millis = value.parse().unwrap_or(0).min(clock::MAX_OVERHEAD_MILLIS);
+            options.move_overhead = Duration::from_millis(millis);
+        },
+        "bookfile" => match polyglot::load_book(&value) {
+            Ok(book) => polyglot::set_book(book),
+            Err(error) => println!("info string Could not load book: {}", error),
+        },
+        "bookbestmove" => {
+            options.book_selection = if value == "true" { polyglot::Selection::Best } else { polyglot::Selection::Weighted };
+        },
+        "syzygypath" => {
+            let tables = syzygy::set_path(&value);
+            println!("info string {} Syzygy tables found", tables);
+        },
+        _ => println!("info string Unknown option {}", name),
+    }
+}
+
+// Reads commands from standard input until quit. The search runs on its own
+// thread so stop can be read while it thinks.
+pub fn run(mut board : board::ChessBoard, own_book : bool, book_selection : polyglot::Selection) {
+    let mut options = Options{ multipv: 1, threads: 1, search: search::default_options(), mate_checks_only: false, own_book, book_selection,
+                               move_overhead: clock::DEFAULT_OVERHEAD };
+    let mut running : Option<Running> = None;
+    // Kept from search to search, cleared for a new game
+    let mut table = Arc::new(transposition::Table::new(transposition::DEFAULT_MEGABYTES));
+    let mut history : Vec<u64> = Vec::new();
+    board.from_fen(board::START_FEN);
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let tokens : Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"uci") => {
+                println!("id name {}", NAME);
+                println!("id author the {} authors", NAME);
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTIPV);
+                println!("option name Ponder type check default false");
+                println!("option name Contempt type spin default 0 min {} max {}", -MAX_CONTEMPT, MAX_CONTEMPT);
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name Hash type spin default {} min 1 max {}", transposition::DEFAULT_MEGABYTES, transposition::MAX_MEGABYTES);
+                println!("option name OwnBook type check default {}", own_book);
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookBestMove type check default {}", book_selection == polyglot::Selection::Best);
+                println!("option name SyzygyPath type string default <empty>");
+                // Switches for measuring the selective search in self-play
+                for name in ["NullMove", "LateMoveReductions", "ReverseFutility", "Futility", "AspirationWindows",
+                             "CheckExtensions", "SingularExtensions", "OneReplyExtensions"].iter() {
+                    println!("option name {} type check default true", name);
+                }
+                println!("option name MateChecksOnly type check default false");
+                println!("option name Move Overhead type spin default {} min 0 max {}", clock::DEFAULT_OVERHEAD.as_millis(), clock::MAX_OVERHEAD_MILLIS);
+                println!("uciok");
+            },
+            Some(&"isready") => {
+                // Built on first use, better now than during the first search
+                kpk::bitbase();
+                println!("readyok");
+            },
+            Some(&"setoption") => {
+                stop_search(&mut running);
+                set_option(&mut options, &mut table, &tokens[1..]);
+            },
+            Some(&"ucinewgame") => {
+                stop_search(&mut running);
+                table.clear();
+                history.clear();
+                board.from_fen(board::START_FEN);
+            },
+            Some(&"position") => {
+                stop_search(&mut running);
+                match set_position(&mut board, &tokens[1..]) {
+                    Ok(keys) => history = keys,
+                    Err(error) => {
+                        history.clear();
+                        println!("info string {}", error);
+                    },
+                }
+            },
+            Some(&"go") => {
+                stop_search(&mut running);
+                let mut limits = parse_go(&tokens[1..], board.protagonist == board::WHITE, options.move_overhead);
+                // A book move now would answer before the ponderhit
+                let book_move = if options.own_book && limits.ponder.is_none() { polyglot::book_move(&mut board, options.book_selection) } else { None };
+                if let Some(amove) = book_move {
+                    println!("bestmove {}", board::move_to_string(amove));
+                    continue;
+                }
+                limits.multipv = options.multipv;
+                limits.history = history.clone();
+                let infinite = tokens.contains(&"infinite");
+                running = Some(start_search(&board, limits, options.search, options.threads, table.clone(), options.mate_checks_only, infinite));
+            },
+            Some(&"ponderhit") => {
+                if let Some(ponder) = running.as_ref().and_then(|search| search.ponder.as_ref()) {
+                    ponder.store(false, Ordering::Relaxed);
+                }
+            },
+            Some(&"stop") => stop_search(&mut running),
+            Some(&"quit") => break,
+            _ => {},
+        }
+    }
+    stop_search(&mut running);
+}
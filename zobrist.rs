@@ -0,0 +1,101 @@
+use board;
+
+// Keys are generated at compile time with splitmix64 from a fixed seed,
+// so hashes are the same from run to run
+const fn splitmix(state : u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+const fn key(index : u64) -> u64 {
+    splitmix(0x5EED_C0DE_u64.wrapping_add(index.wrapping_mul(0x9E3779B97F4A7C15)))
+}
+
+// Indexed by [row * 8 + col][piece], piece being the raw board value with its color bit
+const fn build_piece_keys() -> [[u64; 16]; 64] {
+    let mut keys = [[0u64; 16]; 64];
+    let mut square = 0;
+    while square < 64 {
+        let mut piece = 0;
+        while piece < 16 {
+            keys[square][piece] = key((square * 16 + piece) as u64);
+            piece += 1;
+        }
+        square += 1;
+    }
+    keys
+}
+
+pub const PIECE_KEYS : [[u64; 16]; 64] = build_piece_keys();
+pub const WHITE_TO_MOVE_KEY : u64 = key(1024);
+pub const CASTLE_KEYS : [u64; 4] = [key(1025), key(1026), key(1027), key(1028)];
+pub const EP_KEYS : [u64; 8] = [key(1029), key(1030), key(1031), key(1032), key(1033), key(1034), key(1035), key(1036)];
+
+// The pieces alone, which the board keeps up to date as it moves
+pub fn piece_hash(board : &board::ChessBoard) -> u64 {
+    let mut hash : u64 = 0;
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if piece != 0b0000 {
+                hash ^= PIECE_KEYS[row * 8 + col][piece as usize];
+            }
+        }
+    }
+    hash
+}
+
+pub fn hash(board : &board::ChessBoard) -> u64 {
+    let mut hash = board.piece_key;
+    if board.protagonist == board::WHITE {
+        hash ^= WHITE_TO_MOVE_KEY;
+    }
+    if board.white_kingside_castle {
+        hash ^= CASTLE_KEYS[0];
+    }
+    if board.white_queenside_castle {
+        hash ^= CASTLE_KEYS[1];
+    }
+    if board.black_kingside_castle {
+        hash ^= CASTLE_KEYS[2];
+    }
+    if board.black_queenside_castle {
+        hash ^= CASTLE_KEYS[3];
+    }
+    if let Some(col) = ep_file(board) {
+        hash ^= EP_KEYS[col];
+    }
+    hash
+}
+
+// The en passant file only when a pawn of the side to move could take there,
+// as a double push with no pawn beside it leaves the same position as any other move
+pub fn ep_file(board : &board::ChessBoard) -> Option<usize> {
+    let (row, col) = board.ep?;
+    let (pawn_row, pawn) = if board.protagonist == board::WHITE {
+        (row + 1, board::WHITE | board::PAWN)
+    } else {
+        (row.wrapping_sub(1), board::BLACK | board::PAWN)
+    };
+    if pawn_row < 8 && ((col > 0 && board.board[pawn_row][col - 1] == pawn) || (col < 7 && board.board[pawn_row][col + 1] == pawn)) {
+        Some(col)
+    } else {
+        None
+    }
+}
+
+// Only the pawns, for caching pawn structure terms
+pub fn pawn_hash(board : &board::ChessBoard) -> u64 {
+    let mut hash : u64 = 0;
+    for row in 0..8 {
+        for col in 0..8 {
+            let piece = board.board[row][col];
+            if board::clear_piece_color(piece) == board::PAWN {
+                hash ^= PIECE_KEYS[row * 8 + col][piece as usize];
+            }
+        }
+    }
+    hash
+}